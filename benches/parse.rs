@@ -0,0 +1,85 @@
+//! Parses synthetic maps of increasing size and prints how long each stage
+//! takes. Time per side should stay flat as the map grows.
+//!
+//!     cargo bench --bench parse
+
+use std::time::{Duration, Instant};
+
+use chisel::vmf2::{events::EventReader, generic::GenericNode, tokenizer::ParseOptions, vmf::Vmf};
+
+/// A map made of `solids` 6 sided boxes in a grid, laid out like Hammer writes it
+fn synthetic_map(solids: usize) -> String {
+    let mut out = String::new();
+    out += "versioninfo\r\n{\r\n\t\"editorversion\" \"400\"\r\n\t\"editorbuild\" \"8864\"\r\n\t\"mapversion\" \"1\"\r\n\t\"formatversion\" \"100\"\r\n\t\"prefab\" \"0\"\r\n}\r\n";
+    out += "world\r\n{\r\n\t\"id\" \"1\"\r\n\t\"classname\" \"worldspawn\"\r\n";
+
+    let mut id = 2;
+    for i in 0..solids {
+        let x0 = (i % 128) as i32 * 64 - 4096;
+        let y0 = (i / 128 % 128) as i32 * 64 - 4096;
+        let z0 = (i / (128 * 128)) as i32 * 64;
+        let (x1, y1, z1) = (x0 + 64, y0 + 64, z0 + 64);
+
+        out += &format!("\tsolid\r\n\t{{\r\n\t\t\"id\" \"{id}\"\r\n");
+        id += 1;
+        let planes = [
+            format!("({x0} {y1} {z1}) ({x1} {y1} {z1}) ({x1} {y0} {z1})"),
+            format!("({x0} {y0} {z0}) ({x1} {y0} {z0}) ({x1} {y1} {z0})"),
+            format!("({x0} {y1} {z1}) ({x0} {y0} {z1}) ({x0} {y0} {z0})"),
+            format!("({x1} {y1} {z0}) ({x1} {y0} {z0}) ({x1} {y0} {z1})"),
+            format!("({x1} {y1} {z1}) ({x0} {y1} {z1}) ({x0} {y1} {z0})"),
+            format!("({x1} {y0} {z0}) ({x0} {y0} {z0}) ({x0} {y0} {z1})"),
+        ];
+        for plane in planes {
+            out += &format!(
+                "\t\tside\r\n\t\t{{\r\n\t\t\t\"id\" \"{id}\"\r\n\t\t\t\"plane\" \"{plane}\"\r\n\t\t\t\"material\" \"DEV/DEVMEASUREGENERIC01\"\r\n\t\t\t\"uaxis\" \"[1 0 0 0] 0.25\"\r\n\t\t\t\"vaxis\" \"[0 -1 0 0] 0.25\"\r\n\t\t\t\"rotation\" \"0\"\r\n\t\t\t\"lightmapscale\" \"16\"\r\n\t\t\t\"smoothing_groups\" \"0\"\r\n\t\t}}\r\n"
+            );
+            id += 1;
+        }
+        out += "\t\teditor\r\n\t\t{\r\n\t\t\t\"color\" \"0 180 197\"\r\n\t\t\t\"visgroupshown\" \"1\"\r\n\t\t\t\"visgroupautoshown\" \"1\"\r\n\t\t}\r\n\t}\r\n";
+    }
+
+    out += "}\r\n";
+    out
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let out = f();
+    (out, start.elapsed())
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>8} {:>10} {:>12} {:>12} {:>12} {:>14}",
+        "solids", "sides", "MiB", "events", "generic", "vmf", "ns/side"
+    );
+
+    for solids in [2_000, 4_000, 8_000, 16_000, 32_000] {
+        let text = synthetic_map(solids);
+        let sides = solids * 6;
+
+        let (count, events) = time(|| {
+            EventReader::new(&text, ParseOptions::default())
+                .inspect(|e| assert!(e.is_ok()))
+                .count()
+        });
+        assert!(count > sides);
+
+        let (generic, generic_time) = time(|| GenericNode::parse(&text).unwrap());
+        let (vmf, vmf_time) = time(|| Vmf::parse(generic).unwrap());
+        assert_eq!(vmf.world.solids.len(), solids);
+
+        let total = generic_time + vmf_time;
+        println!(
+            "{:>8} {:>8} {:>10.1} {:>12.2?} {:>12.2?} {:>12.2?} {:>14.0}",
+            solids,
+            sides,
+            text.len() as f64 / (1024.0 * 1024.0),
+            events,
+            generic_time,
+            vmf_time,
+            total.as_nanos() as f64 / sides as f64,
+        );
+    }
+}
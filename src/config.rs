@@ -0,0 +1,336 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    gameinfo::GameInfo,
+    vfs::FileSystem,
+    vmf2::{
+        error::{Position, VmfError},
+        generic::{Entry, GenericNode},
+        history::DEFAULT_DEPTH,
+        tokenizer::ParseOptions,
+    },
+};
+
+/*
+The editor's settings are KeyValues too, one block per game:
+
+    "Config"
+    {
+        "active" "0"
+        "historydepth" "100"
+        "Game"
+        {
+            "name" "Half-Life 2"
+            "gamedir" "C:\\Steam\\steamapps\\common\\Half-Life 2\\hl2"
+            "gameinfo" ""
+            "fgd" "C:\\...\\bin\\halflife2.fgd"
+            "fgd" "C:\\...\\bin\\base.fgd"
+            ...
+        }
+    }
+
+`active` is the index of the active game, names don't have to be unique.
+`historydepth` is how many edits can be undone, in every map.
+Values are written with escapes, so a name can have quotes in it.
+*/
+
+/// Everything the editor needs to know about one game
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameConfig {
+    pub name: String,
+    /// The mod directory, e.g. `.../Half-Life 2/hl2`
+    pub game_dir: PathBuf,
+    /// Empty means `gameinfo.txt` in `game_dir`
+    pub gameinfo: PathBuf,
+    /// Entity definitions, in the order they are read
+    pub fgds: Vec<PathBuf>,
+    /// Where the file dialogs start
+    pub map_dir: PathBuf,
+    pub vbsp: PathBuf,
+    pub vvis: PathBuf,
+    pub vrad: PathBuf,
+    /// The material new brushes get
+    pub default_texture: String,
+}
+
+impl GameConfig {
+    /// What we use when there is no config file yet: loose files in
+    /// `./testing`, no entity definitions
+    pub fn fallback() -> Self {
+        Self {
+            name: "Default".to_owned(),
+            game_dir: PathBuf::from("./testing"),
+            map_dir: PathBuf::from("./testing"),
+            default_texture: "dev/dev_measuregeneric01".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn gameinfo_path(&self) -> PathBuf {
+        if self.gameinfo.as_os_str().is_empty() {
+            self.game_dir.join("gameinfo.txt")
+        } else {
+            self.gameinfo.clone()
+        }
+    }
+
+    /// The game's files, from gameinfo.txt's search paths. Without a
+    /// gameinfo.txt that's just the game directory. Problems with it or
+    /// with its packs come back as messages.
+    pub fn file_system(&self) -> (FileSystem, Vec<String>) {
+        let path = self.gameinfo_path();
+        if !path.is_file() {
+            return (FileSystem::from_dir(&self.game_dir), Vec::new());
+        }
+
+        match GameInfo::load(&path) {
+            Ok(info) => {
+                let (fs, errors) = info.file_system();
+                let errors = errors
+                    .into_iter()
+                    .map(|(vpk, e)| format!("{}: {e}", vpk.display()))
+                    .collect();
+                (fs, errors)
+            }
+            Err(e) => (
+                FileSystem::from_dir(&self.game_dir),
+                vec![format!("{}: {e}", path.display())],
+            ),
+        }
+    }
+
+    fn parse(node: &GenericNode) -> Self {
+        let mut config = Self::default();
+        for entry in &node.entries {
+            let Entry::KeyValue(key, value, _) = entry else {
+                continue;
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "name" => config.name = value.clone(),
+                "gamedir" => config.game_dir = value.into(),
+                "gameinfo" => config.gameinfo = value.into(),
+                "fgd" => config.fgds.push(value.into()),
+                "mapdir" => config.map_dir = value.into(),
+                "vbsp" => config.vbsp = value.into(),
+                "vvis" => config.vvis = value.into(),
+                "vrad" => config.vrad = value.into(),
+                "defaulttexture" => config.default_texture = value.clone(),
+                _ => {}
+            }
+        }
+        config
+    }
+
+    fn as_generic(&self) -> GenericNode {
+        let path = |p: &Path| escape(&p.to_string_lossy());
+        let mut g = GenericNode::new();
+        g.set_value("name", escape(&self.name));
+        g.set_value("gamedir", path(&self.game_dir));
+        g.set_value("gameinfo", path(&self.gameinfo));
+        // set_value would leave just one of these
+        for fgd in &self.fgds {
+            g.entries.push(Entry::KeyValue(
+                "fgd".to_owned(),
+                path(fgd),
+                Position::default(),
+            ));
+        }
+        g.set_value("mapdir", path(&self.map_dir));
+        g.set_value("vbsp", path(&self.vbsp));
+        g.set_value("vvis", path(&self.vvis));
+        g.set_value("vrad", path(&self.vrad));
+        g.set_value("defaulttexture", escape(&self.default_texture));
+        g
+    }
+}
+
+/// Every game the editor knows about, and which one we're mapping for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub games: Vec<GameConfig>,
+    /// Index into `games`
+    pub active: usize,
+    /// How many edits can be undone
+    pub history_depth: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            games: vec![GameConfig::fallback()],
+            active: 0,
+            history_depth: DEFAULT_DEPTH,
+        }
+    }
+}
+
+impl Config {
+    /// Reads a config file. One that doesn't exist yet is the default config.
+    pub fn load(path: &Path) -> Result<Self, VmfError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, VmfError> {
+        let root = GenericNode::parse_with(text, ParseOptions { escapes: true })?;
+        let Some(node) = root.entries.iter().find_map(|e| match e {
+            Entry::Child(name, node) if name.eq_ignore_ascii_case("config") => Some(node),
+            _ => None,
+        }) else {
+            return Err(VmfError::MissingBlock {
+                position: root.position,
+                name: "Config".to_owned(),
+            });
+        };
+
+        let history_depth = node
+            .get_value("historydepth")
+            .and_then(|d| d.trim().parse().ok())
+            .filter(|d| *d > 0)
+            .unwrap_or(DEFAULT_DEPTH);
+        let games: Vec<GameConfig> = node
+            .entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::Child(name, game) if name.eq_ignore_ascii_case("game") => {
+                    Some(GameConfig::parse(game))
+                }
+                _ => None,
+            })
+            .collect();
+        if games.is_empty() {
+            return Ok(Self {
+                history_depth,
+                ..Self::default()
+            });
+        }
+
+        let active = node
+            .get_value("active")
+            .and_then(|i| i.trim().parse().ok())
+            .filter(|i| *i < games.len())
+            .unwrap_or(0);
+        Ok(Self {
+            games,
+            active,
+            history_depth,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut node = GenericNode::new();
+        node.set_value("active", self.active);
+        node.set_value("historydepth", self.history_depth);
+        node.set_children("Game", self.games.iter().map(|g| g.as_generic()).collect());
+
+        let mut root = GenericNode::new();
+        root.set_child("Config", node);
+        root.to_text(0)
+    }
+
+    pub fn active_game(&self) -> Option<&GameConfig> {
+        self.games.get(self.active)
+    }
+}
+
+/// Backslashes, quotes and line breaks written so that reading with escapes
+/// gives back the same text
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            games: vec![
+                GameConfig::fallback(),
+                GameConfig {
+                    name: "Half-Life \"2\"".to_owned(),
+                    game_dir: PathBuf::from("C:\\Steam\\Half-Life 2\\hl2"),
+                    fgds: vec![
+                        PathBuf::from("C:\\bin\\base.fgd"),
+                        PathBuf::from("/b\\n.fgd"),
+                    ],
+                    vbsp: PathBuf::from("C:\\bin\\vbsp.exe"),
+                    default_texture: "dev\\dev_measuregeneric01b".to_owned(),
+                    ..Default::default()
+                },
+                // Names don't have to be unique
+                GameConfig::fallback(),
+            ],
+            active: 2,
+            history_depth: 250,
+        };
+        let text = config.to_text();
+        assert!(text.contains(r#""name" "Half-Life \"2\"""#));
+        assert!(text.contains(r#""gamedir" "C:\\Steam\\Half-Life 2\\hl2""#));
+        assert!(text.contains(r#""historydepth" "250""#));
+        assert_eq!(Config::parse(&text).unwrap(), config);
+
+        let path = std::env::temp_dir().join(format!("chisel-config-{}", std::process::id()));
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), config);
+    }
+
+    #[test]
+    fn active_game() {
+        let text = |active: &str| {
+            format!("Config {{ active \"{active}\" Game {{ name A }} Game {{ name B }} }}")
+        };
+        assert_eq!(
+            Config::parse(&text("1"))
+                .unwrap()
+                .active_game()
+                .unwrap()
+                .name,
+            "B"
+        );
+        // Out of range or not a number, the first one
+        assert_eq!(Config::parse(&text("2")).unwrap().active, 0);
+        assert_eq!(Config::parse(&text("B")).unwrap().active, 0);
+    }
+
+    #[test]
+    fn history_depth() {
+        let depth = |text: &str| Config::parse(text).unwrap().history_depth;
+        assert_eq!(depth("Config { historydepth 20 Game { name A } }"), 20);
+        // Kept without any games too
+        assert_eq!(depth("Config { historydepth 20 }"), 20);
+        // Nothing could be undone with 0
+        assert_eq!(depth("Config { historydepth 0 }"), DEFAULT_DEPTH);
+        assert_eq!(depth("Config { historydepth lots }"), DEFAULT_DEPTH);
+        assert_eq!(depth("Config { }"), DEFAULT_DEPTH);
+    }
+
+    #[test]
+    fn missing_config() {
+        assert_eq!(Config::parse("Config { }").unwrap(), Config::default());
+        assert!(Config::parse("Settings { }").is_err());
+        let path = std::env::temp_dir().join("chisel-config-that-does-not-exist");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+    }
+}
@@ -1,8 +1,12 @@
-use bevy::{input::mouse::MouseMotion, prelude::*, render::view::RenderLayers};
+use std::collections::HashSet;
+
+use bevy::{
+    ecs::system::SystemParam, input::mouse::MouseMotion, prelude::*, render::view::RenderLayers,
+};
 use bevy_mod_raycast::prelude::*;
 
 use crate::{
-    solidcomp::SideComponent,
+    solidcomp::{EntityComponent, GroupComponent, MapObject, SideComponent, SolidComponent},
     views::{
         camera_ortho_controller::get_view_mat,
         split::{ActiveSplit, CameraView},
@@ -17,17 +21,113 @@ impl Plugin for ControlPlugin {
             .add_plugins(DeferredRaycastingPlugin::<OrthoRaycastSet>::default())
             .insert_resource(RaycastPluginState::<View3DRaycastSet>::default())
             .insert_resource(RaycastPluginState::<OrthoRaycastSet>::default())
-            .add_systems(Update, (intersection, ortho_intersection, update_selected));
+            .init_resource::<IgnoreGroups>()
+            .add_event::<SelectObject>()
+            .add_systems(
+                Update,
+                (
+                    intersection,
+                    ortho_intersection,
+                    select_object.before(update_selected),
+                    update_selected,
+                ),
+            );
     }
 }
 
 #[derive(Component)]
 pub struct Selected(pub bool);
 
-/// Report intersections
+/// The colour a 3D view mesh goes back to when it is deselected
+#[derive(Component)]
+pub struct BaseColor(pub Color);
+
+/// Clicking selects single objects instead of the whole group they're in,
+/// Hammer's Toggle group ignore (Ctrl+W)
+#[derive(Default, Resource)]
+pub struct IgnoreGroups(pub bool);
+
+/// The map objects that are selected
+#[derive(Default)]
+pub struct Selection {
+    pub solids: HashSet<u32>,
+    pub entities: HashSet<u32>,
+}
+
+/// Finds what's selected in terms of the map. A selected face selects its
+/// solid, or the brush entity the solid is part of.
+#[derive(SystemParam)]
+pub struct SelectedObjects<'w, 's> {
+    sides: Query<'w, 's, (&'static Selected, &'static Parent), With<SideComponent>>,
+    points: Query<'w, 's, (&'static Selected, &'static EntityComponent), Without<SolidComponent>>,
+    solids: Query<'w, 's, (&'static SolidComponent, Option<&'static EntityComponent>)>,
+}
+
+impl SelectedObjects<'_, '_> {
+    pub fn get(&self) -> Selection {
+        let mut selection = Selection::default();
+        for (selected, parent) in &self.sides {
+            match self.solids.get(parent.get()) {
+                Ok((_, Some(entity))) if selected.0 => selection.entities.insert(entity.id),
+                Ok((solid, None)) if selected.0 => selection.solids.insert(solid.id),
+                _ => false,
+            };
+        }
+        for (selected, entity) in &self.points {
+            if selected.0 {
+                selection.entities.insert(entity.id);
+            }
+        }
+        selection
+    }
+}
+
+/// Selects everything that belongs to an object, and nothing else
+#[derive(Event)]
+pub struct SelectObject(pub MapObject);
+
+#[allow(clippy::type_complexity)]
+fn select_object(
+    mut events: EventReader<SelectObject>,
+    mut q_selected: Query<(
+        &mut Selected,
+        Option<&SideComponent>,
+        Option<&Parent>,
+        Option<&EntityComponent>,
+    )>,
+    q_solids: Query<(&SolidComponent, Option<&EntityComponent>)>,
+) {
+    let Some(SelectObject(object)) = events.read().last() else {
+        return;
+    };
+
+    for (mut selected, side, parent, entity) in q_selected.iter_mut() {
+        // Sides are children of their solid, which may belong to an entity
+        let solid = parent.and_then(|p| q_solids.get(p.get()).ok());
+        let matches = match (object, side) {
+            (MapObject::Side(id), Some(side)) => side.id == *id,
+            (MapObject::Solid(id), Some(_)) => solid.is_some_and(|(s, _)| s.id == *id),
+            (MapObject::Entity(id), Some(_)) => {
+                solid.is_some_and(|(_, e)| e.is_some_and(|e| e.id == *id))
+            }
+            // Point entities
+            (MapObject::Entity(id), None) => entity.is_some_and(|e| e.id == *id),
+            (_, None) => false,
+        };
+        if selected.0 != matches {
+            selected.0 = matches;
+        }
+    }
+}
+
+/// Report intersections. Clicking something in a group selects the group,
+/// unless groups are ignored.
+#[allow(clippy::too_many_arguments)]
 fn intersection(
     q_possible_mesh_hits: Query<&Parent, With<RaycastMesh<View3DRaycastSet>>>,
-    mut q_selected: Query<&mut Selected>,
+    mut q_selected: Query<(Entity, &mut Selected, Option<&Parent>)>,
+    q_groups: Query<&GroupComponent>,
+    ignore_groups: Res<IgnoreGroups>,
     source: Query<&RaycastSource<View3DRaycastSet>>,
     click: Res<ButtonInput<MouseButton>>,
     space: Res<ButtonInput<KeyCode>>,
@@ -40,22 +140,35 @@ fn intersection(
         let source = source.single();
 
         // Get the first intersection
-        if let Some((entity, _)) = source.intersections().get(0) {
-            // deselect all
-            for mut selected in q_selected.iter_mut() {
-                if selected.0 == true {
-                    selected.0 = false;
-                }
-            }
-
-            // find the mesh we clicked on, go to it's parent, which should have a Selected component, then set selected to true.
-            if let Ok(mut selected) = q_possible_mesh_hits
+        if let Some((entity, _)) = source.intersections().first() {
+            // find the mesh we clicked on, go to it's parent, which should have a Selected component
+            let Some(clicked) = q_possible_mesh_hits
                 .get(*entity)
-                .and_then(|parent| q_selected.get_mut(parent.get()))
-            {
-                selected.0 = true;
-            } else {
+                .ok()
+                .and_then(|parent| q_selected.get(parent.get()).ok())
+            else {
                 warn!("Clicked on solid that doesn't exist?");
+                return;
+            };
+
+            // Point entities carry their group, sides are in a solid that does
+            let group_of = |entity: Entity, parent: Option<&Parent>| {
+                let solid = parent.map(|p| p.get());
+                q_groups
+                    .get(entity)
+                    .ok()
+                    .or_else(|| q_groups.get(solid?).ok())
+                    .map(|g| g.id)
+            };
+            let (clicked, _, parent) = clicked;
+            let group = group_of(clicked, parent).filter(|_| !ignore_groups.0);
+
+            for (entity, mut selected, parent) in q_selected.iter_mut() {
+                let matches =
+                    entity == clicked || group.is_some() && group_of(entity, parent) == group;
+                if selected.0 != matches {
+                    selected.0 = matches;
+                }
             }
         }
     }
@@ -74,7 +187,7 @@ fn ortho_intersection(
     {
         for source in sources.iter() {
             if let (Some((entity, _)), ActiveSplit::View(view, _)) =
-                (source.intersections().get(0), &*active_split)
+                (source.intersections().first(), &*active_split)
             {
                 if let Ok((_side, mut trans)) = q_possible_mesh_hits
                     .get(*entity)
@@ -96,7 +209,7 @@ fn ortho_intersection(
 
 fn update_selected(
     q_selection_change: Query<(&Children, &Selected), Changed<Selected>>,
-    q_3d_view_child: Query<(&Handle<StandardMaterial>, &RenderLayers)>,
+    q_3d_view_child: Query<(&Handle<StandardMaterial>, &RenderLayers, Option<&BaseColor>)>,
     mut q_control_nob: Query<&mut Visibility, With<ControlNob>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
@@ -105,13 +218,14 @@ fn update_selected(
         // Look through each child (there should only ever be two but whatever)
         for child in children {
             // If we have both a material and a render layer (should be both children at this point)
-            if let Ok((mat_handle, render_layer)) = q_3d_view_child.get(*child) {
+            if let Ok((mat_handle, render_layer, base_color)) = q_3d_view_child.get(*child) {
                 // If we are on the correct render layer (okay we know for sure we are changing the 3d view mesh)
                 if render_layer.intersects(&RenderLayers::layer(0)) {
+                    let base_color = base_color.map_or(Color::RED, |c| c.0);
                     materials.get_mut(mat_handle).unwrap().base_color = if selected.0 {
-                        Color::YELLOW
+                        Color::YELLOW.with_a(base_color.a())
                     } else {
-                        Color::RED
+                        base_color
                     };
                 }
             }
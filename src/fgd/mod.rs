@@ -0,0 +1,580 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use bevy::math::DVec3;
+
+use crate::vmf2::error::Position;
+
+mod parse;
+mod tokenizer;
+
+/*
+FGD, the entity definitions Hammer reads:
+
+    @include "base.fgd"
+
+    @BaseClass = Targetname
+    [
+        targetname(target_source) : "Name" : : "The name other entities use"
+        input Kill(void) : "Removes this entity from the world."
+    ]
+
+    @PointClass base(Targetname) studio("models/editor/playerstart.mdl") =
+        info_player_start : "This entity indicates the position and facing " +
+        "direction at which the player will spawn."
+    [
+        spawnflags(flags) =
+        [
+            1 : "Master (Has priority if multiple info_player_starts exist)" : 0
+        ]
+    ]
+
+Classes inherit everything from the classes in their `base()`, their own
+definitions of a key replace inherited ones.
+*/
+
+#[derive(Debug)]
+pub enum FgdError {
+    Io(PathBuf, std::io::Error),
+    Syntax {
+        /// Empty for text that didn't come from a file
+        path: PathBuf,
+        position: Position,
+        message: String,
+    },
+}
+
+impl FgdError {
+    fn syntax(position: Position, message: impl ToString) -> Self {
+        FgdError::Syntax {
+            path: PathBuf::new(),
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    fn in_file(self, file: &Path) -> Self {
+        match self {
+            FgdError::Syntax {
+                path,
+                position,
+                message,
+            } if path.as_os_str().is_empty() => FgdError::Syntax {
+                path: file.to_owned(),
+                position,
+                message,
+            },
+            e => e,
+        }
+    }
+}
+
+impl Display for FgdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FgdError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            FgdError::Syntax {
+                path,
+                position,
+                message,
+            } if path.as_os_str().is_empty() => write!(f, "{position}: {message}"),
+            FgdError::Syntax {
+                path,
+                position,
+                message,
+            } => write!(f, "{}, {position}: {message}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for FgdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FgdError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClassKind {
+    /// Only there to be inherited from
+    Base,
+    /// Has an origin, `@NPCClass`, `@KeyFrameClass`, `@MoveClass` and
+    /// `@FilterClass` are point classes too
+    Point,
+    /// Made of brushes
+    Solid,
+}
+
+/// What kind of value a key holds, which decides how it's edited
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PropertyType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Choices,
+    Flags,
+    /// "r g b" from 0 to 255, sometimes with a brightness after it
+    Color255,
+    /// "r g b" from 0 to 1
+    Color1,
+    Studio,
+    Sprite,
+    Sound,
+    Decal,
+    Material,
+    /// This entity's name
+    TargetSource,
+    /// The name of another entity
+    TargetDestination,
+    TargetNameOrClass,
+    Angle,
+    Vector,
+    Origin,
+    Axis,
+    NpcClass,
+    FilterClass,
+    PointEntityClass,
+    /// Face ids
+    Sidelist,
+    Scene,
+    /// Anything we don't know, as written
+    Other(String),
+}
+
+impl PropertyType {
+    pub fn parse(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "string" => PropertyType::String,
+            "integer" => PropertyType::Integer,
+            "float" => PropertyType::Float,
+            "boolean" => PropertyType::Boolean,
+            "choices" => PropertyType::Choices,
+            "flags" => PropertyType::Flags,
+            "color255" => PropertyType::Color255,
+            "color1" => PropertyType::Color1,
+            "studio" => PropertyType::Studio,
+            "sprite" => PropertyType::Sprite,
+            "sound" => PropertyType::Sound,
+            "decal" => PropertyType::Decal,
+            "material" => PropertyType::Material,
+            "target_source" => PropertyType::TargetSource,
+            "target_destination" => PropertyType::TargetDestination,
+            "target_name_or_class" => PropertyType::TargetNameOrClass,
+            "angle" => PropertyType::Angle,
+            "vector" => PropertyType::Vector,
+            "origin" => PropertyType::Origin,
+            "axis" => PropertyType::Axis,
+            "npcclass" => PropertyType::NpcClass,
+            "filterclass" => PropertyType::FilterClass,
+            "pointentityclass" => PropertyType::PointEntityClass,
+            "sidelist" => PropertyType::Sidelist,
+            "scene" => PropertyType::Scene,
+            _ => PropertyType::Other(s.to_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Choice {
+    pub value: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flag {
+    pub bit: u32,
+    pub name: String,
+    pub default: bool,
+}
+
+/// A key an entity can have
+#[derive(Clone, Debug, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub kind: PropertyType,
+    pub display_name: String,
+    pub default: Option<String>,
+    pub description: String,
+    pub readonly: bool,
+    /// Shown in the entity report
+    pub report: bool,
+    /// For `choices`
+    pub choices: Vec<Choice>,
+    /// For `flags`
+    pub flags: Vec<Flag>,
+}
+
+impl Property {
+    /// What Hammer shows for this key, its display name if it has one
+    pub fn label(&self) -> &str {
+        if self.display_name.is_empty() {
+            &self.name
+        } else {
+            &self.display_name
+        }
+    }
+}
+
+/// The type of the parameter an input takes or an output passes on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IoType {
+    Void,
+    Integer,
+    Float,
+    String,
+    Bool,
+    EHandle,
+    Color255,
+    Vector,
+    Script,
+    Other(String),
+}
+
+impl IoType {
+    pub fn parse(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "void" => IoType::Void,
+            "integer" => IoType::Integer,
+            "float" => IoType::Float,
+            "string" => IoType::String,
+            "bool" => IoType::Bool,
+            "ehandle" => IoType::EHandle,
+            "color255" => IoType::Color255,
+            "vector" => IoType::Vector,
+            "script" => IoType::Script,
+            _ => IoType::Other(s.to_owned()),
+        }
+    }
+}
+
+/// An input or an output
+#[derive(Clone, Debug, PartialEq)]
+pub struct IoDef {
+    pub name: String,
+    pub kind: IoType,
+    pub description: String,
+}
+
+/// Something between the `@PointClass` and the `=`, like `size(-8 -8 -8, 8 8 8)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Helper {
+    /// Lowercase
+    pub name: String,
+    /// Split at the commas, each trimmed
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityClass {
+    pub name: String,
+    pub kind: ClassKind,
+    pub description: String,
+    /// From `base()`
+    pub bases: Vec<String>,
+    /// From `size()`, the bounding box in Hammer's (Z up) coordinates
+    pub size: Option<(DVec3, DVec3)>,
+    /// From `color()`
+    pub color: Option<[u8; 3]>,
+    /// From `studio()`, `None` if there's no model. An empty string means
+    /// the model is whatever the entity's `model` key says.
+    pub studio: Option<String>,
+    /// From `iconsprite()`
+    pub iconsprite: Option<String>,
+    /// Every helper, including the ones above
+    pub helpers: Vec<Helper>,
+    pub properties: Vec<Property>,
+    pub inputs: Vec<IoDef>,
+    pub outputs: Vec<IoDef>,
+}
+
+impl EntityClass {
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn input(&self, name: &str) -> Option<&IoDef> {
+        self.inputs
+            .iter()
+            .find(|i| i.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn output(&self, name: &str) -> Option<&IoDef> {
+        self.outputs
+            .iter()
+            .find(|o| o.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn helper(&self, name: &str) -> Option<&Helper> {
+        self.helpers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// `@AutoVisGroup = "Tool Brushes" [ "Clip" [ "func_clip_vphysics" ] ]`, a
+/// top level group with named lists of classnames
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoVisGroup {
+    pub name: String,
+    pub groups: Vec<(String, Vec<String>)>,
+}
+
+/// Every entity class from a game's FGDs
+#[derive(Clone, Debug, Default)]
+pub struct EntityClassDb {
+    /// By lowercase classname
+    classes: HashMap<String, EntityClass>,
+    pub auto_visgroups: Vec<AutoVisGroup>,
+    /// From `@MaterialExclusion`, directories the texture browser leaves out
+    pub material_exclusions: Vec<String>,
+    /// From `@mapsize`
+    pub map_size: Option<(i32, i32)>,
+    /// Files already read, so each `@include` is only read once
+    loaded: HashSet<PathBuf>,
+}
+
+impl EntityClassDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads FGDs in order, later ones replacing classes from earlier ones.
+    /// One that can't be read doesn't stop the others, whatever was read
+    /// before the problem is kept and the problem is handed back.
+    pub fn load(paths: &[impl AsRef<Path>]) -> (Self, Vec<FgdError>) {
+        let mut db = Self::new();
+        let mut errors = Vec::new();
+        for path in paths {
+            if let Err(e) = db.load_file(path.as_ref()) {
+                errors.push(e);
+            }
+        }
+        (db, errors)
+    }
+
+    /// Reads an FGD and everything it includes. `@include` paths are relative
+    /// to the including file.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), FgdError> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if !self.loaded.insert(key) {
+            return Ok(());
+        }
+
+        let text = std::fs::read(path).map_err(|e| FgdError::Io(path.to_owned(), e))?;
+        // Some FGDs are saved as Windows-1252, a stray é shouldn't stop us
+        let text = String::from_utf8_lossy(&text);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.parse_with(&text, &mut |db, include| db.load_file(&dir.join(include)))
+            .map_err(|e| e.in_file(path))
+    }
+
+    /// Reads FGD text that doesn't come from a file, `@include`s are ignored
+    pub fn parse_str(&mut self, text: &str) -> Result<(), FgdError> {
+        self.parse_with(text, &mut |_, _| Ok(()))
+    }
+
+    fn parse_with(
+        &mut self,
+        text: &str,
+        include: &mut dyn FnMut(&mut Self, &str) -> Result<(), FgdError>,
+    ) -> Result<(), FgdError> {
+        let tokens = tokenizer::tokenize(text)?;
+        parse::Parser::new(&tokens).parse(self, include)
+    }
+
+    fn insert(&mut self, class: EntityClass) {
+        self.classes.insert(class.name.to_ascii_lowercase(), class);
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    /// A class as it was defined, without what it inherits
+    pub fn get(&self, classname: &str) -> Option<&EntityClass> {
+        self.classes.get(&classname.to_ascii_lowercase())
+    }
+
+    /// A class with everything it inherits from its bases folded in. Keys,
+    /// inputs and outputs come in the order Hammer shows them: inherited
+    /// ones first, in the order of `base()`.
+    pub fn resolve(&self, classname: &str) -> Option<EntityClass> {
+        let class = self.get(classname)?;
+        let mut resolved = EntityClass {
+            helpers: Vec::new(),
+            properties: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            ..class.clone()
+        };
+        let mut visited = HashSet::new();
+        self.fold_in(class, &mut resolved, &mut visited);
+        Some(resolved)
+    }
+
+    fn fold_in(&self, class: &EntityClass, into: &mut EntityClass, visited: &mut HashSet<String>) {
+        // A class that (through others) is its own base would never end
+        if !visited.insert(class.name.to_ascii_lowercase()) {
+            return;
+        }
+        for base in &class.bases {
+            if let Some(base) = self.get(base) {
+                self.fold_in(base, into, visited);
+            }
+        }
+
+        into.size = class.size.or(into.size);
+        into.color = class.color.or(into.color);
+        if class.studio.is_some() {
+            into.studio.clone_from(&class.studio);
+        }
+        if class.iconsprite.is_some() {
+            into.iconsprite.clone_from(&class.iconsprite);
+        }
+        for helper in &class.helpers {
+            replace_or_push(&mut into.helpers, helper, |a, b| {
+                a.name == b.name && a.name != "base"
+            });
+        }
+        for property in &class.properties {
+            replace_or_push(&mut into.properties, property, |a, b| {
+                a.name.eq_ignore_ascii_case(&b.name)
+            });
+        }
+        for input in &class.inputs {
+            replace_or_push(&mut into.inputs, input, |a, b| {
+                a.name.eq_ignore_ascii_case(&b.name)
+            });
+        }
+        for output in &class.outputs {
+            replace_or_push(&mut into.outputs, output, |a, b| {
+                a.name.eq_ignore_ascii_case(&b.name)
+            });
+        }
+    }
+
+    /// Every class that can be placed in a map (not `@BaseClass`), sorted
+    pub fn placeable(&self) -> Vec<&EntityClass> {
+        let mut classes: Vec<&EntityClass> = self
+            .classes
+            .values()
+            .filter(|c| c.kind != ClassKind::Base)
+            .collect();
+        classes.sort_by(|a, b| a.name.cmp(&b.name));
+        classes
+    }
+}
+
+/// Replaces the item `same` says is the same, keeping its place, or adds it
+/// at the end
+fn replace_or_push<T: Clone>(items: &mut Vec<T>, item: &T, same: impl Fn(&T, &T) -> bool) {
+    match items.iter_mut().find(|i| same(i, item)) {
+        Some(existing) => *existing = item.clone(),
+        None => items.push(item.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FGD: &str = r#"
+        @BaseClass = Targetname
+        [
+            targetname(target_source) : "Name"
+            input Kill(void) : "Removes it"
+        ]
+        @BaseClass size(-8 -8 -8, 8 8 8) color(255 0 0) = Shown
+        [
+            start(integer) : "Start" : 0
+            output OnTrigger(void)
+        ]
+        @PointClass base(Targetname, Shown) color(0 255 0) = a
+        [
+            start(integer) : "Start" : 1
+            own(string)
+            input Kill(void) : "Replaced"
+        ]
+        @PointClass base(Loop) = loop_a []
+        @BaseClass base(loop_a) = Loop [ key(string) ]
+    "#;
+
+    fn db() -> EntityClassDb {
+        let mut db = EntityClassDb::new();
+        db.parse_str(FGD).unwrap();
+        db
+    }
+
+    #[test]
+    fn resolve_folds_in_bases() {
+        let db = db();
+        assert_eq!(db.get("a").unwrap().properties.len(), 2);
+
+        let a = db.resolve("a").unwrap();
+        let keys: Vec<_> = a.properties.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(keys, ["targetname", "start", "own"]);
+        assert_eq!(a.property("start").unwrap().default.as_deref(), Some("1"));
+        assert_eq!(a.input("Kill").unwrap().description, "Replaced");
+        assert!(a.output("OnTrigger").is_some());
+        assert_eq!(a.size.map(|(min, _)| min), Some(DVec3::splat(-8.0)));
+        assert_eq!(a.color, Some([0, 255, 0]));
+        assert_eq!(a.kind, ClassKind::Point);
+        assert!(db.resolve("missing").is_none());
+    }
+
+    #[test]
+    fn resolve_stops_at_cycles() {
+        let a = db().resolve("loop_a").unwrap();
+        assert_eq!(a.properties.len(), 1);
+        assert!(a.property("key").is_some());
+    }
+
+    #[test]
+    fn includes_are_read_once() {
+        let dir = std::env::temp_dir().join(format!("chisel-fgd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+        write(
+            "game.fgd",
+            "@include \"base.fgd\"\n@include \"sub/more.fgd\"\n@include \"base.fgd\"\n",
+        );
+        write(
+            "base.fgd",
+            "@AutoVisGroup = \"All\" [ \"Base\" [ \"a\" ] ]\n@BaseClass = Base []\n",
+        );
+        // Relative to the file that includes it, and back to the first one
+        write(
+            "sub/more.fgd",
+            "@include \"../base.fgd\"\n@include \"../game.fgd\"\n@PointClass base(Base) = a []\n",
+        );
+
+        write("broken.fgd", "@PointClass = b []\n@PointClass = [\n");
+
+        let (db, errors) = EntityClassDb::load(&[dir.join("game.fgd"), dir.join("base.fgd")]);
+        assert!(errors.is_empty());
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.auto_visgroups[0].groups.len(), 1);
+
+        // The FGDs around a broken or missing one are still read
+        let (db, errors) = EntityClassDb::load(&[
+            dir.join("missing.fgd"),
+            dir.join("broken.fgd"),
+            dir.join("game.fgd"),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(errors[..], [FgdError::Io(..), _]));
+        assert!(db.get("a").is_some() && db.get("b").is_some());
+    }
+}
@@ -0,0 +1,663 @@
+use bevy::math::DVec3;
+
+use crate::vmf2::error::Position;
+
+use super::{
+    tokenizer::Token, AutoVisGroup, Choice, ClassKind, EntityClass, EntityClassDb, FgdError, Flag,
+    Helper, IoDef, IoType, Property, PropertyType,
+};
+
+pub struct Parser<'a> {
+    tokens: &'a [(Token, Position)],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [(Token, Position)]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + offset).map(|(t, _)| t)
+    }
+
+    /// Where the next token is, or the last one at the end of the file
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|(_, p)| *p)
+            .unwrap_or_default()
+    }
+
+    fn next(&mut self) -> Result<&'a Token, FgdError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(t, _)| t)
+            .ok_or_else(|| FgdError::syntax(self.position(), "unexpected end of file"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error(&self, expected: &str) -> FgdError {
+        let found = match self.peek() {
+            Some(token) => token.describe(),
+            None => "the end of the file".to_owned(),
+        };
+        FgdError::syntax(
+            self.position(),
+            format!("expected {expected}, found {found}"),
+        )
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), FgdError> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&token.describe()))
+        }
+    }
+
+    /// Takes `token` if it's next
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.peek() == Some(&token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn word(&mut self) -> Result<&'a str, FgdError> {
+        match self.peek() {
+            Some(Token::Word(w)) => {
+                self.pos += 1;
+                Ok(w)
+            }
+            _ => Err(self.error("a name")),
+        }
+    }
+
+    /// A quoted string, with any `+ "more"` after it glued on
+    fn string(&mut self) -> Result<String, FgdError> {
+        let Some(Token::String(s)) = self.peek() else {
+            return Err(self.error("a string"));
+        };
+        self.pos += 1;
+        let mut s = s.clone();
+        while self.peek() == Some(&Token::Plus) {
+            self.pos += 1;
+            let Some(Token::String(more)) = self.peek() else {
+                return Err(self.error("a string after '+'"));
+            };
+            self.pos += 1;
+            s.push_str(more);
+        }
+        Ok(s)
+    }
+
+    /// A string or a bare word, for defaults and choice values
+    fn value(&mut self) -> Result<String, FgdError> {
+        match self.peek() {
+            Some(Token::String(_)) => self.string(),
+            Some(Token::Word(w)) => {
+                self.pos += 1;
+                Ok(w.clone())
+            }
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    pub fn parse(
+        &mut self,
+        db: &mut EntityClassDb,
+        include: &mut dyn FnMut(&mut EntityClassDb, &str) -> Result<(), FgdError>,
+    ) -> Result<(), FgdError> {
+        while self.peek().is_some() {
+            self.expect(Token::At)?;
+            let directive = self.word()?;
+            match directive.to_ascii_lowercase().as_str() {
+                "include" => {
+                    let path = self.string()?;
+                    include(db, &path)?;
+                }
+                "baseclass" => db.insert(self.class(ClassKind::Base)?),
+                "pointclass" | "npcclass" | "keyframeclass" | "moveclass" | "filterclass" => {
+                    db.insert(self.class(ClassKind::Point)?)
+                }
+                "solidclass" => db.insert(self.class(ClassKind::Solid)?),
+                "mapsize" => {
+                    let args = self.helper_args()?;
+                    if let [min, max] = args.as_slice() {
+                        if let (Ok(min), Ok(max)) = (min.parse(), max.parse()) {
+                            db.map_size = Some((min, max));
+                        }
+                    }
+                }
+                "materialexclusion" => {
+                    self.expect(Token::OpenBracket)?;
+                    while !self.eat(Token::CloseBracket) {
+                        let dir = self.string()?;
+                        db.material_exclusions.push(dir);
+                    }
+                }
+                "autovisgroup" => {
+                    let group = self.auto_visgroup()?;
+                    match db.auto_visgroups.iter_mut().find(|g| g.name == group.name) {
+                        Some(existing) => existing.groups.extend(group.groups),
+                        None => db.auto_visgroups.push(group),
+                    }
+                }
+                // Newer FGDs have more, like @gridnav, which we don't need
+                _ => self.skip_directive()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips `(...)`, `= ...` and `[...]` after a directive we don't know
+    fn skip_directive(&mut self) -> Result<(), FgdError> {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::At if depth == 0 => break,
+                Token::OpenParen | Token::OpenBracket => depth += 1,
+                Token::CloseParen | Token::CloseBracket => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// `= "Name" [ "Group" [ "classname" ... ] ... ]`
+    fn auto_visgroup(&mut self) -> Result<AutoVisGroup, FgdError> {
+        self.expect(Token::Equals)?;
+        let name = self.string()?;
+        let mut groups = Vec::new();
+        self.expect(Token::OpenBracket)?;
+        while !self.eat(Token::CloseBracket) {
+            let group = self.string()?;
+            let mut classes = Vec::new();
+            self.expect(Token::OpenBracket)?;
+            while !self.eat(Token::CloseBracket) {
+                classes.push(self.string()?);
+            }
+            groups.push((group, classes));
+        }
+        Ok(AutoVisGroup { name, groups })
+    }
+
+    /// The arguments of a helper, between `(` and `)`, split at commas
+    fn helper_args(&mut self) -> Result<Vec<String>, FgdError> {
+        self.expect(Token::OpenParen)?;
+        let mut args = Vec::new();
+        let mut current: Vec<String> = Vec::new();
+        loop {
+            match self.next()? {
+                Token::CloseParen => break,
+                Token::Comma => args.push(std::mem::take(&mut current).join(" ")),
+                Token::Word(w) | Token::String(w) => current.push(w.clone()),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("')'"));
+                }
+            }
+        }
+        if !current.is_empty() || !args.is_empty() {
+            args.push(current.join(" "));
+        }
+        Ok(args)
+    }
+
+    /// Everything after `@PointClass` and friends
+    fn class(&mut self, kind: ClassKind) -> Result<EntityClass, FgdError> {
+        let mut helpers = Vec::new();
+        while let Some(Token::Word(name)) = self.peek() {
+            self.pos += 1;
+            let args = if self.peek() == Some(&Token::OpenParen) {
+                self.helper_args()?
+            } else {
+                // `halfgridsnap` and the like have no arguments
+                Vec::new()
+            };
+            helpers.push(Helper {
+                name: name.to_ascii_lowercase(),
+                args,
+            });
+        }
+
+        self.expect(Token::Equals)?;
+        let name = self.word()?.to_owned();
+        let description = if self.eat(Token::Colon) {
+            self.string()?
+        } else {
+            String::new()
+        };
+
+        let mut class = EntityClass {
+            name,
+            kind,
+            description,
+            bases: Vec::new(),
+            size: None,
+            color: None,
+            studio: None,
+            iconsprite: None,
+            helpers,
+            properties: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        self.apply_helpers(&mut class);
+
+        self.expect(Token::OpenBracket)?;
+        while !self.eat(Token::CloseBracket) {
+            self.member(&mut class)?;
+        }
+        Ok(class)
+    }
+
+    /// Fills in the helpers we understand
+    fn apply_helpers(&self, class: &mut EntityClass) {
+        for helper in &class.helpers {
+            match (helper.name.as_str(), helper.args.as_slice()) {
+                ("base", bases) => class.bases.extend(bases.iter().cloned()),
+                ("size", [min, max]) => {
+                    if let (Some(min), Some(max)) = (parse_vector(min), parse_vector(max)) {
+                        class.size = Some((min, max));
+                    }
+                }
+                // size(16) is a cube 16 units across
+                ("size", [size]) => {
+                    if let Ok(size) = size.parse::<f64>() {
+                        let half = DVec3::splat(size / 2.0);
+                        class.size = Some((-half, half));
+                    }
+                }
+                ("color", [color]) => {
+                    let rgb: Vec<u8> = color
+                        .split_whitespace()
+                        .filter_map(|c| c.parse().ok())
+                        .collect();
+                    if let [r, g, b] = rgb[..] {
+                        class.color = Some([r, g, b]);
+                    }
+                }
+                ("studio" | "studioprop", args) => {
+                    class.studio = Some(args.first().cloned().unwrap_or_default());
+                }
+                ("iconsprite", args) => {
+                    class.iconsprite = Some(args.first().cloned().unwrap_or_default());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A key, an input or an output
+    fn member(&mut self, class: &mut EntityClass) -> Result<(), FgdError> {
+        let name = self.word()?;
+
+        // `input` and `output` are only keywords when a name follows them
+        let io = name.eq_ignore_ascii_case("input") || name.eq_ignore_ascii_case("output");
+        if io && matches!(self.peek(), Some(Token::Word(_))) {
+            let io_name = self.word()?.to_owned();
+            self.expect(Token::OpenParen)?;
+            let kind = IoType::parse(self.word()?);
+            self.expect(Token::CloseParen)?;
+            let description = if self.eat(Token::Colon) {
+                self.string()?
+            } else {
+                String::new()
+            };
+            let def = IoDef {
+                name: io_name,
+                kind,
+                description,
+            };
+            if name.eq_ignore_ascii_case("input") {
+                class.inputs.push(def);
+            } else {
+                class.outputs.push(def);
+            }
+            return Ok(());
+        }
+
+        self.expect(Token::OpenParen)?;
+        let kind = PropertyType::parse(self.word()?);
+        self.expect(Token::CloseParen)?;
+
+        let mut property = Property {
+            name: name.to_owned(),
+            kind,
+            display_name: String::new(),
+            default: None,
+            description: String::new(),
+            readonly: false,
+            report: false,
+            choices: Vec::new(),
+            flags: Vec::new(),
+        };
+
+        while let Some(Token::Word(w)) = self.peek() {
+            match w.to_ascii_lowercase().as_str() {
+                "readonly" => property.readonly = true,
+                "report" => property.report = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        // name(type) : "Display name" : default : "Description", any of which
+        // can be left out, or left empty between the colons
+        if self.eat(Token::Colon) {
+            if let Some(Token::String(_)) = self.peek() {
+                property.display_name = self.string()?;
+            }
+            if self.eat(Token::Colon) {
+                if matches!(self.peek(), Some(Token::String(_) | Token::Word(_)))
+                    && !self.starts_member()
+                {
+                    property.default = Some(self.value()?);
+                }
+                if self.eat(Token::Colon) {
+                    if let Some(Token::String(_)) = self.peek() {
+                        property.description = self.string()?;
+                    }
+                }
+            }
+        }
+
+        if self.eat(Token::Equals) {
+            self.expect(Token::OpenBracket)?;
+            while !self.eat(Token::CloseBracket) {
+                let value = self.value()?;
+                self.expect(Token::Colon)?;
+                let name = self.string()?;
+                if property.kind == PropertyType::Flags {
+                    let bit = value
+                        .parse()
+                        .map_err(|_| self.error("a flag's bit as a number"))?;
+                    let default = if self.eat(Token::Colon) {
+                        self.value()? != "0"
+                    } else {
+                        false
+                    };
+                    property.flags.push(Flag { bit, name, default });
+                } else {
+                    property.choices.push(Choice { value, name });
+                }
+            }
+        }
+
+        class.properties.push(property);
+        Ok(())
+    }
+
+    /// Whether the next tokens are the start of another key (`name(`) or of
+    /// an input or output (`input Name(`), which means the key before had
+    /// nothing after its last colon
+    fn starts_member(&self) -> bool {
+        match (self.peek(), self.peek_at(1), self.peek_at(2)) {
+            (Some(Token::Word(_)), Some(Token::OpenParen), _) => true,
+            (Some(Token::Word(io)), Some(Token::Word(_)), Some(Token::OpenParen)) => {
+                io.eq_ignore_ascii_case("input") || io.eq_ignore_ascii_case("output")
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_vector(s: &str) -> Option<DVec3> {
+    let v: Vec<f64> = s
+        .split_whitespace()
+        .map(|c| c.parse().ok())
+        .collect::<Option<_>>()?;
+    match v[..] {
+        [x, y, z] => Some(DVec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> EntityClassDb {
+        let mut db = EntityClassDb::new();
+        db.parse_str(text).unwrap();
+        db
+    }
+
+    #[test]
+    fn class_kinds() {
+        let db = parse(
+            "@BaseClass = Targetname []\n\
+             @PointClass = info_target : \"A point\" []\n\
+             @NPCClass = npc_test []\n\
+             @SolidClass = func_detail []\n",
+        );
+        assert_eq!(db.len(), 4);
+        assert_eq!(db.get("targetname").unwrap().kind, ClassKind::Base);
+        assert_eq!(db.get("info_target").unwrap().kind, ClassKind::Point);
+        assert_eq!(db.get("info_target").unwrap().description, "A point");
+        assert_eq!(db.get("npc_test").unwrap().kind, ClassKind::Point);
+        assert_eq!(db.get("FUNC_DETAIL").unwrap().kind, ClassKind::Solid);
+        let names: Vec<_> = db.placeable().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["func_detail", "info_target", "npc_test"]);
+    }
+
+    #[test]
+    fn helpers() {
+        let db = parse(
+            "@PointClass base(Targetname, Angles) size(-8 -8 0, 8 8 32) color(255 0 128) \
+             studio(\"models/editor/playerstart.mdl\") halfgridsnap = a []\n\
+             @PointClass size(16) studio() iconsprite(\"editor/light.vmt\") = b []\n\
+             @PointClass size(oops) color(1 2) = c []\n",
+        );
+        let a = db.get("a").unwrap();
+        assert_eq!(a.bases, ["Targetname", "Angles"]);
+        assert_eq!(
+            a.size,
+            Some((DVec3::new(-8.0, -8.0, 0.0), DVec3::new(8.0, 8.0, 32.0)))
+        );
+        assert_eq!(a.color, Some([255, 0, 128]));
+        assert_eq!(a.studio.as_deref(), Some("models/editor/playerstart.mdl"));
+        assert_eq!(a.iconsprite, None);
+        assert!(a.helper("halfgridsnap").unwrap().args.is_empty());
+        assert_eq!(a.helpers.len(), 5);
+
+        let b = db.get("b").unwrap();
+        assert_eq!(b.size, Some((DVec3::splat(-8.0), DVec3::splat(8.0))));
+        // No model given, the entity's own model key is used
+        assert_eq!(b.studio.as_deref(), Some(""));
+        assert_eq!(b.iconsprite.as_deref(), Some("editor/light.vmt"));
+
+        let c = db.get("c").unwrap();
+        assert_eq!((c.size, c.color), (None, None));
+    }
+
+    #[test]
+    fn joined_descriptions() {
+        let db = parse(
+            "@PointClass = a : \"One, \" + \"two, \" +\n  \"three.\"\n[\n\
+             \tkey(string) : \"Key\" : \"\" : \"Long \" + \"description\"\n]\n",
+        );
+        let a = db.get("a").unwrap();
+        assert_eq!(a.description, "One, two, three.");
+        assert_eq!(a.property("key").unwrap().description, "Long description");
+    }
+
+    #[test]
+    fn properties() {
+        let db = parse(
+            "@PointClass = a\n[\n\
+             \tplain(string)\n\
+             \tnamed(integer) : \"Named\"\n\
+             \tfull(float) readonly report : \"Full\" : \"1.5\" : \"All of it\"\n\
+             \tbare(integer) : \"Bare\" : 3\n\
+             \tempty(string) : \"Empty\" : : \"No default\"\n\
+             \tcustom(mytype) : \"Custom\"\n\
+             ]\n",
+        );
+        let a = db.get("a").unwrap();
+        assert_eq!(a.properties.len(), 6);
+
+        let plain = a.property("plain").unwrap();
+        assert_eq!(plain.kind, PropertyType::String);
+        assert_eq!(plain.label(), "plain");
+        assert_eq!(plain.default, None);
+
+        assert_eq!(a.property("named").unwrap().label(), "Named");
+
+        let full = a.property("full").unwrap();
+        assert_eq!(full.kind, PropertyType::Float);
+        assert!(full.readonly && full.report);
+        assert_eq!(full.default.as_deref(), Some("1.5"));
+        assert_eq!(full.description, "All of it");
+
+        assert_eq!(a.property("bare").unwrap().default.as_deref(), Some("3"));
+
+        let empty = a.property("empty").unwrap();
+        assert_eq!(empty.default, None);
+        assert_eq!(empty.description, "No default");
+
+        assert_eq!(
+            a.property("custom").unwrap().kind,
+            PropertyType::Other("mytype".to_owned())
+        );
+    }
+
+    #[test]
+    fn nothing_after_the_last_colon() {
+        // The next key starts right after the colon, it isn't the default
+        let db = parse(
+            "@PointClass = a\n[\n\
+             \tfirst(string) : \"First\" :\n\
+             \tsecond(integer) : \"Second\" : 2 :\n\
+             \tinput Enable(void) : \"Turns it on\"\n\
+             \tthird(string) : \"Third\" :\n\
+             \toutput OnDone(void)\n\
+             ]\n",
+        );
+        let a = db.get("a").unwrap();
+        let names: Vec<_> = a.properties.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["first", "second", "third"]);
+        assert_eq!(a.property("first").unwrap().default, None);
+        assert_eq!(a.property("second").unwrap().default.as_deref(), Some("2"));
+        assert_eq!(a.property("third").unwrap().default, None);
+        assert!(a.input("Enable").is_some());
+        assert!(a.output("OnDone").is_some());
+    }
+
+    #[test]
+    fn choices_and_flags() {
+        let db = parse(
+            "@SolidClass = a\n[\n\
+             \trendermode(choices) : \"Render Mode\" : 0 =\n\t[\n\
+             \t\t0 : \"Normal\"\n\
+             \t\t\"2\" : \"Texture\"\n\
+             \t\tadd : \"Additive\"\n\
+             \t]\n\
+             \tspawnflags(flags) =\n\t[\n\
+             \t\t1 : \"Start off\" : 0\n\
+             \t\t2 : \"Once\" : 1\n\
+             \t\t4096 : \"No default\"\n\
+             \t]\n\
+             ]\n",
+        );
+        let a = db.get("a").unwrap();
+        let mode = a.property("rendermode").unwrap();
+        assert_eq!(mode.default.as_deref(), Some("0"));
+        let choices: Vec<_> = mode
+            .choices
+            .iter()
+            .map(|c| (c.value.as_str(), c.name.as_str()))
+            .collect();
+        assert_eq!(
+            choices,
+            [("0", "Normal"), ("2", "Texture"), ("add", "Additive")]
+        );
+
+        let flags = &a.property("spawnflags").unwrap().flags;
+        let flags: Vec<_> = flags
+            .iter()
+            .map(|f| (f.bit, f.name.as_str(), f.default))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                (1, "Start off", false),
+                (2, "Once", true),
+                (4096, "No default", false)
+            ]
+        );
+
+        let mut db = EntityClassDb::new();
+        assert!(db
+            .parse_str("@PointClass = a [ spawnflags(flags) = [ x : \"X\" ] ]")
+            .is_err());
+    }
+
+    #[test]
+    fn inputs_and_outputs() {
+        let db = parse(
+            "@PointClass = a\n[\n\
+             \tinput Kill(void) : \"Removes it\"\n\
+             \tinput SetHealth(integer)\n\
+             \toutput OnTrigger(void) : \"When it \" + \"fires\"\n\
+             \toutput OnValue(mytype)\n\
+             \tinput(string) : \"A key named input\"\n\
+             ]\n",
+        );
+        let a = db.get("a").unwrap();
+        assert_eq!(a.inputs.len(), 2);
+        assert_eq!(a.outputs.len(), 2);
+        let kill = a.input("kill").unwrap();
+        assert_eq!(
+            (&kill.kind, kill.description.as_str()),
+            (&IoType::Void, "Removes it")
+        );
+        assert_eq!(a.input("SetHealth").unwrap().kind, IoType::Integer);
+        assert_eq!(a.output("OnTrigger").unwrap().description, "When it fires");
+        assert_eq!(
+            a.output("OnValue").unwrap().kind,
+            IoType::Other("mytype".to_owned())
+        );
+        assert_eq!(a.property("input").unwrap().kind, PropertyType::String);
+    }
+
+    #[test]
+    fn other_directives() {
+        let db = parse(
+            "@mapsize(-16384, 16384)\n\
+             @MaterialExclusion [ \"debug\" \"engine\" ]\n\
+             @AutoVisGroup = \"Tool Brushes\" [ \"Clip\" [ \"func_clip_vphysics\" ] ]\n\
+             @AutoVisGroup = \"Tool Brushes\" [ \"Triggers\" [ \"trigger_once\" ] ]\n\
+             @gridnav(64, 32, 32, 16384)\n\
+             @PointClass = a []\n",
+        );
+        assert_eq!(db.map_size, Some((-16384, 16384)));
+        assert_eq!(db.material_exclusions, ["debug", "engine"]);
+        assert_eq!(db.auto_visgroups.len(), 1);
+        assert_eq!(db.auto_visgroups[0].groups.len(), 2);
+        assert!(db.get("a").is_some());
+    }
+
+    #[test]
+    fn syntax_errors_say_where() {
+        let mut db = EntityClassDb::new();
+        let Err(FgdError::Syntax { position, .. }) =
+            db.parse_str("@PointClass = a\n[\n\tkey(string) : \"Key\" ] ]\n@")
+        else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(position.line, 3);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::vmf2::error::Position;
+
+use super::FgdError;
+
+/*
+FGD tokens. Everything that isn't punctuation or a quoted string is a word:
+names, types, numbers and bare defaults alike.
+
+    @PointClass base(Targetname) size(-8 -8 -8, 8 8 8) = info_target :
+        "A point " + "target" [ ... ]
+
+Quoted strings can't contain quotes or span lines, and `+` between two of
+them glues them together, which the parser takes care of.
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    String(String),
+    At,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Colon,
+    Equals,
+    Comma,
+    Plus,
+}
+
+impl Token {
+    /// For error messages
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Word(w) => format!("\"{w}\""),
+            Token::String(s) => format!("string \"{s}\""),
+            Token::At => "'@'".to_owned(),
+            Token::OpenParen => "'('".to_owned(),
+            Token::CloseParen => "')'".to_owned(),
+            Token::OpenBracket => "'['".to_owned(),
+            Token::CloseBracket => "']'".to_owned(),
+            Token::Colon => "':'".to_owned(),
+            Token::Equals => "'='".to_owned(),
+            Token::Comma => "','".to_owned(),
+            Token::Plus => "'+'".to_owned(),
+        }
+    }
+}
+
+/// Splits a whole file into tokens up front, the parser needs to look ahead
+pub fn tokenize(input: &str) -> Result<Vec<(Token, Position)>, FgdError> {
+    let mut tokens = Vec::new();
+    let mut position = Position { line: 1, column: 1 };
+    let mut chars = input.chars().peekable();
+
+    let advance = |c: char, position: &mut Position| {
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    };
+
+    while let Some(&c) = chars.peek() {
+        let start = position;
+
+        if c.is_whitespace() {
+            chars.next();
+            advance(c, &mut position);
+            continue;
+        }
+
+        if c == '/' {
+            chars.next();
+            advance(c, &mut position);
+            if chars.peek() != Some(&'/') {
+                return Err(FgdError::syntax(start, "expected '//' to start a comment"));
+            }
+            // the newline itself is whitespace, left for the next round
+            while let Some(&c) = chars.peek() {
+                if c == '\n' {
+                    break;
+                }
+                chars.next();
+                advance(c, &mut position);
+            }
+            continue;
+        }
+
+        let punctuation = match c {
+            '@' => Some(Token::At),
+            '(' => Some(Token::OpenParen),
+            ')' => Some(Token::CloseParen),
+            '[' => Some(Token::OpenBracket),
+            ']' => Some(Token::CloseBracket),
+            ':' => Some(Token::Colon),
+            '=' => Some(Token::Equals),
+            ',' => Some(Token::Comma),
+            '+' => Some(Token::Plus),
+            _ => None,
+        };
+        if let Some(token) = punctuation {
+            chars.next();
+            advance(c, &mut position);
+            tokens.push((token, start));
+            continue;
+        }
+
+        if c == '"' {
+            chars.next();
+            advance(c, &mut position);
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => {
+                        advance('"', &mut position);
+                        break;
+                    }
+                    Some('\n') | None => {
+                        return Err(FgdError::syntax(start, "unterminated string"));
+                    }
+                    Some(c) => {
+                        advance(c, &mut position);
+                        s.push(c);
+                    }
+                }
+            }
+            tokens.push((Token::String(s), start));
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || "@()[]:=,+\"".contains(c) {
+                break;
+            }
+            chars.next();
+            advance(c, &mut position);
+            word.push(c);
+        }
+        tokens.push((Token::Word(word), start));
+    }
+
+    Ok(tokens)
+}
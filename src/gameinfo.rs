@@ -0,0 +1,287 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    vfs::FileSystem,
+    vmf2::{
+        error::VmfError,
+        generic::{Entry, GenericNode},
+    },
+    vpk::VpkError,
+};
+
+/*
+The part of gameinfo.txt that says where a game's files are:
+
+    "GameInfo"
+    {
+        game "Half-Life 2"
+        FileSystem
+        {
+            SearchPaths
+            {
+                game            |all_source_engine_paths|hl2/hl2_textures.vpk
+                game+mod+game_write     |gameinfo_path|.
+            }
+        }
+    }
+
+`|gameinfo_path|` is the directory gameinfo.txt is in. Everything else,
+including `|all_source_engine_paths|`, is relative to the directory above
+it, where hl2.exe lives. A path ending in a `*` (`hl2/custom/` and a star)
+means every directory and pack in that directory. Only `game` paths matter for finding
+materials and models.
+*/
+
+#[derive(Clone, Debug)]
+pub struct SearchPath {
+    /// What the path is for, lowercase: `game`, `mod`, `platform`, ...
+    pub kinds: Vec<String>,
+    pub path: String,
+}
+
+impl SearchPath {
+    pub fn is(&self, kind: &str) -> bool {
+        self.kinds.iter().any(|k| k.eq_ignore_ascii_case(kind))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameInfo {
+    /// The gameinfo.txt this was read from
+    pub path: PathBuf,
+    /// The name of the game
+    pub game: String,
+    pub search_paths: Vec<SearchPath>,
+}
+
+impl GameInfo {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, VmfError> {
+        let path = path.into();
+        let text = std::fs::read_to_string(&path)?;
+        Self::parse(path, &text)
+    }
+
+    /// `#base` and `#include` files are read from the directory
+    /// gameinfo.txt is in
+    pub fn parse(path: impl Into<PathBuf>, text: &str) -> Result<Self, VmfError> {
+        let path = path.into();
+        let dir = path.parent().unwrap_or(Path::new(".")).to_owned();
+        let mut root = GenericNode::parse(text)?;
+        root.resolve_includes(|include| {
+            GenericNode::parse(&std::fs::read_to_string(dir.join(include))?)
+        })?;
+        let info = child(&root, "GameInfo").ok_or_else(|| VmfError::MissingBlock {
+            position: root.position,
+            name: "GameInfo".to_owned(),
+        })?;
+
+        let search_paths = child(info, "FileSystem")
+            .and_then(|fs| child(fs, "SearchPaths"))
+            .map(|paths| {
+                paths
+                    .entries
+                    .iter()
+                    .filter_map(|e| match e {
+                        Entry::KeyValue(k, v, _) => Some(SearchPath {
+                            kinds: k
+                                .split('+')
+                                .map(|k| k.trim().to_ascii_lowercase())
+                                .collect(),
+                            path: v.clone(),
+                        }),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let game = info
+            .entries
+            .iter()
+            .find_map(|e| match e {
+                Entry::KeyValue(k, v, _) if k.eq_ignore_ascii_case("game") => Some(v.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            path,
+            game,
+            search_paths,
+        })
+    }
+
+    /// The mod's own directory, the one gameinfo.txt is in
+    pub fn game_dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// The directory with the engine in it, which other paths are relative to
+    pub fn base_dir(&self) -> &Path {
+        self.game_dir().parent().unwrap_or(Path::new("."))
+    }
+
+    /// The directories and packs a search path stands for, wildcards expanded.
+    /// Nothing checks that they exist.
+    pub fn resolve(&self, search_path: &SearchPath) -> Vec<PathBuf> {
+        let path = search_path.path.replace('\\', "/");
+        let path = if let Some(rest) = strip_prefix_ignore_case(&path, "|gameinfo_path|") {
+            self.game_dir().join(rest)
+        } else if let Some(rest) = strip_prefix_ignore_case(&path, "|all_source_engine_paths|") {
+            self.base_dir().join(rest)
+        } else {
+            self.base_dir().join(path)
+        };
+
+        let Some(dir) = path
+            .to_str()
+            .and_then(|p| p.strip_suffix('*'))
+            .map(PathBuf::from)
+        else {
+            return vec![path];
+        };
+
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_dir() || (p.extension().is_some_and(|e| e == "vpk") && !is_archive(p)))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Mounts every `game` search path in order. Packs that can't be read
+    /// are skipped and handed back with why.
+    pub fn file_system(&self) -> (FileSystem, Vec<(PathBuf, VpkError)>) {
+        let mut fs = FileSystem::new();
+        let mut errors = Vec::new();
+
+        for search_path in self.search_paths.iter().filter(|p| p.is("game")) {
+            for path in self.resolve(search_path) {
+                let is_vpk = path.extension().is_some_and(|e| e == "vpk");
+                if !is_vpk && !path.is_dir() {
+                    continue;
+                }
+                if let Err(e) = fs.mount(&path) {
+                    errors.push((path, e));
+                }
+            }
+        }
+
+        (fs, errors)
+    }
+}
+
+/// The first child block called `name`, ignoring case like the engine does
+fn child<'a>(node: &'a GenericNode, name: &str) -> Option<&'a GenericNode> {
+    node.entries.iter().find_map(|e| match e {
+        Entry::Child(n, c) if n.eq_ignore_ascii_case(name) => Some(c),
+        _ => None,
+    })
+}
+
+/// `pak01_000.vpk` and so on only hold file data for `pak01_dir.vpk`
+fn is_archive(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.rsplit_once('_'))
+        .is_some_and(|(_, n)| n.len() == 3 && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &s[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vpk::tests::{temp_dir, write_vpk};
+
+    const GAMEINFO: &str = r#"
+        "GameInfo"
+        {
+            game "My Mod"
+            FileSystem
+            {
+                SearchPaths
+                {
+                    Game+Mod            |gameinfo_path|custom/*
+                    game+mod+game_write |GameInfo_Path|.
+                    game                |all_source_engine_paths|hl2/hl2_textures.vpk
+                    game                hl2
+                    platform            |all_source_engine_paths|platform
+                }
+            }
+        }
+    "#;
+
+    fn write(dir: &Path, path: &str, text: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn search_paths() {
+        let root = temp_dir("gameinfo");
+        write(&root, "mod/gameinfo.txt", GAMEINFO);
+        write(&root, "mod/materials/a.vmt", "mod");
+        write(&root, "mod/custom/loose/materials/a.vmt", "loose");
+        write(&root, "mod/custom/readme.txt", "");
+        write_vpk(
+            &root.join("mod/custom"),
+            "pack",
+            2,
+            &[("materials/b.vmt", b"pack")],
+        );
+        write(&root, "hl2/materials/c.vmt", "hl2");
+        write_vpk(
+            &root.join("hl2"),
+            "hl2_textures",
+            2,
+            &[("materials/c.vmt", b"vpk")],
+        );
+
+        let info = GameInfo::load(root.join("mod/gameinfo.txt")).unwrap();
+        assert_eq!(info.game, "My Mod");
+        let paths = &info.search_paths;
+        assert_eq!(paths.len(), 5);
+        assert_eq!(paths[1].kinds, ["game", "mod", "game_write"]);
+        assert!(paths[0].is("GAME") && paths[0].is("mod") && !paths[4].is("game"));
+
+        let resolve = |i: usize| info.resolve(&paths[i]);
+        assert_eq!(info.game_dir(), root.join("mod"));
+        // The packs' numbered archives aren't packs of their own
+        assert_eq!(
+            resolve(0),
+            [
+                root.join("mod/custom/loose"),
+                root.join("mod/custom/pack_dir.vpk")
+            ]
+        );
+        assert_eq!(resolve(1), [root.join("mod/.")]);
+        assert_eq!(resolve(2), [root.join("hl2/hl2_textures.vpk")]);
+        assert_eq!(resolve(3), [root.join("hl2")]);
+
+        let (fs, errors) = info.file_system();
+        assert!(errors.is_empty());
+        assert_eq!(fs.mounts().len(), 5);
+        let read = |path| fs.read_to_string(path).unwrap();
+        assert_eq!(read("materials/a.vmt"), "loose");
+        assert_eq!(read("materials/b.vmt"), "pack");
+        assert_eq!(read("materials/c.vmt"), "vpk");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn missing_search_paths() {
+        let info = GameInfo::parse("gameinfo.txt", "GameInfo { game Nothing }").unwrap();
+        assert!(info.search_paths.is_empty());
+        assert!(GameInfo::parse("gameinfo.txt", "Other { }").is_err());
+    }
+}
@@ -1,125 +1,208 @@
 use crate::vmf2::vmf;
 use bevy::{
+    math::{DVec2, DVec3},
     prelude::*,
     render::{mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology},
 };
 
+/// A plane as `normal · x = d`, with a unit length normal pointing out of the
+/// brush. Everything here is f64, polygons only go down to f32 when they
+/// become meshes.
 #[derive(Debug)]
 pub struct StandardPlane {
-    pub normal: Vec3,
-    pub d: f32,
+    pub normal: DVec3,
+    pub d: f64,
 }
 
 impl StandardPlane {
     pub fn new(vmf_plane: &vmf::Plane) -> Self {
         // https://tutorial.math.lamar.edu/classes/calcIII/EqnsOfPlanes.aspx
 
-        let p = vmf_plane.points[0].new_vec3();
-        let q = vmf_plane.points[1].new_vec3();
-        let r = vmf_plane.points[2].new_vec3();
+        let p = vmf_plane.points[0].new_dvec3();
+        let q = vmf_plane.points[1].new_dvec3();
+        let r = vmf_plane.points[2].new_dvec3();
 
         let p_to_q = q - p;
         let p_to_r = r - p;
 
-        let c = p_to_q.cross(p_to_r);
+        // Three points in a line give a zero normal, is_degenerate catches those
+        let c = p_to_q.cross(p_to_r).normalize_or_zero();
 
         let w = p.dot(c);
 
-        if w.abs() > 20_000_000.0 {
-            dbg!(&(p, q, r, c, w));
-        }
-
         StandardPlane { normal: c, d: w }
     }
 
-    pub fn intersection_point(&self, p1: &Self, p2: &Self) -> Option<Vec3> {
-        let s = Mat3 {
-            x_axis: self.normal,
-            y_axis: p1.normal,
-            z_axis: p2.normal,
-        }
-        .transpose();
+    /// Signed distance from the plane, positive is outside the brush
+    pub fn distance(&self, point: DVec3) -> f64 {
+        self.normal.dot(point) - self.d
+    }
 
-        let d_col = Vec3::new(self.d, p1.d, p2.d);
+    /// Three points in a line don't make a plane
+    pub fn is_degenerate(&self) -> bool {
+        self.normal == DVec3::ZERO
+    }
 
-        if s.determinant() == 0.0 {
-            None
-        } else {
-            let x = s.inverse() * d_col;
-            // if x.length() > 20_000.0 {
-            //     None
-            // } else {
-            //     Some(x)
-            // }
-            Some(x)
-        }
+    /// Whether both planes are the same, facing the same way
+    pub fn is_same_as(&self, other: &Self) -> bool {
+        self.normal.dot(other.normal) > 1.0 - NORMAL_EPSILON && (self.d - other.d).abs() < EPSILON
+    }
+
+    /// A square on this plane that is bigger than any map can be, wound
+    /// counter-clockwise when looked at from outside the brush
+    fn base_polygon(&self) -> Vec<DVec3> {
+        let center = self.normal * self.d;
+
+        let u = self.normal.any_orthonormal_vector() * MAX_COORD;
+        let v = self.normal.cross(u);
+
+        vec![
+            center - u - v,
+            center + u - v,
+            center + u + v,
+            center - u + v,
+        ]
     }
 }
 
-pub fn planes_to_sides(planes: &[StandardPlane]) -> Vec<Vec<Vec3>> {
-    let mut sides: Vec<Vec<Vec3>> = Vec::new();
+/// Further than anything in a map can be from the origin (Hammer's grid ends
+/// at ±16384), so the base polygon covers everything
+const MAX_COORD: f64 = 65536.0;
 
-    for (i, p1) in planes.iter().enumerate() {
-        let mut points = Vec::new();
+/// How far a point can be from a plane, in map units, and still be on it
+const EPSILON: f64 = 0.01;
+const NORMAL_EPSILON: f64 = 0.00001;
 
-        let mut start = None;
+/// Cuts away the part of `polygon` that is in front of (outside) `plane`.
+/// Points within `EPSILON` of the plane are kept as they are, so faces that
+/// only touch a plane don't get sliced into slivers.
+fn clip_polygon(polygon: &[DVec3], plane: &StandardPlane) -> Vec<DVec3> {
+    let distances: Vec<f64> = polygon.iter().map(|p| plane.distance(*p)).collect();
 
-        'outer: for (j, p2) in planes.iter().enumerate() {
-            for (k, p3) in planes.iter().enumerate() {
-                if i != j && j != k && i != k {
-                    if let Some(point) = p1.intersection_point(p2, p3) {
-                        points.push(point);
-                        start = Some((j, k));
-                        break 'outer;
-                    }
-                }
-            }
-        }
+    if distances.iter().all(|d| *d <= EPSILON) {
+        return polygon.to_vec();
+    }
+    if distances.iter().all(|d| *d >= -EPSILON) {
+        return Vec::new();
+    }
 
-        if start.is_none() {
-            break;
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for i in 0..polygon.len() {
+        let j = (i + 1) % polygon.len();
+        let (a, b) = (polygon[i], polygon[j]);
+        let (da, db) = (distances[i], distances[j]);
+
+        if da <= EPSILON {
+            clipped.push(a);
+        }
+        // Only edges that properly cross the plane get a new point
+        if (da < -EPSILON && db > EPSILON) || (da > EPSILON && db < -EPSILON) {
+            clipped.push(a + (b - a) * (da / (da - db)));
         }
+    }
+    clipped
+}
+
+/// Removes points that are on top of each other, then checks there is still
+/// a face left
+fn clean_polygon(mut polygon: Vec<DVec3>) -> Option<Vec<DVec3>> {
+    polygon.dedup_by(|a, b| a.distance_squared(*b) < EPSILON * EPSILON);
+    while polygon.len() > 1
+        && polygon[0].distance_squared(polygon[polygon.len() - 1]) < EPSILON * EPSILON
+    {
+        polygon.pop();
+    }
+
+    if polygon.len() < 3 {
+        return None;
+    }
 
-        let (start_j, start_k) = start.unwrap();
-        let mut j = start_j;
-        let mut k = start_k;
+    let mut area = DVec3::ZERO;
+    for i in 1..polygon.len() - 1 {
+        area += (polygon[i] - polygon[0]).cross(polygon[i + 1] - polygon[0]);
+    }
+    (area.length() / 2.0 > EPSILON).then_some(polygon)
+}
+
+/// Works out the face polygons of a convex brush from its planes, by cutting
+/// a huge square on each plane down with every other plane.
+///
+/// There is one entry per plane, in the same order. Planes that don't end up
+/// with a face (because they are repeated, outside the brush or don't make
+/// sense) get `None`. Polygons are wound counter-clockwise looking from
+/// outside the brush.
+pub fn planes_to_sides(planes: &[StandardPlane]) -> Vec<Option<Vec<DVec3>>> {
+    planes
+        .iter()
+        .enumerate()
+        .map(|(i, plane)| {
+            if plane.is_degenerate() {
+                return None;
+            }
 
-        fn find_with_with_without(
-            planes: &[StandardPlane],
-            required: usize,
-            with: usize,
-            without: usize,
-        ) -> Option<(usize, Vec3)> {
-            for (i, plane) in planes.iter().enumerate() {
-                if i != required && i != with && i != without {
-                    if let Some(point) = plane.intersection_point(&planes[required], &planes[with])
-                    {
-                        return Some((i, point));
+            let mut polygon = plane.base_polygon();
+            for (j, other) in planes.iter().enumerate() {
+                if i == j || other.is_degenerate() {
+                    continue;
+                }
+                // A repeated plane only gets one face, the first one
+                if plane.is_same_as(other) {
+                    if j < i {
+                        return None;
                     }
+                    continue;
+                }
+                polygon = clip_polygon(&polygon, other);
+                if polygon.is_empty() {
+                    return None;
                 }
             }
-            None
-        }
 
-        while let Some((index, point)) = find_with_with_without(planes, i, j, k) {
-            // println!("estoy loopin? {i} {j} {k}");
-            points.push(point);
-            k = j;
-            j = index;
+            clean_polygon(polygon)
+        })
+        .collect()
+}
 
-            if j == start_j && k == start_k || j == start_k && k == start_j {
-                break;
-            }
-        }
+/// Texture coordinates for each point of a face, from its texture axes:
+/// `u = (point · axis / scale + shift) / width`, the same for v.
+pub fn side_uvs(side: &[DVec3], vmf_side: &vmf::Side, texture_size: DVec2) -> Vec<DVec2> {
+    let coord = |axis: &vmf::UV, point: DVec3, size: f64| {
+        // A zero scale would be a broken map, treat it like Hammer's default
+        let scale = if axis.scale() == 0.0 {
+            0.25
+        } else {
+            axis.scale()
+        };
+        (point.dot(axis.axis()) / scale + axis.shift()) / size
+    };
 
-        sides.push(points);
-    }
+    side.iter()
+        .map(|p| {
+            DVec2::new(
+                coord(&vmf_side.u_axis, *p, texture_size.x),
+                coord(&vmf_side.v_axis, *p, texture_size.y),
+            )
+        })
+        .collect()
+}
 
-    sides
+pub fn side_to_triangles(side: &[DVec3], uvs: &[DVec2]) -> Mesh {
+    // A fan around the first point, the polygons are convex
+    let mut idx = Vec::new();
+    for x in 1..side.len() - 1 {
+        idx.push(0);
+        idx.push(x as u16);
+        idx.push((x + 1) as u16);
+    }
+    triangles_mesh(side, uvs, idx)
 }
 
-pub fn side_to_triangles(side: Vec<Vec3>) -> Mesh {
-    let len = side.len();
+fn triangles_mesh(points: &[DVec3], uvs: &[DVec2], indices: Vec<u16>) -> Mesh {
+    let points: Vec<Vec3> = points.iter().map(|p| p.as_vec3()).collect();
+    // Textures repeat, so move the coordinates near zero before they go down
+    // to f32. Far from the origin they would lose too much precision.
+    let origin = uvs.first().map_or(DVec2::ZERO, |uv| uv.floor());
+    let uvs: Vec<Vec2> = uvs.iter().map(|uv| (*uv - origin).as_vec2()).collect();
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
         // IMPORTANT NOTE: We *need* MAIN_WORLD here because the data must stay on
@@ -127,21 +210,23 @@ pub fn side_to_triangles(side: Vec<Vec3>) -> Mesh {
         //                 Without this, these faces silently aren't used in raycasts
         RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
     );
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, side);
-    let mut idx = Vec::new();
-    for x in 2..len - 1 {
-        idx.push((x + 1) as u16);
-        idx.push(x as u16);
-        idx.push(1);
-    }
-    mesh.insert_indices(Indices::U16(idx));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, points);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_indices(Indices::U16(indices));
     mesh.duplicate_vertices();
     mesh.compute_flat_normals();
 
     mesh
 }
 
-pub fn side_to_lines(side: Vec<Vec3>) -> Mesh {
+pub fn side_to_lines(side: &[DVec3]) -> Mesh {
+    // Back to the start to close the outline
+    let side: Vec<Vec3> = side
+        .iter()
+        .chain(side.first())
+        .map(|p| p.as_vec3())
+        .collect();
+
     let mut linemesh = Mesh::new(
         PrimitiveTopology::LineStrip,
         RenderAssetUsages::RENDER_WORLD,
@@ -151,3 +236,551 @@ pub fn side_to_lines(side: Vec<Vec3>) -> Mesh {
 
     linemesh
 }
+
+/// The points of a displacement row by row, and where they are on the flat
+/// face, which the texture is mapped from. The face has to have four
+/// corners, and the grids have to be big enough for the displacement's
+/// power.
+pub fn disp_points(
+    side: &[DVec3],
+    disp: &vmf::DispInfo,
+    normal: DVec3,
+) -> Option<(Vec<DVec3>, Vec<DVec3>)> {
+    // Hammer only makes powers 2 to 4, anything much bigger is a broken file
+    if side.len() != 4 || disp.power > 4 {
+        return None;
+    }
+    let size = disp.size();
+    // The grid starts at the corner nearest the start position, and goes
+    // round the face the same way the polygon does
+    let start = disp.start_position.new_dvec3();
+    let first = (0..4).min_by(|a, b| {
+        let distance = |i: &usize| side[*i].distance_squared(start);
+        distance(a).total_cmp(&distance(b))
+    })?;
+    let corner = |i: usize| side[(first + i) % 4];
+    let hammer = |v: &Vec3| DVec3::new(v.x as f64, v.z as f64, v.y as f64);
+    let elevation = normal * disp.elevation as f64;
+
+    let last = (size - 1) as f64;
+    let mut points = Vec::with_capacity(size * size);
+    let mut flat = Vec::with_capacity(size * size);
+    for row in 0..size {
+        let t = row as f64 / last;
+        let (a, b) = (corner(0).lerp(corner(1), t), corner(3).lerp(corner(2), t));
+        for col in 0..size {
+            let point = a.lerp(b, col as f64 / last);
+            let direction = hammer(disp.normals.get(row)?.get(col)?);
+            let distance = *disp.distances.get(row)?.get(col)? as f64;
+            let offset = disp
+                .offsets
+                .get(row)
+                .and_then(|r| r.get(col))
+                .map_or(DVec3::ZERO, hammer);
+            points.push(point + direction * distance + offset + elevation);
+            flat.push(point);
+        }
+    }
+    Some((points, flat))
+}
+
+/// Two triangles for every square of a `size` by `size` grid. The diagonals
+/// alternate like a chequerboard, the way Hammer splits them.
+fn disp_indices(size: usize) -> Vec<u16> {
+    let mut idx = Vec::new();
+    for row in 0..size - 1 {
+        for col in 0..size - 1 {
+            let i = |r: usize, c: usize| (r * size + c) as u16;
+            let (a, b, c, d) = (
+                i(row, col),
+                i(row + 1, col),
+                i(row + 1, col + 1),
+                i(row, col + 1),
+            );
+            if (row + col) % 2 == 0 {
+                idx.extend([a, b, c, a, c, d]);
+            } else {
+                idx.extend([a, b, d, b, c, d]);
+            }
+        }
+    }
+    idx
+}
+
+pub fn disp_to_triangles(points: &[DVec3], uvs: &[DVec2], size: usize) -> Mesh {
+    triangles_mesh(points, uvs, disp_indices(size))
+}
+
+/// Every edge of the displacement's triangles, for the 2D views
+pub fn disp_to_lines(points: &[DVec3], size: usize) -> Mesh {
+    let lines: Vec<Vec3> = disp_indices(size)
+        .chunks(3)
+        .flat_map(|t| [t[0], t[1], t[1], t[2], t[2], t[0]])
+        .map(|i| points[i as usize].as_vec3())
+        .collect();
+
+    let mut linemesh = Mesh::new(PrimitiveTopology::LineList, RenderAssetUsages::RENDER_WORLD);
+    linemesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, lines);
+
+    linemesh
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+    use crate::vmf2::generic::GenericNode;
+
+    /// Planes from Hammer's `(x y z) (x y z) (x y z)`, Z up
+    fn hammer_planes(planes: &[&str]) -> Vec<StandardPlane> {
+        planes
+            .iter()
+            .map(|p| StandardPlane::new(&p.parse().unwrap()))
+            .collect()
+    }
+
+    fn plane(normal: DVec3, d: f64) -> StandardPlane {
+        StandardPlane {
+            normal: normal.normalize(),
+            d,
+        }
+    }
+
+    /// Planes around the origin, `n` of them in a circle and a top and bottom
+    fn prism(n: usize, radius: f64, height: f64) -> Vec<StandardPlane> {
+        let mut planes = vec![plane(DVec3::Y, height), plane(DVec3::NEG_Y, 0.0)];
+        for i in 0..n {
+            let angle = i as f64 / n as f64 * TAU;
+            planes.push(plane(DVec3::new(angle.cos(), 0.0, angle.sin()), radius));
+        }
+        planes
+    }
+
+    const CUBE: [&str; 6] = [
+        "(-256 256 0) (256 256 0) (256 -256 0)",
+        "(-256 -256 -64) (256 -256 -64) (256 256 -64)",
+        "(-256 256 0) (-256 -256 0) (-256 -256 -64)",
+        "(256 256 -64) (256 -256 -64) (256 -256 0)",
+        "(256 256 0) (-256 256 0) (-256 256 -64)",
+        "(256 -256 -64) (-256 -256 -64) (-256 -256 0)",
+    ];
+
+    /// Twice the area, facing the way the polygon is wound
+    fn area(polygon: &[DVec3]) -> DVec3 {
+        (1..polygon.len() - 1)
+            .map(|i| (polygon[i] - polygon[0]).cross(polygon[i + 1] - polygon[0]))
+            .sum()
+    }
+
+    /// Checks every face lies on its plane, inside all the others and is
+    /// wound counter-clockwise from outside, and that together they close
+    fn check_brush(planes: &[StandardPlane], sides: &[Option<Vec<DVec3>>]) -> usize {
+        assert_eq!(planes.len(), sides.len());
+        let mut total = DVec3::ZERO;
+        for (plane, side) in planes.iter().zip(sides) {
+            let Some(side) = side else {
+                continue;
+            };
+            for point in side {
+                assert!(
+                    plane.distance(*point).abs() < EPSILON,
+                    "{point} off its plane"
+                );
+                for other in planes.iter().filter(|p| !p.is_degenerate()) {
+                    assert!(other.distance(*point) < EPSILON, "{point} outside");
+                }
+            }
+            let area = area(side);
+            assert!(
+                area.normalize().dot(plane.normal) > 0.999,
+                "wound clockwise"
+            );
+            total += area;
+        }
+        assert!(total.length() < 0.1, "faces don't close, {total}");
+        sides.iter().flatten().count()
+    }
+
+    #[test]
+    fn cube() {
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+        assert!(sides.iter().flatten().all(|s| s.len() == 4));
+        // Hammer's first plane is the top, Y is up here
+        assert_eq!(planes[0].normal, DVec3::Y);
+        let top = sides[0].as_ref().unwrap();
+        assert!(top
+            .iter()
+            .all(|p| p.y == 0.0 && p.x.abs() == 256.0 && p.z.abs() == 256.0));
+    }
+
+    #[test]
+    fn wedge() {
+        let planes = vec![
+            plane(DVec3::NEG_Y, 0.0),
+            plane(DVec3::NEG_X, 0.0),
+            plane(DVec3::Z, 64.0),
+            plane(DVec3::NEG_Z, 64.0),
+            plane(DVec3::new(1.0, 1.0, 0.0), 64.0 / 2f64.sqrt()),
+        ];
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 5);
+        let counts: Vec<_> = sides.iter().flatten().map(Vec::len).collect();
+        assert_eq!(counts, [4, 4, 3, 3, 4]);
+    }
+
+    #[test]
+    fn cylinder() {
+        let planes = prism(16, 64.0, 128.0);
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 18);
+        assert_eq!(sides[0].as_ref().unwrap().len(), 16);
+        assert_eq!(sides[1].as_ref().unwrap().len(), 16);
+        assert!(sides[2..].iter().flatten().all(|s| s.len() == 4));
+    }
+
+    #[test]
+    fn spike() {
+        // A base and 8 sides leaning in to meet at a point 128 units up
+        let mut planes = vec![plane(DVec3::NEG_Y, 0.0)];
+        for i in 0..8 {
+            let angle = i as f64 / 8.0 * TAU;
+            let out = DVec3::new(angle.cos(), 0.0, angle.sin());
+            let normal = (out * 128.0 + DVec3::Y * 64.0).normalize();
+            planes.push(plane(normal, normal.dot(DVec3::Y * 128.0)));
+        }
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 9);
+        assert_eq!(sides[0].as_ref().unwrap().len(), 8);
+        for side in sides[1..].iter().flatten() {
+            assert_eq!(side.len(), 3);
+            assert!(side.iter().any(|p| p.distance(DVec3::Y * 128.0) < EPSILON));
+        }
+    }
+
+    #[test]
+    fn repeated_plane_gets_one_face() {
+        let mut strings = CUBE.to_vec();
+        strings.push(CUBE[0]);
+        strings.insert(3, CUBE[4]);
+        let planes = hammer_planes(&strings);
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+        assert!(sides[0].is_some() && sides[7].is_none());
+        assert!(sides[3].is_some() && sides[5].is_none());
+    }
+
+    #[test]
+    fn degenerate_plane_has_no_face() {
+        let mut strings = CUBE.to_vec();
+        strings.insert(2, "(0 0 0) (64 64 64) (128 128 128)");
+        strings.push("(0 0 0) (0 0 0) (256 0 0)");
+        let planes = hammer_planes(&strings);
+        assert!(planes[2].is_degenerate() && planes[7].is_degenerate());
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+        assert!(sides[2].is_none() && sides[7].is_none());
+    }
+
+    #[test]
+    fn redundant_planes_have_no_face() {
+        let mut planes = prism(4, 64.0, 64.0);
+        // Well outside the brush
+        planes.push(plane(DVec3::X, 1000.0));
+        // Only touching one edge
+        planes.push(plane(DVec3::new(1.0, 1.0, 0.0), 64.0 * 2f64.sqrt()));
+        // Only touching one corner
+        planes.push(plane(DVec3::ONE, 64.0 * 3f64.sqrt()));
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+        assert!(sides[6..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn nearly_parallel_planes() {
+        // A second top, tilted so little it only cuts a sliver off one end
+        let mut planes = prism(4, 64.0, 64.0);
+        planes.push(plane(DVec3::new(0.001, 1.0, 0.0), 64.0 - 0.05));
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 7);
+        assert!(sides[0].is_some() && sides[6].is_some());
+
+        // Close enough to count as the same plane
+        let mut planes = prism(4, 64.0, 64.0);
+        planes.push(plane(DVec3::new(0.000001, 1.0, 0.0), 64.0));
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+        assert!(sides[6].is_none());
+    }
+
+    #[test]
+    fn off_grid_planes() {
+        // The cube turned 30 degrees, so none of the points are whole numbers
+        let (sin, cos) = 30f64.to_radians().sin_cos();
+        let turn = |x: f64, y: f64| (x * cos - y * sin, x * sin + y * cos);
+        let strings: Vec<String> = CUBE
+            .iter()
+            .map(|p| {
+                let plane: vmf::Plane = p.parse().unwrap();
+                plane
+                    .points
+                    .iter()
+                    .map(|p| {
+                        let (x, y) = turn(p.x, p.y);
+                        format!("({x} {y} {})", p.z)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        let planes = hammer_planes(&strings.iter().map(String::as_str).collect::<Vec<_>>());
+        let sides = planes_to_sides(&planes);
+        assert_eq!(check_brush(&planes, &sides), 6);
+
+        let (x, y) = turn(256.0, 256.0);
+        let corner = DVec3::new(x, 0.0, y);
+        let top = sides[0].as_ref().unwrap();
+        assert!(top.iter().any(|p| p.distance(corner) < EPSILON));
+    }
+
+    fn side(plane: &str, u_axis: &str, v_axis: &str) -> vmf::Side {
+        vmf::Side {
+            id: 1,
+            plane: plane.parse().unwrap(),
+            material: "DEV/DEV_MEASUREGENERIC01".to_owned(),
+            u_axis: u_axis.parse().unwrap(),
+            v_axis: v_axis.parse().unwrap(),
+            rotation: 0.0,
+            lightmap_scale: 16,
+            smoothing_groups: 0,
+            disp_info: None,
+            rest: GenericNode::new(),
+        }
+    }
+
+    /// Checks each point's coordinates against the ones for its corner,
+    /// corners going by which way they are from the middle in X and Z
+    fn check_uvs(face: &[DVec3], uvs: &[DVec2], corners: [(f64, f64, DVec2); 4]) {
+        assert_eq!(face.len(), uvs.len());
+        for (point, uv) in face.iter().zip(uvs) {
+            let (_, _, expected) = corners
+                .iter()
+                .find(|(x, z, _)| point.x.signum() == *x && point.z.signum() == *z)
+                .unwrap();
+            assert!(
+                uv.distance(*expected) < EPSILON,
+                "{point}: {uv}, not {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn top_face_uvs() {
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        let top = sides[0].as_ref().unwrap();
+
+        // Hammer's defaults for a floor: X across, -Y down, a quarter unit a
+        // texel. Z is Hammer's Y here, so v goes against Z.
+        let vmf_side = side(CUBE[0], "[1 0 0 0] 0.25", "[0 -1 0 0] 0.25");
+        let uvs = side_uvs(top, &vmf_side, DVec2::splat(512.0));
+        check_uvs(
+            top,
+            &uvs,
+            [
+                (1.0, 1.0, DVec2::new(2.0, -2.0)),
+                (1.0, -1.0, DVec2::new(2.0, 2.0)),
+                (-1.0, 1.0, DVec2::new(-2.0, -2.0)),
+                (-1.0, -1.0, DVec2::new(-2.0, 2.0)),
+            ],
+        );
+
+        // Half a unit a texel, shifted 32 and 16 texels, on a 256x128 texture:
+        // u = (x / 0.5 + 32) / 256, v = (-z / 0.5 + 16) / 128
+        let vmf_side = side(CUBE[0], "[1 0 0 32] 0.5", "[0 -1 0 16] 0.5");
+        let uvs = side_uvs(top, &vmf_side, DVec2::new(256.0, 128.0));
+        check_uvs(
+            top,
+            &uvs,
+            [
+                (1.0, 1.0, DVec2::new(2.125, -3.875)),
+                (1.0, -1.0, DVec2::new(2.125, 4.125)),
+                (-1.0, 1.0, DVec2::new(-1.875, -3.875)),
+                (-1.0, -1.0, DVec2::new(-1.875, 4.125)),
+            ],
+        );
+
+        // A scale of 0 is taken as 0.25
+        let vmf_side = side(CUBE[0], "[1 0 0 0] 0", "[0 -1 0 0] 0");
+        let uvs = side_uvs(top, &vmf_side, DVec2::splat(512.0));
+        assert!(uvs.iter().all(|uv| uv.abs() == DVec2::splat(2.0)));
+    }
+
+    #[test]
+    fn wall_uvs() {
+        // The +X wall, Hammer's Y across and -Z down. The face is 64 high,
+        // from Y -64 to 0, so v only goes to 0.5.
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        let wall = sides[3].as_ref().unwrap();
+        let vmf_side = side(CUBE[3], "[0 1 0 0] 0.25", "[0 0 -1 0] 0.25");
+        let uvs = side_uvs(wall, &vmf_side, DVec2::splat(512.0));
+        for (point, uv) in wall.iter().zip(&uvs) {
+            assert_eq!(point.x, 256.0);
+            let expected = DVec2::new(point.z / 128.0, -point.y / 128.0);
+            assert!(uv.distance(expected) < EPSILON, "{point}: {uv}");
+            assert!(uv.y == 0.0 || uv.y == 0.5);
+        }
+    }
+
+    #[test]
+    fn uvs_are_moved_toward_zero() {
+        // Shifted 100 textures across, u goes from 98 to 102
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        let top = sides[0].as_ref().unwrap();
+        let vmf_side = side(CUBE[0], "[1 0 0 51232] 0.25", "[0 -1 0 0] 0.25");
+        let uvs = side_uvs(top, &vmf_side, DVec2::splat(512.0));
+        assert!(uvs.iter().all(|uv| (98.0..=102.1).contains(&uv.x)));
+        let origin = uvs[0].floor();
+
+        // The mesh has each triangle's own copy of the points, in the order
+        // of the fan around the first one
+        let mesh = side_to_triangles(top, &uvs);
+        let Some(VertexAttributeValues::Float32x2(mesh_uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("no texture coordinates");
+        };
+        let fan = [0, 1, 2, 0, 2, 3];
+        assert_eq!(mesh_uvs.len(), fan.len());
+        for (mesh_uv, i) in mesh_uvs.iter().zip(fan) {
+            let expected = uvs[i] - origin;
+            assert!(Vec2::from(*mesh_uv).distance(expected.as_vec2()) < 1e-5);
+        }
+        assert!((0.0..1.0).contains(&mesh_uvs[0][0]));
+    }
+
+    /// A displacement on the cube's top face, starting at Hammer's
+    /// (-256 -256 0). Every point is pushed up by its row times 10 plus its
+    /// column, and the middle one is also moved 8 along Hammer's X.
+    fn disp(power: u32) -> vmf::DispInfo {
+        let size = (1 << power) + 1;
+        let grid = |f: &dyn Fn(usize, usize) -> Vec3| -> Vec<Vec<Vec3>> {
+            (0..size)
+                .map(|row| (0..size).map(|col| f(row, col)).collect())
+                .collect()
+        };
+        vmf::DispInfo {
+            power,
+            start_position: "-256 -256 0".parse().unwrap(),
+            elevation: 0.0,
+            normals: grid(&|_, _| Vec3::Z),
+            distances: (0..size)
+                .map(|row| (0..size).map(|col| (row * 10 + col) as f32).collect())
+                .collect(),
+            offsets: grid(&|row, col| {
+                if (row, col) == (size / 2, size / 2) {
+                    Vec3::X * 8.0
+                } else {
+                    Vec3::ZERO
+                }
+            }),
+            offset_normals: Vec::new(),
+            alphas: Vec::new(),
+            triangle_tags: Vec::new(),
+            allowed_verts: Vec::new(),
+            rest: GenericNode::new(),
+        }
+    }
+
+    #[test]
+    fn disp_point_placement() {
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        let top = sides[0].as_ref().unwrap();
+
+        for power in 2..=4 {
+            let disp = disp(power);
+            let size = disp.size();
+            assert_eq!(size, (1 << power) + 1);
+            let (points, flat) = disp_points(top, &disp, planes[0].normal).unwrap();
+            assert_eq!(points.len(), size * size);
+            assert_eq!(flat.len(), size * size);
+
+            // The grid goes from the start corner to the opposite one, in
+            // even steps along the face
+            let step = 512.0 / (size - 1) as f64;
+            assert_eq!(flat[0], DVec3::new(-256.0, 0.0, -256.0));
+            assert_eq!(flat[size * size - 1], DVec3::new(256.0, 0.0, 256.0));
+            for row in 0..size {
+                for col in 0..size {
+                    let i = row * size + col;
+                    assert_eq!(flat[i].y, 0.0);
+                    if col > 0 {
+                        assert!((flat[i].distance(flat[i - 1]) - step).abs() < EPSILON);
+                    }
+                    if row > 0 {
+                        assert!((flat[i].distance(flat[i - size]) - step).abs() < EPSILON);
+                    }
+
+                    // Hammer's Z normals are our Y, its X offsets our X
+                    let mut expected = flat[i] + DVec3::Y * (row * 10 + col) as f64;
+                    if (row, col) == (size / 2, size / 2) {
+                        expected += DVec3::X * 8.0;
+                    }
+                    assert_eq!(points[i], expected);
+                }
+            }
+        }
+
+        // Raised as a whole by the elevation
+        let mut raised = disp(2);
+        raised.elevation = 16.0;
+        let (points, flat) = disp_points(top, &raised, planes[0].normal).unwrap();
+        assert_eq!(points[0], flat[0] + DVec3::Y * 16.0);
+
+        // Starting from the other corner turns the grid around
+        let mut turned = disp(2);
+        turned.start_position = "256 256 0".parse().unwrap();
+        let (_, flat) = disp_points(top, &turned, planes[0].normal).unwrap();
+        assert_eq!(flat[0], DVec3::new(256.0, 0.0, 256.0));
+
+        // Too big, rows missing, or not on a quad
+        assert!(disp_points(top, &disp(5), planes[0].normal).is_none());
+        let mut short = disp(2);
+        short.normals.pop();
+        assert!(disp_points(top, &short, planes[0].normal).is_none());
+        assert!(disp_points(&top[..3], &disp(2), planes[0].normal).is_none());
+    }
+
+    #[test]
+    fn disp_triangles() {
+        // The first row of squares, the diagonal flips from one to the next
+        let idx = disp_indices(5);
+        assert_eq!(idx.len(), 4 * 4 * 6);
+        assert_eq!(idx[..12], [0, 5, 6, 0, 6, 1, 1, 6, 2, 6, 7, 2]);
+        assert_eq!(idx[24..30], [5, 10, 6, 10, 11, 6]);
+
+        // On the flat face they all face out, and together cover it
+        let planes = hammer_planes(&CUBE);
+        let sides = planes_to_sides(&planes);
+        let top = sides[0].as_ref().unwrap();
+        for power in 2..=4 {
+            let disp = disp(power);
+            let (_, flat) = disp_points(top, &disp, planes[0].normal).unwrap();
+            let idx = disp_indices(disp.size());
+            let mut total = DVec3::ZERO;
+            for triangle in idx.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| flat[triangle[i] as usize]);
+                let area = (b - a).cross(c - a);
+                assert!(area.normalize().dot(planes[0].normal) > 0.999);
+                total += area;
+            }
+            assert!((total.length() - 2.0 * 512.0 * 512.0).abs() < EPSILON);
+        }
+    }
+}
@@ -1,135 +1,413 @@
-use std::f32::consts::PI;
+use std::{collections::HashSet, f32::consts::PI};
 
-use bevy::{prelude::*, render::view::RenderLayers};
+use bevy::{
+    math::{DVec2, DVec3},
+    prelude::*,
+    render::view::RenderLayers,
+};
 use bevy_mod_raycast::prelude::*;
 
+use chisel::fgd::EntityClass;
+
 use crate::{
-    controls::{ControlNob, OrthoRaycastSet, Selected, View3DRaycastSet},
-    geometry::{planes_to_sides, side_to_lines, side_to_triangles, StandardPlane},
-    solidcomp::{SideComponent, SolidComponent},
-    vmf2::res::{ActiveVmf, VmfFile},
+    controls::{BaseColor, ControlNob, OrthoRaycastSet, Selected, View3DRaycastSet},
+    geometry::{
+        disp_points, disp_to_lines, disp_to_triangles, planes_to_sides, side_to_lines,
+        side_to_triangles, side_uvs, StandardPlane,
+    },
+    solidcomp::{EntityComponent, GroupComponent, SideComponent, SolidComponent},
+    textures::MapTextures,
+    ui::{hide::HiddenObjects, settings::EntityClasses},
+    vmf2::{
+        res::{ActiveVmf, VmfChanged, VmfFile},
+        vmf::{self, Solid},
+    },
 };
 
 pub struct InitPlugin;
 impl Plugin for InitPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_system)
+        app.add_event::<VmfChanged>()
+            .add_systems(Startup, setup_system)
             .add_systems(Update, change_vmf);
     }
 }
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn change_vmf(
     active_vmf: Res<ActiveVmf>,
     vmfs_files: Res<Assets<VmfFile>>,
+    mut vmf_events: EventReader<AssetEvent<VmfFile>>,
+    mut changed: EventReader<VmfChanged>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut textures: ResMut<MapTextures>,
+    classes: Res<EntityClasses>,
+    hidden: Res<HiddenObjects>,
+    asset_server: Res<AssetServer>,
     mut commands: Commands,
-    solids: Query<Entity, With<SolidComponent>>,
+    spawned: Query<Entity, Or<(With<SolidComponent>, With<EntityComponent>)>>,
+    q_selected: Query<(&Selected, Option<&SideComponent>, Option<&EntityComponent>)>,
 ) {
-    if active_vmf.is_changed() {
+    // Rebuild when we switch maps, when the active one is loaded again, or
+    // when it was edited behind the scene's back
+    let mut reloaded = changed.read().count() > 0;
+    for event in vmf_events.read() {
+        if let Some(active) = &active_vmf.active {
+            reloaded |= event.is_loaded_with_dependencies(active);
+        }
+    }
+
+    if active_vmf.is_changed() || hidden.is_changed() || reloaded {
         if let Some(vmf) = active_vmf
             .active
             .as_ref()
             .and_then(|handle| vmfs_files.get(handle))
         {
+            // Side and point entity ids stay the same across a rebuild, so
+            // whatever was selected can stay selected
+            let mut selected_sides = HashSet::new();
+            let mut selected_entities = HashSet::new();
+            for (selected, side, entity) in &q_selected {
+                match (selected.0, side, entity) {
+                    (true, Some(side), _) => selected_sides.insert(side.id),
+                    (true, None, Some(entity)) => selected_entities.insert(entity.id),
+                    _ => false,
+                };
+            }
+
             println!("Removing old Solids");
-            for solid in &solids {
+            for solid in &spawned {
                 commands.entity(solid).despawn_recursive();
             }
             println!("Adding new Solids");
-            for solid in &vmf.vmf.world.solids {
-                let vmf_sides = &solid.sides;
+            let world_solids = vmf.vmf.world.solids.iter();
+            for solid in world_solids.filter(|s| hidden.shows(s.hidden) && s.visgroup_shown()) {
+                spawn_solid(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &mut textures,
+                    &asset_server,
+                    solid,
+                    WORLD_COLOR,
+                    None,
+                    vmf.vmf.top_group(solid.editor.as_ref()),
+                    &selected_sides,
+                );
+            }
+            debug!("Adding new entities");
+            for entity in vmf
+                .vmf
+                .entities
+                .iter()
+                .filter(|e| hidden.shows(e.hidden) && e.visgroup_shown())
+            {
+                let group = vmf.vmf.top_group(entity.editor.as_ref());
+                if entity.solids.is_empty() {
+                    spawn_point_entity(
+                        &mut commands,
+                        &mut meshes,
+                        &mut materials,
+                        entity,
+                        classes.0.resolve(&entity.classname).as_ref(),
+                        group,
+                        &selected_entities,
+                    );
+                } else {
+                    for solid in entity
+                        .solids
+                        .iter()
+                        .filter(|s| hidden.shows(s.hidden) && s.visgroup_shown())
+                    {
+                        spawn_solid(
+                            &mut commands,
+                            &mut meshes,
+                            &mut materials,
+                            &mut textures,
+                            &asset_server,
+                            solid,
+                            entity_color(&entity.classname),
+                            Some(entity),
+                            group,
+                            &selected_sides,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The size of most dev textures, for faces without a texture we can load
+const DEFAULT_TEXTURE_SIZE: f64 = 512.0;
+
+const WORLD_COLOR: Color = Color::rgb(1.0, 0.0, 0.0);
+
+/// Roughly the colours Hammer uses to tell brush entities apart from world
+/// geometry. Anything we don't know about gets a neutral purple.
+fn entity_color(classname: &str) -> Color {
+    if classname.starts_with("trigger_") {
+        Color::rgba(1.0, 0.5, 0.0, 0.4)
+    } else if classname == "func_detail" {
+        Color::rgb(0.0, 0.6, 1.0)
+    } else if classname.starts_with("func_") {
+        Color::rgb(0.0, 0.8, 0.4)
+    } else {
+        Color::rgb(0.8, 0.0, 0.8)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_solid(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    textures: &mut MapTextures,
+    asset_server: &AssetServer,
+    solid: &Solid,
+    color: Color,
+    owner: Option<&vmf::Entity>,
+    group: Option<u32>,
+    selected: &HashSet<u32>,
+) {
+    let vmf_sides = &solid.sides;
+
+    let planes: Vec<StandardPlane> = vmf_sides
+        .iter()
+        .map(|s| StandardPlane::new(&s.plane))
+        .collect();
+
+    let sides = planes_to_sides(&planes);
+
+    // println!("{sides:#?}");
+
+    let mut solid_entity = commands.spawn((
+        TransformBundle {
+            // global: GlobalTransform::from_scale(Vec3::splat(1.0 / 128.0)),
+            local: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
+            ..default()
+        },
+        VisibilityBundle::default(),
+        SolidComponent { id: solid.id },
+    ));
+
+    if let Some(owner) = owner {
+        solid_entity.insert(EntityComponent {
+            id: owner.id,
+            classname: owner.classname.clone(),
+        });
+    }
+    if let Some(id) = group {
+        solid_entity.insert(GroupComponent { id });
+    }
 
-                let planes: Vec<StandardPlane> = vmf_sides
-                    .iter()
-                    .map(|s| StandardPlane::new(&s.plane))
-                    .collect();
+    let alpha_mode = if color.a() < 1.0 {
+        AlphaMode::Blend
+    } else {
+        AlphaMode::Opaque
+    };
 
-                let sides = planes_to_sides(&planes);
+    solid_entity.with_children(|child_builder| {
+        // planes_to_sides gives us the polygons in the same order as the planes
+        for (side, vmf_side) in sides.into_iter().zip(vmf_sides) {
+            let Some(side) = side else {
+                warn!("Side {} of solid {} has no face", vmf_side.id, solid.id);
+                continue;
+            };
+            let avg = (side.iter().sum::<DVec3>() / side.len() as f64).as_vec3();
 
-                // println!("{sides:#?}");
+            // Faces with a texture we can find show it, tinted for brush
+            // entities that are see-through, the rest are flat colours
+            let texture = textures.get(asset_server, &vmf_side.material);
+            let (face_color, texture_size, image, alpha_mode) = match texture {
+                Some(texture) => (
+                    Color::WHITE.with_a(color.a()),
+                    texture.size,
+                    Some(texture.image),
+                    if color.a() < 1.0 {
+                        AlphaMode::Blend
+                    } else {
+                        texture.alpha_mode
+                    },
+                ),
+                None => (color, DVec2::splat(DEFAULT_TEXTURE_SIZE), None, alpha_mode),
+            };
+
+            // Displacements replace the flat face, the texture is still
+            // mapped from where their points would be on it
+            let disp = vmf_side.disp_info.as_ref().and_then(|disp| {
+                let normal = StandardPlane::new(&vmf_side.plane).normal;
+                let points = disp_points(&side, disp, normal);
+                if points.is_none() {
+                    warn!("Displacement on side {} doesn't fit it", vmf_side.id);
+                }
+                Some((points?, disp.size()))
+            });
+            let (mesh, linemesh) = match disp {
+                Some(((points, flat), size)) => {
+                    let uvs = side_uvs(&flat, vmf_side, texture_size);
+                    (
+                        disp_to_triangles(&points, &uvs, size),
+                        disp_to_lines(&points, size),
+                    )
+                }
+                None => {
+                    let uvs = side_uvs(&side, vmf_side, texture_size);
+                    (side_to_triangles(&side, &uvs), side_to_lines(&side))
+                }
+            };
+
+            child_builder
+                .spawn((
+                    Selected(selected.contains(&vmf_side.id)),
+                    SideComponent { id: vmf_side.id },
+                    TransformBundle::default(),
+                    VisibilityBundle::default(),
+                ))
+                .with_children(|child_builder| {
+                    child_builder.spawn((
+                        PbrBundle {
+                            // transform: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
+                            mesh: meshes.add(mesh),
+                            material: materials.add(StandardMaterial {
+                                base_color: face_color,
+                                base_color_texture: image,
+                                double_sided: true,
+                                // cull_mode: None,
+                                cull_mode: None,
+                                perceptual_roughness: 1.0,
+                                reflectance: 0.0,
+                                alpha_mode,
+                                ..default()
+                            }),
+                            ..Default::default()
+                        },
+                        BaseColor(face_color),
+                        RenderLayers::layer(0),
+                        NoBackfaceCulling,
+                        RaycastMesh::<View3DRaycastSet>::default(),
+                    ));
 
-                commands
-                    .spawn((
-                        TransformBundle {
-                            // global: GlobalTransform::from_scale(Vec3::splat(1.0 / 128.0)),
-                            local: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
+                    child_builder.spawn((
+                        PbrBundle {
+                            // transform: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
+                            mesh: meshes.add(linemesh),
+                            material: materials.add(StandardMaterial {
+                                base_color: color.with_a(1.0),
+                                unlit: true,
+                                ..default()
+                            }),
+                            ..Default::default()
+                        },
+                        RenderLayers::layer(1),
+                    ));
+
+                    child_builder.spawn((
+                        PbrBundle {
+                            transform: Transform::from_translation(avg),
+                            mesh: meshes.add(Cuboid {
+                                half_size: Vec3::splat(8.0),
+                            }),
+                            material: materials.add(StandardMaterial {
+                                base_color: Color::CYAN,
+                                unlit: true,
+                                ..default()
+                            }),
                             ..default()
                         },
-                        VisibilityBundle::default(),
-                        SolidComponent { id: solid.id },
-                    ))
-                    .with_children(|child_builder| {
-                        for side in sides {
-                            let avg = side.iter().skip(1).sum::<Vec3>() / (side.len() - 1) as f32;
-
-                            let mesh = side_to_triangles(side.clone());
-
-                            child_builder
-                                .spawn((
-                                    Selected(false),
-                                    SideComponent { id: 5 },
-                                    TransformBundle::default(),
-                                    VisibilityBundle::default(),
-                                ))
-                                .with_children(|child_builder| {
-                                    child_builder.spawn((
-                                        PbrBundle {
-                                            // transform: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
-                                            mesh: meshes.add(mesh),
-                                            material: materials.add(StandardMaterial {
-                                                base_color: Color::rgb(1.0, 0.0, 0.0),
-                                                double_sided: true,
-                                                // cull_mode: None,
-                                                cull_mode: None,
-                                                perceptual_roughness: 1.0,
-                                                reflectance: 0.0,
-                                                ..default()
-                                            }),
-                                            ..Default::default()
-                                        },
-                                        RenderLayers::layer(0),
-                                        NoBackfaceCulling,
-                                        RaycastMesh::<View3DRaycastSet>::default(),
-                                    ));
-
-                                    let linemesh = side_to_lines(side);
-                                    child_builder.spawn((
-                                        PbrBundle {
-                                            // transform: Transform::from_scale(Vec3::splat(1.0 / 128.0)),
-                                            mesh: meshes.add(linemesh),
-                                            material: materials.add(StandardMaterial {
-                                                base_color: Color::rgb(1.0, 0.0, 0.0),
-                                                unlit: true,
-                                                ..default()
-                                            }),
-                                            ..Default::default()
-                                        },
-                                        RenderLayers::layer(1),
-                                    ));
-
-                                    child_builder.spawn((
-                                        PbrBundle {
-                                            transform: Transform::from_translation(avg),
-                                            mesh: meshes.add(Cuboid {
-                                                half_size: Vec3::splat(8.0),
-                                            }),
-                                            material: materials.add(StandardMaterial {
-                                                base_color: Color::CYAN,
-                                                unlit: true,
-                                                ..default()
-                                            }),
-                                            ..default()
-                                        },
-                                        ControlNob,
-                                        RaycastMesh::<OrthoRaycastSet>::default(),
-                                        RenderLayers::layer(1),
-                                    ));
-                                });
-                        }
-                    });
-            }
+                        ControlNob,
+                        RaycastMesh::<OrthoRaycastSet>::default(),
+                        RenderLayers::layer(1),
+                    ));
+                });
         }
+    });
+}
+
+const POINT_ENTITY_COLOR: Color = Color::rgb(0.0, 1.0, 0.0);
+
+/// Point entities don't have any geometry of their own (until we can load
+/// models), so draw them the way Hammer does: a box at their origin, the
+/// size and colour their FGD class gives them, or 16 units of green.
+fn spawn_point_entity(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    entity: &vmf::Entity,
+    class: Option<&EntityClass>,
+    group: Option<u32>,
+    selected: &HashSet<u32>,
+) {
+    let Some(origin) = &entity.origin else {
+        warn!(
+            "Point entity {} ({}) has no origin, skipping",
+            entity.id, entity.classname
+        );
+        return;
+    };
+
+    // FGD sizes are in Hammer's Z up coordinates
+    let (min, max) = class
+        .and_then(|c| c.size)
+        .map_or((Vec3::splat(-8.0), Vec3::splat(8.0)), |(min, max)| {
+            (min.xzy().as_vec3(), max.xzy().as_vec3())
+        });
+    let color = class
+        .and_then(|c| c.color)
+        .map_or(POINT_ENTITY_COLOR, |[r, g, b]| Color::rgb_u8(r, g, b));
+    let cube = meshes.add(Cuboid {
+        half_size: (max - min) / 2.0,
+    });
+    let cube_transform = Transform::from_translation((min + max) / 2.0);
+
+    let mut root = commands.spawn((
+        TransformBundle {
+            local: Transform::from_translation(origin.new_vec3() / 128.0)
+                .with_scale(Vec3::splat(1.0 / 128.0)),
+            ..default()
+        },
+        VisibilityBundle::default(),
+        EntityComponent {
+            id: entity.id,
+            classname: entity.classname.clone(),
+        },
+        Selected(selected.contains(&entity.id)),
+    ));
+    if let Some(id) = group {
+        root.insert(GroupComponent { id });
     }
+    root.with_children(|child_builder| {
+        child_builder.spawn((
+            PbrBundle {
+                transform: cube_transform,
+                mesh: cube.clone(),
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    perceptual_roughness: 1.0,
+                    reflectance: 0.0,
+                    ..default()
+                }),
+                ..default()
+            },
+            BaseColor(color),
+            RenderLayers::layer(0),
+            RaycastMesh::<View3DRaycastSet>::default(),
+        ));
+
+        child_builder.spawn((
+            PbrBundle {
+                transform: cube_transform,
+                mesh: cube,
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    unlit: true,
+                    ..default()
+                }),
+                ..default()
+            },
+            RenderLayers::layer(1),
+        ));
+    });
 }
 
 pub fn setup_system(
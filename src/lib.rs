@@ -0,0 +1,12 @@
+//! The parts of chisel that don't need a window: reading and writing VMFs
+//! and the other game files that go with them. The editor itself lives in
+//! `main.rs`.
+
+pub mod config;
+pub mod fgd;
+pub mod gameinfo;
+pub mod materials;
+pub mod vfs;
+pub mod vmf2;
+pub mod vpk;
+pub mod vtf;
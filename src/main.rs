@@ -1,20 +1,29 @@
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
+use chisel::vmf2;
 use controls::ControlPlugin;
 use init::InitPlugin;
+use sync::SyncPlugin;
+use textures::TexturePlugin;
 use ui::ChiselUIPlugin;
 use views::split::ChiselCamerasPlugin;
+use watch::WatchPlugin;
 
 mod controls;
 mod geometry;
 mod init;
+mod problems;
 mod solidcomp;
+mod sync;
+mod textures;
 mod ui;
 mod views;
-mod vmf2;
+mod watch;
 
 fn main() {
     App::new()
+        // Registers the game:// asset source, which has to come first
+        .add_plugins(TexturePlugin)
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Chisel VMF Viewer".to_string(),
@@ -29,5 +38,7 @@ fn main() {
         .add_plugins(ChiselCamerasPlugin)
         .add_plugins(InitPlugin)
         .add_plugins(ControlPlugin)
+        .add_plugins(SyncPlugin)
+        .add_plugins(WatchPlugin)
         .run();
 }
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+
+use crate::{
+    vfs::FileSystem,
+    vmf2::{
+        error::VmfError,
+        generic::{Entry, GenericNode},
+    },
+};
+
+/*
+A VMT is a single KeyValues block named after its shader:
+
+    "LightmappedGeneric"
+    {
+        "$basetexture" "dev/dev_measuregeneric01"
+        "%keywords" "dev"
+    }
+
+Keys are case insensitive. `patch` materials take another VMT and change
+some of its parameters:
+
+    "patch"
+    {
+        "include" "materials/dev/dev_measuregeneric01.vmt"
+        "replace" { "$basetexture" "dev/dev_measuregeneric01b" }
+        "insert" { "$surfaceprop" "metal" }
+    }
+*/
+
+/// How many `patch` materials can include each other before we give up
+const MAX_PATCH_DEPTH: usize = 8;
+
+/// What we need to know about a material to draw it and to check a map
+#[derive(Clone, Debug)]
+pub struct MaterialInfo {
+    /// Normalised, see `normalize_name`
+    pub name: String,
+    pub shader: String,
+    pub base_texture: Option<String>,
+    pub bump_map: Option<String>,
+    pub translucent: bool,
+    pub alpha_test: bool,
+    pub surface_prop: Option<String>,
+    /// `%compilenodraw`, `%compileclip`, ... that are turned on, lowercase
+    pub compile_flags: Vec<String>,
+    /// Every top level parameter, with lowercase keys
+    pub params: HashMap<String, String>,
+}
+
+impl MaterialInfo {
+    /// Parses the text of a VMT. `load` is given the path of another VMT,
+    /// relative to the game directory, for `patch` materials to include.
+    pub fn parse(
+        name: &str,
+        text: &str,
+        load: &mut dyn FnMut(&str) -> Result<String, VmfError>,
+    ) -> Result<Self, VmfError> {
+        let (shader, params) = parse_params(text, load, 0)?;
+
+        let flag = |key: &str| params.get(key).is_some_and(|v| is_true(v));
+        let texture = |key: &str| params.get(key).map(|v| normalize_name(v));
+
+        let mut compile_flags: Vec<String> = params
+            .iter()
+            .filter(|(k, v)| k.starts_with("%compile") && is_true(v))
+            .map(|(k, _)| k.clone())
+            .collect();
+        compile_flags.sort();
+
+        Ok(Self {
+            name: normalize_name(name),
+            base_texture: texture("$basetexture"),
+            bump_map: texture("$bumpmap"),
+            translucent: flag("$translucent"),
+            alpha_test: flag("$alphatest"),
+            surface_prop: params.get("$surfaceprop").cloned(),
+            compile_flags,
+            shader,
+            params,
+        })
+    }
+
+    pub fn has_compile_flag(&self, flag: &str) -> bool {
+        self.compile_flags
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(flag))
+    }
+}
+
+/// The shader name and the parameters of a VMT, with any `patch` applied
+fn parse_params(
+    text: &str,
+    load: &mut dyn FnMut(&str) -> Result<String, VmfError>,
+    depth: usize,
+) -> Result<(String, HashMap<String, String>), VmfError> {
+    let mut root = GenericNode::parse(text)?;
+    root.resolve_includes(|path| GenericNode::parse(&load(path)?))?;
+    let Some((shader, node)) = root.entries.into_iter().find_map(|e| match e {
+        Entry::Child(name, node) => Some((name, node)),
+        _ => None,
+    }) else {
+        return Err(VmfError::MissingBlock {
+            position: root.position,
+            name: "shader".to_owned(),
+        });
+    };
+
+    if !shader.eq_ignore_ascii_case("patch") {
+        return Ok((shader.to_ascii_lowercase(), key_values(&node)));
+    }
+
+    let (include, include_position) = node
+        .entries
+        .iter()
+        .find_map(|e| match e {
+            Entry::KeyValue(k, v, position) if k.eq_ignore_ascii_case("include") => {
+                Some((v, *position))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| VmfError::MissingKey {
+            position: node.position,
+            key: "include".to_owned(),
+        })?;
+    if depth >= MAX_PATCH_DEPTH {
+        return Err(VmfError::InvalidValue {
+            position: include_position,
+            key: "include".to_owned(),
+            value: include.clone(),
+        });
+    }
+
+    let (shader, mut params) = parse_params(&load(include)?, load, depth + 1)?;
+    for entry in &node.entries {
+        match entry {
+            Entry::Child(name, block) if name.eq_ignore_ascii_case("replace") => {
+                params.extend(key_values(block));
+            }
+            Entry::Child(name, block) if name.eq_ignore_ascii_case("insert") => {
+                for (k, v) in key_values(block) {
+                    params.entry(k).or_insert(v);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((shader, params))
+}
+
+/// The keyvalues directly in a block, keys lowercased, the first one wins
+fn key_values(node: &GenericNode) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    for entry in &node.entries {
+        if let Entry::KeyValue(k, v, _) = entry {
+            params
+                .entry(k.to_ascii_lowercase())
+                .or_insert_with(|| v.clone());
+        }
+    }
+    params
+}
+
+/// Source reads flags with atoi, so "1", "1.0" and " 1" are all on
+fn is_true(value: &str) -> bool {
+    value.trim().parse::<f32>().is_ok_and(|v| v != 0.0)
+}
+
+/// Material and texture names the way the engine compares them: lowercase,
+/// forward slashes, without `materials/` in front or an extension
+pub fn normalize_name(name: &str) -> String {
+    let name = name.trim().replace('\\', "/").to_ascii_lowercase();
+    // "dev//foo" and "/dev/foo" are both just "dev/foo"
+    let name = name
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    let name = name.strip_prefix("materials/").unwrap_or(&name);
+    let name = name
+        .strip_suffix(".vmt")
+        .or_else(|| name.strip_suffix(".vtf"))
+        .unwrap_or(name);
+    name.to_owned()
+}
+
+/// Finds and reads materials from a game's files, keeping what it has read.
+#[derive(Debug)]
+pub struct Materials {
+    fs: FileSystem,
+    cache: HashMap<String, Option<MaterialInfo>>,
+}
+
+impl Materials {
+    pub fn new(fs: FileSystem) -> Self {
+        Self {
+            fs,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Where materials and their textures are read from
+    pub fn fs(&self) -> &FileSystem {
+        &self.fs
+    }
+
+    /// The path of the VMT for a material, if it exists
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let path = format!("materials/{}.vmt", normalize_name(name));
+        self.fs.contains(&path).then_some(path)
+    }
+
+    /// Reads a material without looking at (or filling) the cache
+    pub fn load(&self, name: &str) -> Result<MaterialInfo, VmfError> {
+        let read = |name: &str| -> Result<String, VmfError> {
+            let path = format!("materials/{}.vmt", normalize_name(name));
+            Ok(self.fs.read_to_string(&path)?)
+        };
+
+        let text = read(name)?;
+        MaterialInfo::parse(name, &text, &mut |include| read(include))
+    }
+
+    /// A material, read on first use. Materials that can't be read are
+    /// remembered as missing.
+    pub fn get(&mut self, name: &str) -> Option<&MaterialInfo> {
+        let key = normalize_name(name);
+        if !self.cache.contains_key(&key) {
+            let material = self.load(&key).ok();
+            self.cache.insert(key.clone(), material);
+        }
+        self.cache[&key].as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vpk::tests::temp_dir;
+
+    /// Parses `text`, with `files` to include by path
+    fn parse(text: &str, files: &[(&str, &str)]) -> Result<MaterialInfo, VmfError> {
+        MaterialInfo::parse("materials\\Dev\\Test.vmt", text, &mut |path| {
+            files
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
+        })
+    }
+
+    const BRICK: &str = r#"
+        "LightmappedGeneric"
+        {
+            "$basetexture" "Brick\Brick01"
+            "$SurfaceProp" "brick"
+            "$translucent" "0"
+            "%compilenodraw" "0"
+            "%compileClip" "1"
+        }
+    "#;
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(normalize_name("DEV/Dev_Floor"), "dev/dev_floor");
+        assert_eq!(normalize_name("materials\\dev\\floor.vmt"), "dev/floor");
+        assert_eq!(normalize_name(" /dev//floor.VTF "), "dev/floor");
+        assert_eq!(normalize_name("dev/floor.png"), "dev/floor.png");
+        assert_eq!(normalize_name("materialsdev/floor"), "materialsdev/floor");
+    }
+
+    #[test]
+    fn plain_material() {
+        let material = parse(BRICK, &[]).unwrap();
+        assert_eq!(material.name, "dev/test");
+        assert_eq!(material.shader, "lightmappedgeneric");
+        assert_eq!(material.base_texture.as_deref(), Some("brick/brick01"));
+        assert_eq!(material.surface_prop.as_deref(), Some("brick"));
+        assert_eq!(material.bump_map, None);
+        assert!(!material.translucent && !material.alpha_test);
+        assert_eq!(material.compile_flags, ["%compileclip"]);
+        assert!(material.has_compile_flag("%CompileClip"));
+        assert!(!material.has_compile_flag("%compilenodraw"));
+    }
+
+    #[test]
+    fn flags() {
+        let flags = |value: &str| {
+            let text = format!(
+                "\"UnlitGeneric\" {{ \"$translucent\" \"{value}\" \"$alphatest\" \"{value}\" }}"
+            );
+            let material = parse(&text, &[]).unwrap();
+            assert_eq!(material.translucent, material.alpha_test);
+            material.translucent
+        };
+        assert!(flags("1"));
+        assert!(flags(" 1"));
+        assert!(flags("1.0"));
+        assert!(!flags("0"));
+        assert!(!flags("0.0"));
+        assert!(!flags(""));
+        assert!(!flags("yes"));
+    }
+
+    #[test]
+    fn patch() {
+        let text = r#"
+            "Patch"
+            {
+                "include" "materials/brick/brick01.vmt"
+                "Replace" { "$basetexture" "brick/brick02" "$translucent" "1" }
+                "insert" { "$surfaceprop" "metal" "$alphatest" "1" }
+            }
+        "#;
+        let material = parse(text, &[("materials/brick/brick01.vmt", BRICK)]).unwrap();
+        assert_eq!(material.name, "dev/test");
+        assert_eq!(material.shader, "lightmappedgeneric");
+        assert_eq!(material.base_texture.as_deref(), Some("brick/brick02"));
+        assert!(material.translucent);
+        // Insert doesn't replace what the included material already has
+        assert_eq!(material.surface_prop.as_deref(), Some("brick"));
+        assert!(material.alpha_test);
+    }
+
+    #[test]
+    fn patch_of_a_patch() {
+        let patch = |include: &str| {
+            format!("patch {{ include \"{include}\" replace {{ $bumpmap \"{include}\" }} }}")
+        };
+        let first = patch("b.vmt");
+        let second = patch("c.vmt");
+        let files = [("b.vmt", second.as_str()), ("c.vmt", BRICK)];
+        let material = parse(&first, &files).unwrap();
+        assert_eq!(material.bump_map.as_deref(), Some("b"));
+        assert_eq!(material.base_texture.as_deref(), Some("brick/brick01"));
+
+        assert!(parse("patch { replace { $basetexture a } }", &[]).is_err());
+        assert!(parse(&patch("missing.vmt"), &[]).is_err());
+        // Including itself would never end
+        let looped = patch("a.vmt");
+        assert!(parse(&looped, &[("a.vmt", &looped)]).is_err());
+    }
+
+    #[test]
+    fn misses_are_cached() {
+        let dir = temp_dir("materials");
+        let write = |name: &str| {
+            let path = dir.join("materials/dev").join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, BRICK).unwrap();
+        };
+        write("found.vmt");
+        let mut materials = Materials::new(FileSystem::from_dir(&dir));
+
+        assert_eq!(
+            materials.resolve("DEV\\Found").as_deref(),
+            Some("materials/dev/found.vmt")
+        );
+        assert_eq!(materials.get("dev/found").unwrap().name, "dev/found");
+        assert!(materials.get("dev/missing").is_none());
+
+        // Only a new Materials sees files added later
+        write("missing.vmt");
+        assert!(materials.get("Dev/Missing.vmt").is_none());
+        assert!(materials.load("dev/missing").is_ok());
+        let mut materials = Materials::new(FileSystem::from_dir(&dir));
+        assert!(materials.get("dev/missing").is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,573 @@
+use std::{collections::HashMap, fmt::Display};
+
+use bevy::math::DVec3;
+use chisel::fgd::EntityClassDb;
+
+use crate::{
+    geometry::{planes_to_sides, StandardPlane},
+    solidcomp::MapObject,
+    vmf2::vmf::{Entity, Output, Solid, Vmf},
+};
+
+/// Vertices further than this from a whole unit are off the grid
+const GRID_EPSILON: f64 = 0.01;
+
+/// Area vectors of a closed hull add up to nothing, this is how far off they
+/// can be (relative to the total area) before we call the hull open
+const CLOSED_EPSILON: f64 = 0.000001;
+
+/// Twice as far out as Hammer's grid goes. A vertex beyond this belongs to a
+/// face that nothing cut down, so the hull is open on that side.
+const MAP_EXTENT: f64 = 32768.0;
+
+#[derive(Debug, PartialEq)]
+pub enum ProblemKind {
+    TooFewPlanes(usize),
+    DuplicatePlane,
+    /// A plane from three points in a line, or on top of each other
+    DegeneratePlane,
+    /// A plane that doesn't end up with a face, so the solid isn't the
+    /// convex shape its planes describe
+    NotConvex,
+    OpenHull,
+    MissingMaterial,
+    DuplicateSolidId,
+    DuplicateSideId,
+    NoClassname,
+    OffGrid(DVec3),
+    /// A `connections` entry that couldn't be read, as it is in the file
+    MalformedOutput(String),
+    /// An output the entity's class doesn't have
+    UnknownOutput(String),
+    NoTarget,
+    /// An output targets a name no entity has
+    UnknownTarget(String),
+    /// None of the entities an output targets have the input it calls
+    UnknownInput {
+        target: String,
+        input: String,
+    },
+}
+
+impl Display for ProblemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProblemKind::TooFewPlanes(n) => write!(f, "Solid has only {n} planes"),
+            ProblemKind::DuplicatePlane => f.write_str("Side has the same plane as another side"),
+            ProblemKind::DegeneratePlane => f.write_str("Side's plane points are in a line"),
+            ProblemKind::NotConvex => f.write_str("Side doesn't touch the solid (not convex)"),
+            ProblemKind::OpenHull => f.write_str("Solid isn't closed"),
+            ProblemKind::MissingMaterial => f.write_str("Side has no material"),
+            ProblemKind::DuplicateSolidId => f.write_str("Solid id is used more than once"),
+            ProblemKind::DuplicateSideId => f.write_str("Side id is used more than once"),
+            ProblemKind::NoClassname => f.write_str("Entity has no classname"),
+            // Back to Hammer's axes for the user
+            ProblemKind::OffGrid(v) => {
+                write!(f, "Vertex ({} {} {}) is off the grid", v.x, v.z, v.y)
+            }
+            ProblemKind::MalformedOutput(value) => {
+                let value = value.replace('\x1b', ",");
+                write!(f, "Output \"{value}\" can't be read")
+            }
+            ProblemKind::UnknownOutput(output) => {
+                write!(f, "Entity's class has no output \"{output}\"")
+            }
+            ProblemKind::NoTarget => f.write_str("Output has no target"),
+            ProblemKind::UnknownTarget(target) => {
+                write!(
+                    f,
+                    "Output targets \"{target}\", but no entity has that name"
+                )
+            }
+            ProblemKind::UnknownInput { target, input } => {
+                write!(f, "\"{target}\" has no input \"{input}\"")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub object: MapObject,
+    pub kind: ProblemKind,
+    /// Where the problem is, in our Y up map units, with a rough size, so a
+    /// view can be moved to look at it
+    pub focus: Option<(DVec3, f64)>,
+}
+
+/// Everything we can find wrong with a map, like Hammer's "Check for problems".
+/// Entity outputs are checked against `classes`, as far as they go.
+pub fn check_vmf(vmf: &Vmf, classes: &EntityClassDb) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut solid_ids: HashMap<u32, usize> = HashMap::new();
+    let mut side_ids: HashMap<u32, usize> = HashMap::new();
+
+    let owned_solids = std::iter::once(&vmf.world.solids)
+        .chain(vmf.entities.iter().map(|e| &e.solids))
+        .flatten();
+    for solid in owned_solids {
+        *solid_ids.entry(solid.id).or_default() += 1;
+        for side in &solid.sides {
+            *side_ids.entry(side.id).or_default() += 1;
+        }
+        check_solid(solid, &mut problems);
+    }
+
+    let owned_solids = std::iter::once(&vmf.world.solids)
+        .chain(vmf.entities.iter().map(|e| &e.solids))
+        .flatten();
+    for solid in owned_solids {
+        let focus = solid_focus(solid);
+        if solid_ids[&solid.id] > 1 {
+            problems.push(Problem {
+                object: MapObject::Solid(solid.id),
+                kind: ProblemKind::DuplicateSolidId,
+                focus,
+            });
+        }
+        for side in &solid.sides {
+            if side_ids[&side.id] > 1 {
+                problems.push(Problem {
+                    object: MapObject::Side(side.id),
+                    kind: ProblemKind::DuplicateSideId,
+                    focus,
+                });
+            }
+        }
+    }
+
+    for entity in &vmf.entities {
+        let focus = match &entity.origin {
+            Some(origin) => Some((origin.new_dvec3(), 16.0)),
+            None => entity.solids.first().and_then(solid_focus),
+        };
+        let mut kinds = Vec::new();
+        if entity.classname.trim().is_empty() {
+            kinds.push(ProblemKind::NoClassname);
+        }
+        for output in entity.connections.iter().flatten() {
+            kinds.extend(check_output(vmf, classes, entity, output));
+        }
+        problems.extend(kinds.into_iter().map(|kind| Problem {
+            object: MapObject::Entity(entity.id),
+            kind,
+            focus,
+        }));
+    }
+
+    problems
+}
+
+/// What's wrong with one of `entity`'s outputs. Classes that aren't in the
+/// FGDs, and targets like `!activator` that are only known when the game is
+/// running, are taken to be fine.
+pub fn check_output(
+    vmf: &Vmf,
+    classes: &EntityClassDb,
+    entity: &Entity,
+    output: &Output,
+) -> Vec<ProblemKind> {
+    let mut problems = Vec::new();
+
+    if output.is_malformed() {
+        let value = output.original().unwrap_or_default();
+        problems.push(ProblemKind::MalformedOutput(value.to_owned()));
+        return problems;
+    }
+
+    if let Some(class) = classes.resolve(&entity.classname) {
+        if class.output(&output.output).is_none() {
+            problems.push(ProblemKind::UnknownOutput(output.output.clone()));
+        }
+    }
+
+    let target = output.target.trim();
+    if target.is_empty() {
+        problems.push(ProblemKind::NoTarget);
+        return problems;
+    }
+    if target.starts_with('!') {
+        return problems;
+    }
+
+    let targets = targeted_by(vmf, target);
+    if targets.is_empty() {
+        problems.push(ProblemKind::UnknownTarget(target.to_owned()));
+        return problems;
+    }
+    let known: Vec<_> = targets
+        .iter()
+        .filter_map(|e| classes.resolve(&e.classname))
+        .collect();
+    if !known.is_empty() && !known.iter().any(|c| c.input(&output.input).is_some()) {
+        problems.push(ProblemKind::UnknownInput {
+            target: target.to_owned(),
+            input: output.input.clone(),
+        });
+    }
+
+    problems
+}
+
+/// The entities an output aimed at `target` reaches
+pub fn targeted_by<'a>(vmf: &'a Vmf, target: &str) -> Vec<&'a Entity> {
+    vmf.entities
+        .iter()
+        .filter(|e| is_target(target, e))
+        .collect()
+}
+
+/// Whether `entity` is reached by an output aimed at `target`: by name, by
+/// class, or by the start of its name for a `*` wildcard. Names are case
+/// insensitive, like in the game.
+pub fn is_target(target: &str, entity: &Entity) -> bool {
+    let target = target.trim().to_ascii_lowercase();
+    let matches = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        match target.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == target,
+        }
+    };
+    entity.key("targetname").is_some_and(|n| matches(&n)) || matches(&entity.classname)
+}
+
+fn check_solid(solid: &Solid, problems: &mut Vec<Problem>) {
+    let focus = solid_focus(solid);
+    let problem = |object, kind| Problem {
+        object,
+        kind,
+        focus,
+    };
+
+    for side in &solid.sides {
+        if side.material.trim().is_empty() {
+            problems.push(problem(
+                MapObject::Side(side.id),
+                ProblemKind::MissingMaterial,
+            ));
+        }
+    }
+
+    if solid.sides.len() < 4 {
+        problems.push(problem(
+            MapObject::Solid(solid.id),
+            ProblemKind::TooFewPlanes(solid.sides.len()),
+        ));
+        return;
+    }
+
+    let planes: Vec<StandardPlane> = solid
+        .sides
+        .iter()
+        .map(|s| StandardPlane::new(&s.plane))
+        .collect();
+    let polygons = planes_to_sides(&planes);
+
+    for (i, (side, polygon)) in solid.sides.iter().zip(&polygons).enumerate() {
+        if polygon.is_some() {
+            continue;
+        }
+        let duplicate = planes[..i].iter().any(|p| p.is_same_as(&planes[i]));
+        let kind = if planes[i].is_degenerate() {
+            ProblemKind::DegeneratePlane
+        } else if duplicate {
+            ProblemKind::DuplicatePlane
+        } else {
+            ProblemKind::NotConvex
+        };
+        problems.push(problem(MapObject::Side(side.id), kind));
+    }
+
+    // Each face's area along its normal, for a closed hull these cancel out
+    let mut total = DVec3::ZERO;
+    let mut area = 0.0;
+    for polygon in polygons.iter().flatten() {
+        let face = polygon_area(polygon);
+        total += face;
+        area += face.length();
+    }
+    let unbounded = polygons
+        .iter()
+        .flatten()
+        .flatten()
+        .any(|v| v.abs().max_element() > MAP_EXTENT);
+    if unbounded || area == 0.0 || total.length() > area * CLOSED_EPSILON {
+        problems.push(problem(MapObject::Solid(solid.id), ProblemKind::OpenHull));
+        // Vertices of an open hull are out at the end of the world, no point
+        // in checking them
+        return;
+    }
+
+    let mut off_grid: Vec<DVec3> = Vec::new();
+    for vertex in polygons.iter().flatten().flatten() {
+        let is_off = (*vertex - vertex.round()).abs().max_element() > GRID_EPSILON;
+        if is_off && !off_grid.iter().any(|v| v.distance(*vertex) < GRID_EPSILON) {
+            off_grid.push(*vertex);
+        }
+    }
+    for vertex in off_grid {
+        problems.push(Problem {
+            object: MapObject::Solid(solid.id),
+            kind: ProblemKind::OffGrid(vertex),
+            focus: Some((vertex, 16.0)),
+        });
+    }
+}
+
+/// Twice the area of a polygon, pointing along its normal
+fn polygon_area(polygon: &[DVec3]) -> DVec3 {
+    let mut area = DVec3::ZERO;
+    for i in 1..polygon.len().saturating_sub(1) {
+        area += (polygon[i] - polygon[0]).cross(polygon[i + 1] - polygon[0]);
+    }
+    area
+}
+
+/// The middle of a solid and how big it is, going by its plane points. These
+/// are always near the solid, even when the solid itself is broken.
+fn solid_focus(solid: &Solid) -> Option<(DVec3, f64)> {
+    let points = solid.sides.iter().flat_map(|s| &s.plane.points);
+    let (min, max) = points.fold(None, |bounds, p| {
+        let p = p.new_dvec3();
+        match bounds {
+            None => Some((p, p)),
+            Some((min, max)) => Some((p.min(min), p.max(max))),
+        }
+    })?;
+    Some(((min + max) / 2.0, (max - min).length().max(16.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chisel::vmf2::generic::GenericNode;
+
+    use super::*;
+
+    const CUBE: [&str; 6] = [
+        "(-256 256 0) (256 256 0) (256 -256 0)",
+        "(-256 -256 -64) (256 -256 -64) (256 256 -64)",
+        "(-256 256 0) (-256 -256 0) (-256 -256 -64)",
+        "(256 256 -64) (256 -256 -64) (256 -256 0)",
+        "(256 256 0) (-256 256 0) (-256 256 -64)",
+        "(256 -256 -64) (-256 -256 -64) (-256 -256 0)",
+    ];
+
+    const FGD: &str = r#"
+        @PointClass = logic_relay
+        [
+            targetname(target_source)
+            input Trigger(void)
+            output OnTrigger(void)
+        ]
+        @SolidClass = func_door
+        [
+            targetname(target_source)
+            input Open(void)
+        ]
+    "#;
+
+    /// A solid with sides numbered from `id + 1`
+    fn solid(id: u32, planes: &[&str]) -> String {
+        let sides: String = planes
+            .iter()
+            .zip(id + 1..)
+            .map(|(plane, id)| {
+                format!(
+                    "side\n{{\n\"id\" \"{id}\"\n\"plane\" \"{plane}\"\n\
+                     \"material\" \"TOOLS/TOOLSNODRAW\"\n\
+                     \"uaxis\" \"[1 0 0 0] 0.25\"\n\"vaxis\" \"[0 -1 0 0] 0.25\"\n\
+                     \"rotation\" \"0\"\n\"lightmapscale\" \"16\"\n\"smoothing_groups\" \"0\"\n}}\n"
+                )
+            })
+            .collect();
+        format!("solid\n{{\n\"id\" \"{id}\"\n{sides}}}\n")
+    }
+
+    fn entity(id: u32, keys: &str, connections: &[(&str, &str)]) -> String {
+        let connections: String = connections
+            .iter()
+            .map(|(output, value)| format!("\"{output}\" \"{value}\"\n"))
+            .collect();
+        format!("entity\n{{\n\"id\" \"{id}\"\n{keys}\nconnections\n{{\n{connections}}}\n}}\n")
+    }
+
+    fn check(world: &str, entities: &str) -> Vec<(MapObject, ProblemKind)> {
+        let text = format!(
+            "versioninfo\n{{\n\"editorversion\" \"400\"\n\"editorbuild\" \"8864\"\n\
+             \"mapversion\" \"1\"\n\"formatversion\" \"100\"\n\"prefab\" \"0\"\n}}\n\
+             world\n{{\n\"id\" \"1\"\n\"classname\" \"worldspawn\"\n{world}}}\n{entities}"
+        );
+        let vmf = Vmf::parse(GenericNode::parse(&text).unwrap()).unwrap();
+        let mut classes = EntityClassDb::new();
+        classes.parse_str(FGD).unwrap();
+        check_vmf(&vmf, &classes)
+            .into_iter()
+            .map(|p| (p.object, p.kind))
+            .collect()
+    }
+
+    fn with(extra: &str) -> Vec<&str> {
+        let mut planes = CUBE.to_vec();
+        planes.push(extra);
+        planes
+    }
+
+    #[test]
+    fn cube_is_fine() {
+        assert_eq!(check(&solid(2, &CUBE), ""), []);
+    }
+
+    #[test]
+    fn broken_planes() {
+        assert_eq!(
+            check(&solid(2, &CUBE[..3]), ""),
+            [(MapObject::Solid(2), ProblemKind::TooFewPlanes(3))]
+        );
+        assert_eq!(
+            check(&solid(2, &with(CUBE[0])), ""),
+            [(MapObject::Side(9), ProblemKind::DuplicatePlane)]
+        );
+        assert_eq!(
+            check(&solid(2, &with("(0 0 0) (64 64 64) (128 128 128)")), ""),
+            [(MapObject::Side(9), ProblemKind::DegeneratePlane)]
+        );
+        assert_eq!(
+            check(&solid(2, &with("(0 0 0) (0 0 0) (256 0 0)")), ""),
+            [(MapObject::Side(9), ProblemKind::DegeneratePlane)]
+        );
+        // Well outside the cube
+        assert_eq!(
+            check(
+                &solid(2, &with("(1000 256 -64) (1000 -256 -64) (1000 -256 0)")),
+                ""
+            ),
+            [(MapObject::Side(9), ProblemKind::NotConvex)]
+        );
+    }
+
+    #[test]
+    fn open_hull() {
+        let planes = [&CUBE[..1], &CUBE[2..]].concat();
+        assert_eq!(
+            check(&solid(2, &planes), ""),
+            [(MapObject::Solid(2), ProblemKind::OpenHull)]
+        );
+    }
+
+    #[test]
+    fn missing_material() {
+        let world = solid(2, &CUBE).replacen("TOOLS/TOOLSNODRAW", " ", 1);
+        assert_eq!(
+            check(&world, ""),
+            [(MapObject::Side(3), ProblemKind::MissingMaterial)]
+        );
+    }
+
+    #[test]
+    fn off_grid() {
+        let mut planes = CUBE.to_vec();
+        planes[0] = "(-256 256 0.5) (256 256 0.5) (256 -256 0.5)";
+        let problems = check(&solid(2, &planes), "");
+        assert_eq!(problems.len(), 4);
+        for (object, kind) in problems {
+            assert_eq!(object, MapObject::Solid(2));
+            let ProblemKind::OffGrid(vertex) = kind else {
+                panic!("{kind:?} isn't off grid");
+            };
+            assert_eq!(vertex.y, 0.5);
+            assert_eq!(vertex.x.abs(), 256.0);
+        }
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let world = solid(2, &CUBE) + &solid(2, &CUBE).replace("\"id\" \"3\"", "\"id\" \"30\"");
+        let problems = check(&world, "");
+        assert_eq!(
+            problems,
+            [
+                (MapObject::Solid(2), ProblemKind::DuplicateSolidId),
+                // Side 3 is only in the first one now
+                (MapObject::Side(4), ProblemKind::DuplicateSideId),
+                (MapObject::Side(5), ProblemKind::DuplicateSideId),
+                (MapObject::Side(6), ProblemKind::DuplicateSideId),
+                (MapObject::Side(7), ProblemKind::DuplicateSideId),
+                (MapObject::Side(8), ProblemKind::DuplicateSideId),
+                (MapObject::Solid(2), ProblemKind::DuplicateSolidId),
+                (MapObject::Side(4), ProblemKind::DuplicateSideId),
+                (MapObject::Side(5), ProblemKind::DuplicateSideId),
+                (MapObject::Side(6), ProblemKind::DuplicateSideId),
+                (MapObject::Side(7), ProblemKind::DuplicateSideId),
+                (MapObject::Side(8), ProblemKind::DuplicateSideId),
+            ]
+        );
+    }
+
+    #[test]
+    fn entity_problems() {
+        let entities = entity(10, "\"origin\" \"0 0 0\"", &[]);
+        assert_eq!(
+            check("", &entities),
+            [(MapObject::Entity(10), ProblemKind::NoClassname)]
+        );
+    }
+
+    #[test]
+    fn outputs() {
+        let door = entity(
+            20,
+            &format!(
+                "\"classname\" \"func_door\"\n\"targetname\" \"door\"\n{}",
+                solid(21, &CUBE)
+            ),
+            &[],
+        );
+        let relay = entity(
+            10,
+            "\"classname\" \"logic_relay\"\n\"targetname\" \"relay\"",
+            &[
+                ("OnTrigger", "door,Open,,0,-1"),
+                ("OnTrigger", "DOOR*,Open,,0,-1"),
+                ("OnTrigger", "!activator,Anything,,0,-1"),
+                ("OnTrigger", "door,Open"),
+                ("OnTrigger", "door,Explode,,0,-1"),
+                ("OnTrigger", "nobody,Open,,0,-1"),
+                ("OnTrigger", ",Open,,0,-1"),
+                ("OnFire", "relay,Trigger,,0,-1"),
+            ],
+        );
+        // Nothing is known about the outputs of classes that aren't in the FGD
+        let unknown = entity(
+            30,
+            "\"classname\" \"logic_mystery\"",
+            &[("OnAnything", "relay,Trigger,,0,-1")],
+        );
+        assert_eq!(
+            check("", &(relay + &door + &unknown)),
+            [
+                (
+                    MapObject::Entity(10),
+                    ProblemKind::MalformedOutput("door,Open".into())
+                ),
+                (
+                    MapObject::Entity(10),
+                    ProblemKind::UnknownInput {
+                        target: "door".into(),
+                        input: "Explode".into()
+                    }
+                ),
+                (
+                    MapObject::Entity(10),
+                    ProblemKind::UnknownTarget("nobody".into())
+                ),
+                (MapObject::Entity(10), ProblemKind::NoTarget),
+                (
+                    MapObject::Entity(10),
+                    ProblemKind::UnknownOutput("OnFire".into())
+                ),
+            ]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use bevy::prelude::*;
 
 #[derive(Debug, Component)]
@@ -8,4 +10,38 @@ pub struct SolidComponent {
 #[derive(Debug, Component)]
 pub struct SideComponent {
     pub id: u32,
-}
\ No newline at end of file
+}
+
+/// Marks anything spawned for a VMF `entity`: the root of a point entity, or
+/// each solid belonging to a brush entity.
+#[derive(Debug, Component)]
+pub struct EntityComponent {
+    pub id: u32,
+    pub classname: String,
+}
+
+/// The outermost group a solid or point entity is in, everything with the
+/// same id is selected together
+#[derive(Debug, Component)]
+pub struct GroupComponent {
+    pub id: u32,
+}
+
+/// Something in the map that can be pointed at from outside the viewports,
+/// like a problem report or a list in the UI
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapObject {
+    Solid(u32),
+    Side(u32),
+    Entity(u32),
+}
+
+impl Display for MapObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapObject::Solid(id) => write!(f, "Solid {id}"),
+            MapObject::Side(id) => write!(f, "Side {id}"),
+            MapObject::Entity(id) => write!(f, "Entity {id}"),
+        }
+    }
+}
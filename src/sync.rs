@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+
+use crate::{
+    solidcomp::SideComponent,
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+    },
+};
+
+/// Writes what is done to the solids in the viewports back into the active
+/// `VmfFile`, so it gets saved (and can be undone).
+///
+/// The ECS entities know which part of the map they came from through the
+/// ids in `SolidComponent` and `SideComponent`. Edits are made to the map
+/// through the history, then the scene is rebuilt from the map, so the map is
+/// always what the viewports show.
+pub struct SyncPlugin;
+
+impl Plugin for SyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, write_back_moved_sides);
+    }
+}
+
+/// Once a face has been dragged and let go of, moves its plane by the same
+/// amount and puts the face entity back where it was
+fn write_back_moved_sides(
+    click: Res<ButtonInput<MouseButton>>,
+    mut q_sides: Query<(&SideComponent, &mut Transform)>,
+    active_vmf: Res<ActiveVmf>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    mut changed: EventWriter<VmfChanged>,
+) {
+    if !click.just_released(MouseButton::Left) {
+        return;
+    }
+
+    let moved: Vec<_> = q_sides
+        .iter_mut()
+        .filter(|(_, transform)| transform.translation != Vec3::ZERO)
+        .collect();
+    if moved.is_empty() {
+        return;
+    }
+
+    let Some(vmf_file) = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get_mut(h))
+    else {
+        return;
+    };
+
+    for (side, mut transform) in moved {
+        // Sides are children of their solid, so this is already in map units.
+        // Whole units only, so integer planes stay integers instead of
+        // picking up f32 noise like 0.10000000149.
+        let offset = std::mem::take(&mut transform.translation)
+            .as_dvec3()
+            .round();
+        let Some(plane) = vmf_file
+            .vmf
+            .side(side.id)
+            .map(|s| s.plane.translated(offset))
+        else {
+            warn!("Moved side {} that isn't in the map", side.id);
+            continue;
+        };
+        vmf_file.apply(Command::MoveSide {
+            side: side.id,
+            plane,
+        });
+    }
+
+    // The neighbouring faces of the solid have to be clipped again
+    changed.send(VmfChanged);
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use bevy::{
+    asset::io::{AssetReader, AssetReaderError, AssetSource, PathStream, Reader, VecReader},
+    math::DVec2,
+    prelude::*,
+    tasks::{block_on, poll_once, IoTaskPool, Task},
+    utils::BoxedFuture,
+};
+use chisel::{materials::Materials, vfs::FileSystem, vtf::read_size, vtf::VtfLoader};
+
+use crate::vmf2::res::VmfChanged;
+
+/// Textures are loaded from `game://`, the game's loose files and VPKs.
+/// Asset sources have to exist before the `AssetPlugin` does, so this goes
+/// before `DefaultPlugins`.
+pub struct TexturePlugin;
+impl Plugin for TexturePlugin {
+    fn build(&self, app: &mut App) {
+        // Empty until the game config is applied
+        let game_files = GameFiles::new(FileSystem::new());
+        let reader_files = game_files.clone();
+        app.register_asset_source(
+            "game",
+            AssetSource::build()
+                .with_reader(move || Box::new(GameAssetReader(reader_files.clone()))),
+        )
+        .insert_resource(MapTextures::new(game_files.get()))
+        .insert_resource(game_files)
+        .add_systems(Update, finish_texture_lookups);
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.init_asset_loader::<VtfLoader>();
+    }
+}
+
+/// The game filesystem, shared with the asset reader for `game://`
+#[derive(Resource, Clone)]
+pub struct GameFiles(Arc<RwLock<FileSystem>>);
+
+impl GameFiles {
+    pub fn new(fs: FileSystem) -> Self {
+        Self(Arc::new(RwLock::new(fs)))
+    }
+
+    pub fn get(&self) -> FileSystem {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, fs: FileSystem) {
+        *self.0.write().unwrap() = fs;
+    }
+}
+
+struct GameAssetReader(GameFiles);
+
+impl AssetReader for GameAssetReader {
+    fn read<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<Reader<'a>>, AssetReaderError>> {
+        Box::pin(async move {
+            let fs = self.0.get();
+            let name = path.to_string_lossy();
+            if !fs.contains(&name) {
+                return Err(AssetReaderError::NotFound(path.to_owned()));
+            }
+            let reader: Box<Reader> = Box::new(VecReader::new(fs.read(&name)?));
+            Ok(reader)
+        })
+    }
+
+    /// Game files never come with `.meta` files
+    fn read_meta<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<Reader<'a>>, AssetReaderError>> {
+        Box::pin(async move { Err(AssetReaderError::NotFound(path.to_owned())) })
+    }
+
+    fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxedFuture<'a, Result<Box<PathStream>, AssetReaderError>> {
+        Box::pin(async move { Err(AssetReaderError::NotFound(path.to_owned())) })
+    }
+
+    fn is_directory<'a>(
+        &'a self,
+        _path: &'a Path,
+    ) -> BoxedFuture<'a, Result<bool, AssetReaderError>> {
+        Box::pin(async move { Ok(false) })
+    }
+}
+
+/// What a face needs to draw its material
+#[derive(Clone, Debug)]
+pub struct FaceTexture {
+    pub image: Handle<Image>,
+    /// In texels, for working out texture coordinates
+    pub size: DVec2,
+    pub alpha_mode: AlphaMode,
+}
+
+/// The materials of the game we're editing, and the base textures we've
+/// started loading for them. Finding a material's texture means reading its
+/// VMT and the VTF's header, from a VPK or loose files, so that's done on the
+/// IO task pool and faces are flat colours until it's done.
+#[derive(Resource)]
+pub struct MapTextures {
+    materials: Arc<Materials>,
+    /// By material name, `None` if it has no base texture we can find
+    textures: HashMap<String, Option<FaceTexture>>,
+    /// Lookups that are still running, by material name
+    pending: HashMap<String, Task<Option<FaceTexture>>>,
+}
+
+impl MapTextures {
+    pub fn new(fs: FileSystem) -> Self {
+        Self {
+            materials: Arc::new(Materials::new(fs)),
+            textures: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// The base texture of a material, `None` while it's still being looked
+    /// up. The first call starts the lookup.
+    pub fn get(&mut self, asset_server: &AssetServer, material: &str) -> Option<FaceTexture> {
+        let key = material.to_ascii_lowercase();
+        if let Some(texture) = self.textures.get(&key) {
+            return texture.clone();
+        }
+
+        if !self.pending.contains_key(&key) {
+            let materials = self.materials.clone();
+            let asset_server = asset_server.clone();
+            let material = material.to_owned();
+            let task = IoTaskPool::get()
+                .spawn(async move { load_texture(&materials, &asset_server, &material) });
+            self.pending.insert(key, task);
+        }
+        None
+    }
+}
+
+fn load_texture(
+    materials: &Materials,
+    asset_server: &AssetServer,
+    material: &str,
+) -> Option<FaceTexture> {
+    let info = match materials.load(material) {
+        Ok(info) => info,
+        Err(e) => {
+            warn!("Material {material}: {e}");
+            return None;
+        }
+    };
+    let base_texture = info.base_texture.clone()?;
+    let alpha_mode = if info.translucent {
+        AlphaMode::Blend
+    } else if info.alpha_test {
+        AlphaMode::Mask(0.5)
+    } else {
+        AlphaMode::Opaque
+    };
+
+    // The image loads in the background too, but the face needs the size to
+    // work out its texture coordinates
+    let path = format!("materials/{base_texture}.vtf");
+    let size = match materials.fs().open(&path).map(read_size) {
+        Ok(Ok(size)) => size.as_dvec2(),
+        Ok(Err(e)) => {
+            warn!("Can't read {path}: {e}");
+            return None;
+        }
+        Err(e) => {
+            warn!("Texture {base_texture} of material {material}: {e}");
+            return None;
+        }
+    };
+
+    Some(FaceTexture {
+        image: asset_server.load(format!("game://{path}")),
+        size,
+        alpha_mode,
+    })
+}
+
+/// Collects the texture lookups that are done, and redraws the map once the
+/// last of them is in. Redrawing for each one would rebuild a big map
+/// hundreds of times while it opens.
+pub fn finish_texture_lookups(
+    mut textures: ResMut<MapTextures>,
+    mut changed: EventWriter<VmfChanged>,
+) {
+    if textures.pending.is_empty() {
+        return;
+    }
+
+    let textures = &mut *textures;
+    let mut done = Vec::new();
+    for (key, task) in &mut textures.pending {
+        if let Some(texture) = block_on(poll_once(task)) {
+            done.push((key.clone(), texture));
+        }
+    }
+    for (key, texture) in done {
+        textures.pending.remove(&key);
+        textures.textures.insert(key, texture);
+    }
+    if textures.pending.is_empty() {
+        changed.send(VmfChanged);
+    }
+}
@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    controls::SelectedObjects,
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+    },
+};
+
+/// Hammer's Delete (Del). Only world brushes go for now, entities have no
+/// command that removes them.
+#[derive(Clone, Copy, Event)]
+pub struct DeleteObjects;
+
+pub fn delete_shortcut(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut delete: EventWriter<DeleteObjects>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Delete) {
+        delete.send(DeleteObjects);
+    }
+}
+
+/// Deletes the selected solids as one step, undoing it puts each one back
+/// where it was in the file
+pub fn delete_objects(
+    mut events: EventReader<DeleteObjects>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    mut changed: EventWriter<VmfChanged>,
+    selected: SelectedObjects,
+) {
+    if events.read().last().is_none() {
+        return;
+    }
+    let Some(handle) = active_vmf.active.clone() else {
+        return;
+    };
+    let Some(vmf) = vmf_files.get(&handle).map(|f| &f.vmf) else {
+        return;
+    };
+
+    // In file order, so the history reads the same way every time
+    let selection = selected.get();
+    let mut commands: Vec<Command> = vmf
+        .world
+        .solids
+        .iter()
+        .filter(|s| selection.solids.contains(&s.id))
+        .map(|s| Command::DeleteSolid { solid: s.id })
+        .collect();
+    let command = match commands.len() {
+        0 => return,
+        1 => commands.pop().unwrap(),
+        n => Command::Batch {
+            description: format!("Delete {n} solids"),
+            commands,
+        },
+    };
+
+    // Only now, get_mut counts as a change to the map
+    let vmf_file = vmf_files.get_mut(&handle).unwrap();
+    if vmf_file.apply(command) {
+        changed.send(VmfChanged);
+    }
+}
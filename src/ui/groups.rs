@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    controls::{IgnoreGroups, SelectedObjects},
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+        vmf::{Editor, Group, Vmf},
+    },
+};
+
+/// Hammer's Group (Ctrl+G) and Ungroup (Ctrl+U)
+#[derive(Clone, Copy, Event)]
+pub enum GroupObjects {
+    Group,
+    Ungroup,
+}
+
+pub fn group_shortcuts(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut group: EventWriter<GroupObjects>,
+    mut ignore_groups: ResMut<IgnoreGroups>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyG) {
+        group.send(GroupObjects::Group);
+    } else if keys.just_pressed(KeyCode::KeyU) {
+        group.send(GroupObjects::Ungroup);
+    } else if keys.just_pressed(KeyCode::KeyW) {
+        ignore_groups.0 = !ignore_groups.0;
+    }
+}
+
+/// Groups the selection, or breaks up the groups it is in. Either way only
+/// the outermost groups are touched, groups inside them stay as they are.
+pub fn group_objects(
+    mut events: EventReader<GroupObjects>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    mut changed: EventWriter<VmfChanged>,
+    selected: SelectedObjects,
+) {
+    let Some(&event) = events.read().last() else {
+        return;
+    };
+    let Some(handle) = active_vmf.active.clone() else {
+        return;
+    };
+    let Some(vmf) = vmf_files.get(&handle).map(|f| &f.vmf) else {
+        return;
+    };
+
+    let selection = selected.get();
+    let solids = selection
+        .solids
+        .iter()
+        .filter_map(|&id| Some((id, vmf.solid(id)?.editor.as_ref())));
+    let entities = selection
+        .entities
+        .iter()
+        .filter_map(|&id| Some((id, vmf.entity(id)?.editor.as_ref())));
+    let tops: HashSet<u32> = solids
+        .clone()
+        .chain(entities.clone())
+        .filter_map(|(_, editor)| vmf.top_group(editor))
+        .collect();
+
+    let command = match event {
+        GroupObjects::Group => {
+            let loose = |(_, editor): &(u32, Option<&Editor>)| vmf.top_group(*editor).is_none();
+            let solids: Vec<_> = solids.filter(loose).collect();
+            let entities: Vec<_> = entities.filter(loose).collect();
+            // One group on its own would only end up inside another
+            let count = solids.len() + entities.len() + tops.len();
+            if count < 2 {
+                return;
+            }
+            let id = vmf.next_object_id();
+            let set = |editor: Option<&Editor>| {
+                let mut editor = editor.cloned().unwrap_or_default();
+                editor.group_id = Some(id);
+                Some(editor)
+            };
+
+            let mut groups = vmf.world.groups.clone();
+            for group in groups.iter_mut().filter(|g| tops.contains(&g.id)) {
+                group.editor = set(group.editor.as_ref());
+            }
+            groups.push(Group::new(id));
+            let mut commands = vec![Command::SetGroups { groups }];
+            for (solid, editor) in solids {
+                commands.push(Command::SetSolidEditor {
+                    solid,
+                    editor: set(editor),
+                });
+            }
+            for (entity, editor) in entities {
+                commands.push(Command::SetEntityEditor {
+                    entity,
+                    editor: set(editor),
+                });
+            }
+            Command::Batch {
+                description: format!("Group {count} objects"),
+                commands,
+            }
+        }
+        GroupObjects::Ungroup => {
+            if tops.is_empty() {
+                return;
+            }
+            ungroup(vmf, &tops)
+        }
+    };
+
+    // Only now, get_mut counts as a change to the map
+    let vmf_file = vmf_files.get_mut(&handle).unwrap();
+    if vmf_file.apply(command) {
+        changed.send(VmfChanged);
+    }
+}
+
+/// Removes the groups, what was directly in them isn't in a group anymore
+fn ungroup(vmf: &Vmf, ids: &HashSet<u32>) -> Command {
+    let in_group = |editor: &Option<Editor>| {
+        editor
+            .as_ref()
+            .and_then(|e| e.group_id)
+            .is_some_and(|id| ids.contains(&id))
+    };
+    let without = |editor: &Option<Editor>| {
+        editor.clone().map(|mut e| {
+            e.group_id = None;
+            e
+        })
+    };
+
+    let mut groups = vmf.world.groups.clone();
+    groups.retain(|g| !ids.contains(&g.id));
+    for group in groups.iter_mut().filter(|g| in_group(&g.editor)) {
+        group.editor = without(&group.editor);
+    }
+    let mut commands = vec![Command::SetGroups { groups }];
+    let owned = vmf.entities.iter().flat_map(|e| &e.solids);
+    for solid in vmf.world.solids.iter().chain(owned) {
+        if in_group(&solid.editor) {
+            commands.push(Command::SetSolidEditor {
+                solid: solid.id,
+                editor: without(&solid.editor),
+            });
+        }
+    }
+    for entity in &vmf.entities {
+        if in_group(&entity.editor) {
+            commands.push(Command::SetEntityEditor {
+                entity: entity.id,
+                editor: without(&entity.editor),
+            });
+        }
+    }
+    Command::Batch {
+        description: match ids.len() {
+            1 => "Ungroup".to_owned(),
+            n => format!("Ungroup {n} groups"),
+        },
+        commands,
+    }
+}
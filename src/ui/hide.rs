@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use crate::{
+    controls::SelectedObjects,
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+    },
+};
+
+#[derive(Default, Resource)]
+pub struct HiddenObjects {
+    /// Draw hidden objects anyway
+    pub show: bool,
+}
+
+impl HiddenObjects {
+    /// Whether an object that is or isn't hidden gets drawn
+    pub fn shows(&self, hidden: bool) -> bool {
+        self.show || !hidden
+    }
+}
+
+/// Hammer's Hide selected objects (H), Hide unselected objects (Ctrl+H) and
+/// Unhide all (U)
+#[derive(Clone, Copy, Event)]
+pub enum HideObjects {
+    Selected,
+    Unselected,
+    UnhideAll,
+}
+
+pub fn hide_shortcuts(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut hide: EventWriter<HideObjects>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if keys.just_pressed(KeyCode::KeyH) {
+        hide.send(if ctrl {
+            HideObjects::Unselected
+        } else {
+            HideObjects::Selected
+        });
+    } else if keys.just_pressed(KeyCode::KeyU) && !ctrl {
+        hide.send(HideObjects::UnhideAll);
+    }
+}
+
+/// Hides solids and whole entities, going by what's selected in the scene
+pub fn hide_objects(
+    mut events: EventReader<HideObjects>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    mut changed: EventWriter<VmfChanged>,
+    selected: SelectedObjects,
+) {
+    let Some(&event) = events.read().last() else {
+        return;
+    };
+    let Some(handle) = active_vmf.active.clone() else {
+        return;
+    };
+    let Some(vmf) = vmf_files.get(&handle).map(|f| &f.vmf) else {
+        return;
+    };
+
+    let selection = selected.get();
+
+    // Solids of entities go with their entity, unless they were hidden on
+    // their own in the file
+    let solids = vmf
+        .world
+        .solids
+        .iter()
+        .chain(
+            vmf.entities
+                .iter()
+                .flat_map(|e| e.solids.iter().filter(|s| s.hidden)),
+        )
+        .map(|s| (s.id, s.hidden, &selection.solids));
+    let entities = vmf
+        .entities
+        .iter()
+        .map(|e| (e.id, e.hidden, &selection.entities));
+    // Everything whose hidden flag changes, and what to
+    let pick = |(id, hidden, selected): (u32, bool, &HashSet<u32>)| {
+        let hide = match event {
+            HideObjects::Selected => hidden || selected.contains(&id),
+            HideObjects::Unselected => hidden || !selected.contains(&id),
+            HideObjects::UnhideAll => false,
+        };
+        (hide != hidden).then_some((id, hide))
+    };
+    let solids: Vec<(u32, bool)> = solids.filter_map(pick).collect();
+    let entities: Vec<(u32, bool)> = entities.filter_map(pick).collect();
+    if solids.is_empty() && entities.is_empty() {
+        return;
+    }
+
+    // Only now, get_mut counts as a change to the map
+    let vmf_file = vmf_files.get_mut(&handle).unwrap();
+    if vmf_file.apply(Command::SetHidden { solids, entities }) {
+        changed.send(VmfChanged);
+    }
+}
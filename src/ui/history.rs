@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::vmf2::res::{ActiveVmf, VmfChanged, VmfFile};
+
+use super::{settings::GameSettings, ErrorDialog};
+
+/// The depth itself is in the config, see [`GameSettings`]
+#[derive(Default, Resource)]
+pub struct HistorySettings {
+    pub show_panel: bool,
+}
+
+/// Undoes (negative `steps`) or redoes edits to the active map, rebuilding
+/// the scene if anything changed
+pub fn step_history(
+    vmf_files: &mut Assets<VmfFile>,
+    active_vmf: &ActiveVmf,
+    changed: &mut EventWriter<VmfChanged>,
+    steps: isize,
+) {
+    let Some(vmf_file) = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get_mut(h))
+    else {
+        return;
+    };
+
+    let mut any = false;
+    for _ in 0..steps.unsigned_abs() {
+        let stepped = if steps < 0 {
+            vmf_file.undo()
+        } else {
+            vmf_file.redo()
+        };
+        if !stepped {
+            break;
+        }
+        any = true;
+    }
+    if any {
+        changed.send(VmfChanged);
+    }
+}
+
+/// Ctrl+Z to undo, Ctrl+Y or Ctrl+Shift+Z to redo
+pub fn undo_shortcuts(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    mut changed: EventWriter<VmfChanged>,
+) {
+    // Leave text boxes to do their own undo
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::KeyZ) && !shift {
+        step_history(&mut vmf_files, &active_vmf, &mut changed, -1);
+    } else if keys.just_pressed(KeyCode::KeyY) || (keys.just_pressed(KeyCode::KeyZ) && shift) {
+        step_history(&mut vmf_files, &active_vmf, &mut changed, 1);
+    }
+}
+
+/// Keeps the active map's history as deep as the config says
+pub fn sync_history_depth(
+    game_settings: Res<GameSettings>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+) {
+    let Some(handle) = &active_vmf.active else {
+        return;
+    };
+    // Check first, get_mut counts as a modification of the asset
+    if vmf_files
+        .get(handle)
+        .is_some_and(|f| f.history.depth() != game_settings.config.history_depth)
+    {
+        let depth = game_settings.config.history_depth;
+        vmf_files.get_mut(handle).unwrap().history.set_depth(depth);
+    }
+}
+
+/// Lists the edits to the active map. Clicking one goes back (or forward) to
+/// just after it was made.
+pub fn history_window(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<HistorySettings>,
+    mut game_settings: ResMut<GameSettings>,
+    mut error_dialog: ResMut<ErrorDialog>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    mut changed: EventWriter<VmfChanged>,
+) {
+    if !settings.show_panel {
+        return;
+    }
+
+    let mut open = true;
+    let mut steps = 0;
+    let mut depth = game_settings.config.history_depth;
+    let mut save_depth = false;
+
+    egui::Window::new("History")
+        .open(&mut open)
+        .default_width(240.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Depth");
+                let response = ui.add(egui::DragValue::new(&mut depth).clamp_range(1..=10_000));
+                // Saved once a drag is over, not for every step of it
+                save_depth = response.drag_stopped() || (response.changed() && !response.dragged());
+            });
+            ui.separator();
+
+            let Some(vmf_file) = active_vmf.active.as_ref().and_then(|h| vmf_files.get(h)) else {
+                ui.label("No active file");
+                return;
+            };
+            let history = &vmf_file.history;
+            let undo: Vec<&str> = history.undo_descriptions().collect();
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if ui.selectable_label(undo.is_empty(), "Opened map").clicked() {
+                    steps = -(undo.len() as isize);
+                }
+                for (i, description) in undo.iter().enumerate() {
+                    let current = i + 1 == undo.len();
+                    if ui.selectable_label(current, *description).clicked() {
+                        steps = -((undo.len() - i - 1) as isize);
+                    }
+                }
+                for (i, description) in history.redo_descriptions().enumerate() {
+                    let text = egui::RichText::new(description).weak();
+                    if ui.selectable_label(false, text).clicked() {
+                        steps = i as isize + 1;
+                    }
+                }
+            });
+        });
+
+    settings.show_panel = open;
+    if save_depth {
+        if let Err(e) = game_settings.set_history_depth(depth) {
+            error_dialog.show("Failed to save the history depth", e);
+        }
+    } else if depth != game_settings.config.history_depth {
+        game_settings.config.history_depth = depth;
+    }
+    if steps != 0 {
+        step_history(&mut vmf_files, &active_vmf, &mut changed, steps);
+    }
+}
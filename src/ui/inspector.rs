@@ -0,0 +1,850 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use chisel::fgd::{Property, PropertyType};
+
+use crate::{
+    controls::{SelectObject, Selected},
+    problems::{check_output, is_target, ProblemKind},
+    solidcomp::{EntityComponent, MapObject, SideComponent, SolidComponent},
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+        vmf::{Entity, Output, Side, Vmf, UV},
+    },
+};
+
+use super::{settings::EntityClasses, OccupiedScreenSpace};
+
+#[derive(Resource)]
+pub struct Inspector {
+    pub tab: EntityTab,
+    /// Edit keys with widgets for their FGD type, instead of as raw text
+    pub smart_edit: bool,
+    /// Which of the entity's outputs is open for editing
+    output: usize,
+    /// The row for adding a key by hand
+    new_key: String,
+    new_value: String,
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self {
+            tab: EntityTab::Keys,
+            smart_edit: true,
+            output: 0,
+            new_key: String::new(),
+            new_value: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EntityTab {
+    Keys,
+    Outputs,
+    /// The outputs of other entities that reach this one
+    Inputs,
+}
+
+/// An edit the panel wants to make. Changes that come a little at a time,
+/// like dragging a value, are merged into one step of the history.
+struct Edit {
+    command: Command,
+    merge: bool,
+}
+
+/// The right panel, showing and editing the selected entity and face
+#[allow(clippy::too_many_arguments)]
+pub fn inspector_panel(
+    mut contexts: EguiContexts,
+    mut inspector: ResMut<Inspector>,
+    mut occupied_screen_space: ResMut<OccupiedScreenSpace>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    classes: Res<EntityClasses>,
+    mut changed: EventWriter<VmfChanged>,
+    mut select: EventWriter<SelectObject>,
+    q_sides: Query<(&Selected, &SideComponent, &Parent)>,
+    q_points: Query<(&Selected, &EntityComponent), Without<SolidComponent>>,
+    q_solids: Query<&EntityComponent, With<SolidComponent>>,
+) {
+    let mut side_ids = Vec::new();
+    let mut entity_ids = Vec::new();
+    for (selected, side, parent) in &q_sides {
+        if selected.0 {
+            side_ids.push(side.id);
+            // Sides of a brush entity select the entity too
+            if let Ok(entity) = q_solids.get(parent.get()) {
+                entity_ids.push(entity.id);
+            }
+        }
+    }
+    for (selected, entity) in &q_points {
+        if selected.0 {
+            entity_ids.push(entity.id);
+        }
+    }
+    side_ids.sort_unstable();
+    entity_ids.sort_unstable();
+    entity_ids.dedup();
+
+    // Edits are collected and made once the panel is drawn, get_mut would
+    // count as a change to the map every frame
+    let vmf = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get(h))
+        .map(|f| &f.vmf);
+    let entity = vmf.and_then(|vmf| entity_ids.first().and_then(|&id| vmf.entity(id)));
+    let side = vmf.and_then(|vmf| side_ids.first().and_then(|&id| vmf.side(id)));
+
+    let mut edits = Vec::new();
+    let inspector = inspector.as_mut();
+
+    occupied_screen_space.right = egui::SidePanel::right("right_panel")
+        .resizable(true)
+        .default_width(280.0)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.heading("Entity");
+                match vmf.zip(entity) {
+                    Some((vmf, entity)) => {
+                        if entity_ids.len() > 1 {
+                            ui.label(format!(
+                                "{} entities selected, showing {}",
+                                entity_ids.len(),
+                                entity.id
+                            ));
+                        }
+                        entity_section(
+                            ui,
+                            inspector,
+                            &classes,
+                            vmf,
+                            entity,
+                            &mut edits,
+                            &mut select,
+                        );
+                    }
+                    None => {
+                        ui.label("No entity selected");
+                    }
+                }
+
+                ui.separator();
+                ui.heading("Face");
+                match side {
+                    Some(side) => {
+                        if side_ids.len() > 1 {
+                            ui.label(format!(
+                                "{} faces selected, showing {}",
+                                side_ids.len(),
+                                side.id
+                            ));
+                        }
+                        face_section(ui, side, &mut edits);
+                    }
+                    None => {
+                        ui.label("No face selected");
+                    }
+                }
+            });
+            ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
+        })
+        .response
+        .rect
+        .width();
+
+    if edits.is_empty() {
+        return;
+    }
+    let Some(vmf_file) = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get_mut(h))
+    else {
+        return;
+    };
+    let mut any = false;
+    for edit in edits {
+        any |= if edit.merge {
+            vmf_file.apply_merged(edit.command)
+        } else {
+            vmf_file.apply(edit.command)
+        };
+    }
+    if any {
+        changed.send(VmfChanged);
+    }
+}
+
+fn entity_section(
+    ui: &mut egui::Ui,
+    inspector: &mut Inspector,
+    classes: &EntityClasses,
+    vmf: &Vmf,
+    entity: &Entity,
+    edits: &mut Vec<Edit>,
+    select: &mut EventWriter<SelectObject>,
+) {
+    let set_key = |edits: &mut Vec<Edit>, key: &str, value: Option<String>, merge: bool| {
+        edits.push(Edit {
+            command: Command::SetEntityKey {
+                entity: entity.id,
+                key: key.to_owned(),
+                value,
+            },
+            merge,
+        });
+    };
+
+    ui.horizontal(|ui| {
+        ui.label("Class");
+        let placeable = classes.0.placeable();
+        if placeable.is_empty() {
+            if let Some(classname) = text_field(
+                ui,
+                ui.id().with((entity.id, "classname")),
+                &entity.classname,
+            ) {
+                set_key(edits, "classname", Some(classname), false);
+            }
+            return;
+        }
+        egui::ComboBox::from_id_source("classname")
+            .selected_text(&entity.classname)
+            .width(ui.available_width())
+            .show_ui(ui, |ui| {
+                for class in placeable {
+                    if ui
+                        .selectable_label(class.name == entity.classname, &class.name)
+                        .on_hover_text(&class.description)
+                        .clicked()
+                        && class.name != entity.classname
+                    {
+                        set_key(edits, "classname", Some(class.name.clone()), false);
+                    }
+                }
+            });
+    });
+    ui.label(format!("Id {}", entity.id));
+
+    let outputs = entity.connections.as_ref().map_or(0, Vec::len);
+    let inputs = incoming(vmf, entity).count();
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut inspector.tab, EntityTab::Keys, "Keys");
+        ui.selectable_value(
+            &mut inspector.tab,
+            EntityTab::Outputs,
+            format!("Outputs ({outputs})"),
+        );
+        ui.selectable_value(
+            &mut inspector.tab,
+            EntityTab::Inputs,
+            format!("Inputs ({inputs})"),
+        );
+    });
+    ui.separator();
+    match inspector.tab {
+        EntityTab::Keys => {}
+        EntityTab::Outputs => return outputs_tab(ui, inspector, classes, vmf, entity, edits),
+        EntityTab::Inputs => return inputs_tab(ui, classes, vmf, entity, select),
+    }
+
+    ui.checkbox(&mut inspector.smart_edit, "SmartEdit");
+
+    let keys = entity.keys();
+    let class = classes.0.resolve(&entity.classname);
+
+    match class.filter(|_| inspector.smart_edit) {
+        Some(class) => {
+            if !class.description.is_empty() {
+                ui.label(egui::RichText::new(&class.description).small());
+            }
+            let value_of = |name: &str| {
+                keys.iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+            };
+
+            egui::Grid::new("smart_keys")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    for property in &class.properties {
+                        if property.kind == PropertyType::Flags {
+                            continue;
+                        }
+                        // Keys are written the way the map already has them
+                        let (key, value) = value_of(&property.name).unwrap_or_else(|| {
+                            (
+                                property.name.clone(),
+                                property.default.clone().unwrap_or_default(),
+                            )
+                        });
+                        let label = ui.label(property.label());
+                        if !property.description.is_empty() {
+                            label.on_hover_text(&property.description);
+                        }
+                        ui.add_enabled_ui(!property.readonly, |ui| {
+                            let id = ui.id().with((entity.id, &property.name));
+                            if let Some((value, merge)) = property_widget(ui, id, property, &value)
+                            {
+                                set_key(edits, &key, Some(value), merge);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+
+            for property in class
+                .properties
+                .iter()
+                .filter(|p| p.kind == PropertyType::Flags)
+            {
+                let (key, value) = value_of(&property.name).unwrap_or_else(|| {
+                    let default = property
+                        .flags
+                        .iter()
+                        .filter(|f| f.default)
+                        .fold(0, |bits, f| bits | f.bit);
+                    (property.name.clone(), default.to_string())
+                });
+                let bits = value.trim().parse::<u32>().unwrap_or(0);
+                egui::CollapsingHeader::new(property.label())
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        for flag in &property.flags {
+                            let mut on = bits & flag.bit != 0;
+                            if ui.checkbox(&mut on, &flag.name).changed() {
+                                let bits = if on {
+                                    bits | flag.bit
+                                } else {
+                                    bits & !flag.bit
+                                };
+                                set_key(edits, &key, Some(bits.to_string()), false);
+                            }
+                        }
+                    });
+            }
+
+            let others: Vec<_> = keys
+                .iter()
+                .filter(|(k, _)| k != "classname" && class.property(k).is_none())
+                .collect();
+            if !others.is_empty() {
+                egui::CollapsingHeader::new("Other keys").show(ui, |ui| {
+                    egui::Grid::new("other_keys")
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (key, value) in others {
+                                ui.label(key);
+                                if let Some(value) =
+                                    text_field(ui, ui.id().with((entity.id, key)), value)
+                                {
+                                    set_key(edits, key, Some(value), false);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            }
+        }
+        None => {
+            if inspector.smart_edit {
+                ui.label("This class isn't in the FGDs, showing its keys as text");
+            }
+            egui::Grid::new("raw_keys")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (key, value) in &keys {
+                        ui.label(key);
+                        if let Some(value) = text_field(ui, ui.id().with((entity.id, key)), value) {
+                            set_key(edits, key, Some(value), false);
+                        }
+                        if key != "classname" && ui.small_button("x").clicked() {
+                            set_key(edits, key, None, false);
+                        }
+                        ui.end_row();
+                    }
+
+                    ui.text_edit_singleline(&mut inspector.new_key);
+                    ui.text_edit_singleline(&mut inspector.new_value);
+                    let key = inspector.new_key.trim();
+                    if ui
+                        .add_enabled(!key.is_empty(), egui::Button::new("Add"))
+                        .clicked()
+                    {
+                        set_key(edits, key, Some(inspector.new_value.clone()), false);
+                        inspector.new_key.clear();
+                        inspector.new_value.clear();
+                    }
+                    ui.end_row();
+                });
+        }
+    }
+}
+
+/// The entity's outputs as a list, with the one picked from it below
+fn outputs_tab(
+    ui: &mut egui::Ui,
+    inspector: &mut Inspector,
+    classes: &EntityClasses,
+    vmf: &Vmf,
+    entity: &Entity,
+    edits: &mut Vec<Edit>,
+) {
+    let mut outputs = entity.connections.clone().unwrap_or_default();
+    let set_outputs = |edits: &mut Vec<Edit>, outputs: Vec<Output>, merge: bool| {
+        edits.push(Edit {
+            command: Command::SetConnections {
+                entity: entity.id,
+                connections: Some(outputs),
+            },
+            merge,
+        });
+    };
+
+    for (i, output) in outputs.iter().enumerate() {
+        let problems = check_output(vmf, &classes.0, entity, output);
+        ui.horizontal(|ui| {
+            let mut text = format!("{} > {}.{}", output.output, output.target, output.input);
+            if !output.parameter.is_empty() {
+                text += &format!("({})", output.parameter);
+            }
+            if output.delay != 0.0 {
+                text += &format!(" after {}s", output.delay);
+            }
+            if !problems.is_empty() {
+                warning(ui, &problems);
+            }
+            if ui.selectable_label(inspector.output == i, text).clicked() {
+                inspector.output = i;
+            }
+        });
+    }
+    ui.horizontal(|ui| {
+        if ui.button("Add").clicked() {
+            // New outputs are written the way the entity's others are
+            let separator = outputs.first().map_or(',', |o| o.separator);
+            let mut output = Output::new("", "", "");
+            output.separator = separator;
+            outputs.push(output);
+            inspector.output = outputs.len() - 1;
+            set_outputs(edits, outputs.clone(), false);
+        }
+        if ui
+            .add_enabled(
+                inspector.output < outputs.len(),
+                egui::Button::new("Delete"),
+            )
+            .clicked()
+        {
+            outputs.remove(inspector.output);
+            set_outputs(edits, outputs.clone(), false);
+        }
+    });
+
+    let Some(output) = outputs.get(inspector.output) else {
+        return;
+    };
+    ui.separator();
+
+    let class = classes.0.resolve(&entity.classname);
+    let output_names: Vec<&str> = class
+        .iter()
+        .flat_map(|c| c.outputs.iter().map(|o| o.name.as_str()))
+        .collect();
+    // Every input any of the targets has
+    let target_classes: Vec<_> = vmf
+        .entities
+        .iter()
+        .filter(|e| is_target(&output.target, e))
+        .filter_map(|e| classes.0.resolve(&e.classname))
+        .collect();
+    let mut input_names: Vec<&str> = target_classes
+        .iter()
+        .flat_map(|c| c.inputs.iter().map(|i| i.name.as_str()))
+        .collect();
+    input_names.sort_unstable_by_key(|n| n.to_ascii_lowercase());
+    input_names.dedup_by_key(|n| n.to_ascii_lowercase());
+    let mut target_names: Vec<String> = vmf
+        .entities
+        .iter()
+        .filter_map(|e| e.key("targetname"))
+        .filter(|n| !n.is_empty())
+        .collect();
+    target_names.sort_unstable();
+    target_names.dedup();
+
+    let mut edited = output.clone();
+    let mut merge = false;
+    let id = ui.id().with(("output", entity.id, inspector.output));
+    egui::Grid::new("output")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("My output");
+            if let Some(name) = name_field(ui, id.with("output"), &output.output, &output_names) {
+                edited.output = name;
+            }
+            ui.end_row();
+
+            ui.label("Target");
+            let target_names: Vec<&str> = target_names.iter().map(String::as_str).collect();
+            if let Some(target) = name_field(ui, id.with("target"), &output.target, &target_names) {
+                edited.target = target;
+            }
+            ui.end_row();
+
+            ui.label("Input");
+            if let Some(input) = name_field(ui, id.with("input"), &output.input, &input_names) {
+                edited.input = input;
+            }
+            ui.end_row();
+
+            ui.label("Parameter");
+            if let Some(parameter) = text_field(ui, id.with("parameter"), &output.parameter) {
+                edited.parameter = parameter;
+            }
+            ui.end_row();
+
+            ui.label("Delay");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut edited.delay)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f32::MAX)
+                        .suffix(" s"),
+                )
+                .changed()
+            {
+                merge = true;
+            }
+            ui.end_row();
+
+            ui.label("Only once");
+            let mut once = output.times > 0;
+            if ui.checkbox(&mut once, "").changed() {
+                edited.times = if once { 1 } else { -1 };
+            }
+            ui.end_row();
+        });
+
+    // Say what's wrong with the picked input in words too
+    let problems = check_output(vmf, &classes.0, entity, output);
+    for problem in &problems {
+        ui.colored_label(egui::Color32::YELLOW, problem.to_string());
+    }
+    if let Some(input) = target_classes.iter().find_map(|c| c.input(&output.input)) {
+        if !input.description.is_empty() {
+            ui.label(egui::RichText::new(&input.description).small());
+        }
+    }
+
+    if !edited.same_as(output) {
+        outputs[inspector.output] = edited;
+        set_outputs(edits, outputs, merge);
+    }
+}
+
+/// The outputs in the map that reach this entity, clicking one selects the
+/// entity it's on
+fn inputs_tab(
+    ui: &mut egui::Ui,
+    classes: &EntityClasses,
+    vmf: &Vmf,
+    entity: &Entity,
+    select: &mut EventWriter<SelectObject>,
+) {
+    let mut any = false;
+    for (source, output) in incoming(vmf, entity) {
+        any = true;
+        let problems = check_output(vmf, &classes.0, source, output);
+        let name = source
+            .key("targetname")
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| source.classname.clone());
+        ui.horizontal(|ui| {
+            if !problems.is_empty() {
+                warning(ui, &problems);
+            }
+            let mut text = format!("{name}.{} > {}", output.output, output.input);
+            if !output.parameter.is_empty() {
+                text += &format!("({})", output.parameter);
+            }
+            if ui
+                .link(text)
+                .on_hover_text(format!("Select entity {}", source.id))
+                .clicked()
+            {
+                select.send(SelectObject(MapObject::Entity(source.id)));
+            }
+        });
+    }
+    if !any {
+        ui.label("No outputs reach this entity");
+    }
+}
+
+/// Every output in the map, with the entity it's on, that reaches `entity`
+fn incoming<'a>(
+    vmf: &'a Vmf,
+    entity: &'a Entity,
+) -> impl Iterator<Item = (&'a Entity, &'a Output)> {
+    vmf.entities.iter().flat_map(move |source| {
+        source
+            .connections
+            .iter()
+            .flatten()
+            .filter(|o| !o.target.trim().is_empty() && is_target(&o.target, entity))
+            .map(move |o| (source, o))
+    })
+}
+
+fn warning(ui: &mut egui::Ui, problems: &[ProblemKind]) {
+    let text: Vec<String> = problems.iter().map(ToString::to_string).collect();
+    ui.colored_label(egui::Color32::YELLOW, "⚠")
+        .on_hover_text(text.join("\n"));
+}
+
+/// A text field with a menu of the names the FGD knows next to it
+fn name_field(ui: &mut egui::Ui, id: egui::Id, value: &str, names: &[&str]) -> Option<String> {
+    ui.horizontal(|ui| {
+        let mut picked = text_field(ui, id, value);
+        ui.add_enabled_ui(!names.is_empty(), |ui| {
+            ui.menu_button("▾", |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for name in names {
+                            if ui.selectable_label(*name == value, *name).clicked() {
+                                picked = Some(name.to_string());
+                                ui.close_menu();
+                            }
+                        }
+                    });
+            });
+        });
+        picked.filter(|p| p != value)
+    })
+    .inner
+}
+
+/// Edits a value the way its FGD type says. Returns the new value, and
+/// whether it should be merged with the last edit.
+fn property_widget(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    property: &Property,
+    value: &str,
+) -> Option<(String, bool)> {
+    match property.kind {
+        PropertyType::Choices if !property.choices.is_empty() => {
+            let selected = property
+                .choices
+                .iter()
+                .find(|c| c.value == value)
+                .map_or(value, |c| c.name.as_str());
+            let mut picked = None;
+            egui::ComboBox::from_id_source(id)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for choice in &property.choices {
+                        if ui
+                            .selectable_label(choice.value == value, &choice.name)
+                            .clicked()
+                            && choice.value != value
+                        {
+                            picked = Some((choice.value.clone(), false));
+                        }
+                    }
+                });
+            picked
+        }
+        PropertyType::Boolean => {
+            let mut on = value.trim() != "0" && !value.trim().is_empty();
+            ui.checkbox(&mut on, "")
+                .changed()
+                .then(|| ((on as u8).to_string(), false))
+        }
+        PropertyType::Color255 => {
+            // "r g b", sometimes with a brightness after it that we keep
+            let mut parts = value.split_whitespace();
+            let mut color = [0u8; 3];
+            for c in &mut color {
+                *c = parts
+                    .next()
+                    .and_then(|p| p.parse::<f32>().ok())
+                    .unwrap_or(0.0)
+                    .clamp(0.0, 255.0) as u8;
+            }
+            let rest: Vec<&str> = parts.collect();
+            ui.horizontal(|ui| {
+                let picked = ui.color_edit_button_srgb(&mut color).changed().then(|| {
+                    let mut value = format!("{} {} {}", color[0], color[1], color[2]);
+                    for part in &rest {
+                        value.push(' ');
+                        value.push_str(part);
+                    }
+                    (value, true)
+                });
+                text_field(ui, id, value).map(|v| (v, false)).or(picked)
+            })
+            .inner
+        }
+        PropertyType::Color1 => {
+            let mut parts = value.split_whitespace();
+            let mut color = [0f32; 3];
+            for c in &mut color {
+                *c = parts
+                    .next()
+                    .and_then(|p| p.parse::<f32>().ok())
+                    .unwrap_or(0.0)
+                    .clamp(0.0, 1.0);
+            }
+            ui.color_edit_button_rgb(&mut color)
+                .changed()
+                .then(|| (format!("{} {} {}", color[0], color[1], color[2]), true))
+        }
+        PropertyType::Angle => {
+            let mut angles = [0f32; 3];
+            for (a, part) in angles.iter_mut().zip(value.split_whitespace()) {
+                *a = part.parse().unwrap_or(0.0);
+            }
+            ui.horizontal(|ui| {
+                let mut any = false;
+                for (a, name) in angles.iter_mut().zip(["Pitch", "Yaw", "Roll"]) {
+                    any |= ui
+                        .add(egui::DragValue::new(a).speed(1.0).suffix("°"))
+                        .on_hover_text(name)
+                        .changed();
+                }
+                any.then(|| (format!("{} {} {}", angles[0], angles[1], angles[2]), true))
+            })
+            .inner
+        }
+        _ => text_field(ui, id, value).map(|v| (v, false)),
+    }
+}
+
+fn face_section(ui: &mut egui::Ui, side: &Side, edits: &mut Vec<Edit>) {
+    ui.label(format!("Id {}", side.id));
+
+    let mut u_axis = side.u_axis.clone();
+    let mut v_axis = side.v_axis.clone();
+    let mut rotation = side.rotation;
+    let mut lightmap_scale = side.lightmap_scale;
+    let mut merge = true;
+
+    egui::Grid::new("face")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Material");
+            if let Some(material) =
+                text_field(ui, ui.id().with((side.id, "material")), &side.material)
+            {
+                edits.push(Edit {
+                    command: Command::SetMaterial {
+                        side: side.id,
+                        material,
+                    },
+                    merge: false,
+                });
+            }
+            ui.end_row();
+
+            for (label, axis) in [("U axis", &mut u_axis), ("V axis", &mut v_axis)] {
+                ui.label(label);
+                if let Some(parsed) =
+                    text_field(ui, ui.id().with((side.id, label)), &axis.to_string())
+                        .and_then(|t| t.parse().ok())
+                {
+                    *axis = parsed;
+                    merge = false;
+                }
+                ui.end_row();
+            }
+
+            ui.label("Scale");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut u_axis.1).speed(0.01).prefix("X "));
+                ui.add(egui::DragValue::new(&mut v_axis.1).speed(0.01).prefix("Y "));
+            });
+            ui.end_row();
+
+            ui.label("Shift");
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut u_axis.0[3])
+                        .speed(1.0)
+                        .prefix("X "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut v_axis.0[3])
+                        .speed(1.0)
+                        .prefix("Y "),
+                );
+            });
+            ui.end_row();
+
+            ui.label("Rotation");
+            ui.add(egui::DragValue::new(&mut rotation).speed(1.0).suffix("°"));
+            ui.end_row();
+
+            ui.label("Lightmap scale");
+            ui.add(egui::DragValue::new(&mut lightmap_scale).clamp_range(1..=128));
+            ui.end_row();
+        });
+
+    if rotation != side.rotation {
+        // The axes carry the rotation, turn them around the face
+        let normal = axis_of(&u_axis).cross(axis_of(&v_axis));
+        let delta = (rotation - side.rotation) as f64;
+        if normal.length_squared() > 0.0 {
+            u_axis = u_axis.rotated(normal, delta);
+            v_axis = v_axis.rotated(normal, delta);
+        }
+    }
+
+    if u_axis != side.u_axis
+        || v_axis != side.v_axis
+        || rotation != side.rotation
+        || lightmap_scale != side.lightmap_scale
+    {
+        edits.push(Edit {
+            command: Command::SetFaceTexture {
+                side: side.id,
+                u_axis,
+                v_axis,
+                rotation,
+                lightmap_scale,
+            },
+            merge,
+        });
+    }
+}
+
+/// A texture axis in Hammer's Z up space
+fn axis_of(uv: &UV) -> bevy::math::DVec3 {
+    bevy::math::DVec3::new(uv.0[0] as f64, uv.0[1] as f64, uv.0[2] as f64)
+}
+
+/// A text box that only hands back its text once the user is done with it,
+/// so typing a value doesn't make an edit per key press
+fn text_field(ui: &mut egui::Ui, id: egui::Id, value: &str) -> Option<String> {
+    let mut text = ui
+        .data_mut(|d| d.get_temp::<String>(id))
+        .unwrap_or_else(|| value.to_owned());
+    let response = ui.add(egui::TextEdit::singleline(&mut text).id(id));
+    if response.has_focus() {
+        ui.data_mut(|d| d.insert_temp(id, text));
+        return None;
+    }
+    ui.data_mut(|d| d.remove::<String>(id));
+    (response.lost_focus() && text != value).then_some(text)
+}
@@ -1,10 +1,34 @@
-use bevy::prelude::*;
+use bevy::{asset::AssetLoadFailedEvent, prelude::*};
 use bevy_egui::{
     egui::{self, load::SizedTexture},
     EguiContexts,
 };
 
-use crate::vmf2::res::{ActiveVmf, VmfFile};
+use crate::{
+    controls::IgnoreGroups,
+    solidcomp::{EntityComponent, SolidComponent},
+    vmf2::res::{ActiveVmf, VmfChanged, VmfFile, VmfLoader},
+};
+
+use self::{
+    delete::{delete_objects, delete_shortcut, DeleteObjects},
+    groups::{group_objects, group_shortcuts, GroupObjects},
+    hide::{hide_objects, hide_shortcuts, HiddenObjects, HideObjects},
+    history::{history_window, step_history, sync_history_depth, undo_shortcuts, HistorySettings},
+    inspector::{inspector_panel, Inspector},
+    problems::{problems_shortcut, problems_window, ProblemsPanel},
+    settings::{apply_game_config, settings_window, EntityClasses, GameSettings},
+    visgroups::{visgroups_window, VisgroupPanel},
+};
+
+mod delete;
+mod groups;
+pub mod hide;
+mod history;
+mod inspector;
+mod problems;
+pub mod settings;
+mod visgroups;
 
 #[derive(Default, Resource)]
 pub struct OccupiedScreenSpace {
@@ -14,6 +38,19 @@ pub struct OccupiedScreenSpace {
     pub bottom: f32,
 }
 
+/// An error to show the user in a popup, instead of panicking or printing it
+/// to a console they probably don't have open.
+#[derive(Default, Resource)]
+pub struct ErrorDialog {
+    pub error: Option<(String, String)>,
+}
+
+impl ErrorDialog {
+    pub fn show(&mut self, title: impl ToString, message: impl ToString) {
+        self.error = Some((title.to_string(), message.to_string()));
+    }
+}
+
 #[derive(Resource)]
 pub struct Images {
     select_mode_icon: Handle<Image>,
@@ -33,14 +70,43 @@ pub struct ChiselUIPlugin;
 impl Plugin for ChiselUIPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<OccupiedScreenSpace>()
+            .init_resource::<ErrorDialog>()
             .init_resource::<Images>()
             .init_resource::<ActiveVmf>()
+            .init_resource::<HistorySettings>()
+            .init_resource::<ProblemsPanel>()
+            .init_resource::<GameSettings>()
+            .init_resource::<EntityClasses>()
+            .init_resource::<Inspector>()
+            .init_resource::<HiddenObjects>()
+            .init_resource::<VisgroupPanel>()
+            .add_event::<HideObjects>()
+            .add_event::<GroupObjects>()
+            .add_event::<DeleteObjects>()
             .init_asset::<VmfFile>()
+            .init_asset_loader::<VmfLoader>()
             // .add_asset::<VmfFile>()
-            .add_systems(Update, ui_system);
+            .add_systems(
+                Update,
+                (
+                    ui_system,
+                    // The side panel goes in the space the others leave
+                    inspector_panel.after(ui_system),
+                    entity_labels,
+                    track_vmf_loading,
+                    (undo_shortcuts, history_window, sync_history_depth),
+                    (problems_shortcut, problems_window),
+                    (hide_shortcuts, hide_objects).chain(),
+                    (group_shortcuts, group_objects).chain(),
+                    (delete_shortcut, delete_objects).chain(),
+                    visgroups_window,
+                    (apply_game_config, settings_window),
+                ),
+            );
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn ui_system(
     mut contexts: EguiContexts,
     mut rendered_texture_id: Local<egui::TextureId>,
@@ -50,6 +116,22 @@ pub fn ui_system(
     // mut vmf_file: ResMut<VmfFile>
     mut vmf_files: ResMut<Assets<VmfFile>>,
     mut active_vmf: ResMut<ActiveVmf>,
+    mut error_dialog: ResMut<ErrorDialog>,
+    mut history_settings: ResMut<HistorySettings>,
+    mut problems_panel: ResMut<ProblemsPanel>,
+    mut game_settings: ResMut<GameSettings>,
+    classes: Res<EntityClasses>,
+    mut changed: EventWriter<VmfChanged>,
+    // Grouped, systems only take so many parameters
+    (mut hidden, mut hide, mut visgroup_panel, mut group, mut ignore_groups, mut delete): (
+        ResMut<HiddenObjects>,
+        EventWriter<HideObjects>,
+        ResMut<VisgroupPanel>,
+        EventWriter<GroupObjects>,
+        ResMut<IgnoreGroups>,
+        EventWriter<DeleteObjects>,
+    ),
+    asset_server: Res<AssetServer>,
 ) {
     if !*is_initialized {
         *is_initialized = true;
@@ -58,6 +140,24 @@ pub fn ui_system(
 
     let ctx = contexts.ctx_mut();
 
+    if let Some((title, message)) = &error_dialog.error {
+        let mut close = false;
+        egui::Window::new(title.as_str())
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(message.as_str());
+                if ui.button("OK").clicked() {
+                    close = true;
+                }
+            });
+        if close {
+            error_dialog.error = None;
+        }
+    }
+
+    let map_dir = game_settings.game().map_dir;
     occupied_screen_space.top = egui::TopBottomPanel::top("top_panel")
         .resizable(false)
         .show(ctx, |ui| {
@@ -68,36 +168,44 @@ pub fn ui_system(
                     }
                     if ui.button("Load").clicked() {
                         if let Some(path) = rfd::FileDialog::new()
-                            .set_directory("./testing")
+                            .set_directory(&map_dir)
                             .add_filter("Valve Map Format (.vmf)", &["vmf"])
                             .pick_file()
                         {
                             println!("loading...");
-                            let vmf_file = VmfFile::open(path);
-                            active_vmf.active = Some(vmf_files.add(vmf_file));
-                            println!("loaded");
+                            let handle = asset_server.load(path);
+                            // A map that is still open gets its old handle
+                            // back, and no event says it's loaded
+                            if asset_server.is_loaded_with_dependencies(&handle) {
+                                active_vmf.loading = None;
+                                active_vmf.active = Some(handle);
+                            } else {
+                                active_vmf.loading = Some(handle);
+                            }
                         }
                     }
-                    match active_vmf
-                        .active
-                        .as_ref()
-                        .and_then(|h| vmf_files.get_mut(h))
-                    {
-                        Some(vmf_file) => {
+                    // Only borrow the file mutably when we have to, get_mut
+                    // counts as a modification of the asset
+                    match active_vmf.active.clone().filter(|h| vmf_files.contains(h)) {
+                        Some(handle) => {
                             if ui.button("Save").clicked() {
                                 println!("saving...");
-                                vmf_file.save();
-                                println!("saved");
+                                match vmf_files.get_mut(&handle).unwrap().save() {
+                                    Ok(()) => println!("saved"),
+                                    Err(e) => error_dialog.show("Failed to save map", e),
+                                }
                             }
                             if ui.button("Save As...").clicked() {
                                 if let Some(path) = rfd::FileDialog::new()
-                                    .set_directory("./testing")
+                                    .set_directory(&map_dir)
                                     .add_filter("Valve Map Format (.vmf)", &["vmf"])
                                     .save_file()
                                 {
                                     println!("saving as...");
-                                    vmf_file.save_as(path);
-                                    println!("saved as");
+                                    match vmf_files.get_mut(&handle).unwrap().save_as(path) {
+                                        Ok(()) => println!("saved as"),
+                                        Err(e) => error_dialog.show("Failed to save map", e),
+                                    }
                                 }
                             }
                         }
@@ -106,16 +214,113 @@ pub fn ui_system(
                             ui.label("Save As...");
                         }
                     }
+                    ui.separator();
+                    if ui.button("Game configurations...").clicked() {
+                        game_settings.open();
+                        ui.close_menu();
+                    }
                 });
                 egui::menu::menu_button(ui, "Edit", |ui| {
-                    ui.label("Undo");
-                    ui.label("Redo");
-                    // if ui.button("Undo").clicked() {
-                    //     println!("undo")
-                    // }
-                    // if ui.button("Redo").clicked() {
-                    //     println!("redo")
-                    // }
+                    let history = active_vmf
+                        .active
+                        .as_ref()
+                        .and_then(|h| vmf_files.get(h))
+                        .map(|f| &f.history);
+                    let next_undo = history
+                        .and_then(|h| h.undo_descriptions().next_back())
+                        .map(str::to_owned);
+                    let next_redo = history
+                        .and_then(|h| h.redo_descriptions().next())
+                        .map(str::to_owned);
+
+                    let undo = match &next_undo {
+                        Some(description) => format!("Undo {description}"),
+                        None => "Undo".to_owned(),
+                    };
+                    if ui
+                        .add_enabled(
+                            next_undo.is_some(),
+                            egui::Button::new(undo).shortcut_text("Ctrl+Z"),
+                        )
+                        .clicked()
+                    {
+                        step_history(&mut vmf_files, &active_vmf, &mut changed, -1);
+                    }
+                    let redo = match &next_redo {
+                        Some(description) => format!("Redo {description}"),
+                        None => "Redo".to_owned(),
+                    };
+                    if ui
+                        .add_enabled(
+                            next_redo.is_some(),
+                            egui::Button::new(redo).shortcut_text("Ctrl+Y"),
+                        )
+                        .clicked()
+                    {
+                        step_history(&mut vmf_files, &active_vmf, &mut changed, 1);
+                    }
+                    ui.separator();
+                    if ui
+                        .add(egui::Button::new("Delete").shortcut_text("Del"))
+                        .clicked()
+                    {
+                        delete.send(DeleteObjects);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    for (event, text, shortcut) in [
+                        (GroupObjects::Group, "Group", "Ctrl+G"),
+                        (GroupObjects::Ungroup, "Ungroup", "Ctrl+U"),
+                    ] {
+                        if ui
+                            .add(egui::Button::new(text).shortcut_text(shortcut))
+                            .clicked()
+                        {
+                            group.send(event);
+                            ui.close_menu();
+                        }
+                    }
+                    let mut ignore = ignore_groups.0;
+                    if ui
+                        .add(egui::Checkbox::new(&mut ignore, "Ignore groups"))
+                        .on_hover_text("Ctrl+W, clicking selects single objects")
+                        .changed()
+                    {
+                        ignore_groups.0 = ignore;
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut history_settings.show_panel, "History");
+                });
+                egui::menu::menu_button(ui, "View", |ui| {
+                    for (event, text, shortcut) in [
+                        (HideObjects::Selected, "Hide selected objects", "H"),
+                        (HideObjects::Unselected, "Hide unselected objects", "Ctrl+H"),
+                        (HideObjects::UnhideAll, "Unhide all", "U"),
+                    ] {
+                        if ui
+                            .add(egui::Button::new(text).shortcut_text(shortcut))
+                            .clicked()
+                        {
+                            hide.send(event);
+                            ui.close_menu();
+                        }
+                    }
+                    ui.separator();
+                    // Through a copy, changing the setting redraws the map
+                    let mut show = hidden.show;
+                    if ui.checkbox(&mut show, "Show hidden objects").changed() {
+                        hidden.show = show;
+                    }
+                    ui.checkbox(&mut visgroup_panel.open, "Visgroups");
+                });
+                egui::menu::menu_button(ui, "Map", |ui| {
+                    if ui
+                        .add(egui::Button::new("Check for problems").shortcut_text("Alt+P"))
+                        .clicked()
+                    {
+                        problems_panel.check(&vmf_files, &active_vmf, &classes);
+                        ui.close_menu();
+                    }
                 });
             });
         })
@@ -126,14 +331,23 @@ pub fn ui_system(
     occupied_screen_space.bottom = egui::TopBottomPanel::bottom("bottom_panel")
         .resizable(false)
         .show(ctx, |ui| {
-            match active_vmf
-                .active
-                .as_ref()
-                .and_then(|h| vmf_files.get_mut(h))
-            {
-                Some(vmf_file) => ui.label(vmf_file.path.to_str().unwrap()),
-                None => ui.label("No active file"),
-            };
+            ui.horizontal(|ui| {
+                match active_vmf.active.as_ref().and_then(|h| vmf_files.get(h)) {
+                    Some(vmf_file) if vmf_file.unsaved_changes() => {
+                        ui.label(format!("{}*", vmf_file.path.display()))
+                    }
+                    Some(vmf_file) => ui.label(vmf_file.path.to_str().unwrap()),
+                    None => ui.label("No active file"),
+                };
+                if let Some(loading) = &active_vmf.loading {
+                    ui.separator();
+                    ui.spinner();
+                    match asset_server.get_path(loading) {
+                        Some(path) => ui.label(format!("Loading {path}...")),
+                        None => ui.label("Loading..."),
+                    };
+                }
+            });
             // ui.label("Bottom Text");
             ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
         })
@@ -180,14 +394,73 @@ pub fn ui_system(
         .response
         .rect
         .width();
+}
 
-    occupied_screen_space.right = egui::SidePanel::right("right_panel")
-        .resizable(true)
-        .show(ctx, |ui| {
-            ui.label("Right resizeable panel");
-            ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
-        })
-        .response
-        .rect
-        .width();
+/// Swaps in a map once the asset server has finished loading it, or tells
+/// the user why it couldn't be loaded.
+pub fn track_vmf_loading(
+    mut active_vmf: ResMut<ActiveVmf>,
+    mut loaded: EventReader<AssetEvent<VmfFile>>,
+    mut failed: EventReader<AssetLoadFailedEvent<VmfFile>>,
+    mut error_dialog: ResMut<ErrorDialog>,
+) {
+    let Some(loading) = active_vmf.loading.clone() else {
+        loaded.clear();
+        failed.clear();
+        return;
+    };
+
+    for event in loaded.read() {
+        if event.is_loaded_with_dependencies(&loading) {
+            println!("loaded");
+            active_vmf.loading = None;
+            active_vmf.active = Some(loading.clone());
+        }
+    }
+
+    for event in failed.read() {
+        if event.id == loading.id() {
+            active_vmf.loading = None;
+            error_dialog.show(
+                "Failed to open map",
+                format!("{}\n\n{}", event.path, event.error),
+            );
+        }
+    }
+}
+
+/// Draws the classname of every point entity next to its box, in each of the viewports
+pub fn entity_labels(
+    mut contexts: EguiContexts,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    point_entities: Query<(&EntityComponent, &GlobalTransform), Without<SolidComponent>>,
+) {
+    let painter = contexts.ctx_mut().layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("entity_labels"),
+    ));
+
+    for (camera, camera_transform) in &cameras {
+        let Some(viewport) = camera.logical_viewport_rect() else {
+            continue;
+        };
+        let clip = egui::Rect::from_min_max(
+            egui::pos2(viewport.min.x, viewport.min.y),
+            egui::pos2(viewport.max.x, viewport.max.y),
+        );
+        let painter = painter.with_clip_rect(clip);
+
+        for (entity, transform) in &point_entities {
+            if let Some(pos) = camera.world_to_viewport(camera_transform, transform.translation()) {
+                let pos = viewport.min + pos;
+                painter.text(
+                    egui::pos2(pos.x + 6.0, pos.y - 6.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    &entity.classname,
+                    egui::FontId::proportional(12.0),
+                    egui::Color32::WHITE,
+                );
+            }
+        }
+    }
 }
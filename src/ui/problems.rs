@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    controls::SelectObject,
+    problems::{check_vmf, Problem},
+    views::split::FrameView,
+    vmf2::res::{ActiveVmf, VmfFile},
+};
+
+use super::settings::EntityClasses;
+
+#[derive(Default, Resource)]
+pub struct ProblemsPanel {
+    pub open: bool,
+    pub problems: Vec<Problem>,
+}
+
+impl ProblemsPanel {
+    /// Checks the active map again and shows what was found
+    pub fn check(
+        &mut self,
+        vmf_files: &Assets<VmfFile>,
+        active_vmf: &ActiveVmf,
+        classes: &EntityClasses,
+    ) {
+        self.open = true;
+        self.problems = active_vmf
+            .active
+            .as_ref()
+            .and_then(|h| vmf_files.get(h))
+            .map(|f| check_vmf(&f.vmf, &classes.0))
+            .unwrap_or_default();
+    }
+}
+
+/// Alt+P, like Hammer
+pub fn problems_shortcut(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panel: ResMut<ProblemsPanel>,
+    vmf_files: Res<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    classes: Res<EntityClasses>,
+) {
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) && keys.just_pressed(KeyCode::KeyP) {
+        panel.check(&vmf_files, &active_vmf, &classes);
+    }
+}
+
+/// Lists the problems found in the map. Clicking one selects the object and
+/// moves the views to it.
+pub fn problems_window(
+    mut contexts: EguiContexts,
+    mut panel: ResMut<ProblemsPanel>,
+    vmf_files: Res<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    classes: Res<EntityClasses>,
+    mut select: EventWriter<SelectObject>,
+    mut frame: EventWriter<FrameView>,
+) {
+    if !panel.open {
+        return;
+    }
+
+    let mut open = true;
+    let mut check_again = false;
+
+    egui::Window::new("Check for problems")
+        .open(&mut open)
+        .default_width(320.0)
+        .show(contexts.ctx_mut(), |ui| {
+            if ui.button("Check again").clicked() {
+                check_again = true;
+            }
+            ui.separator();
+
+            if panel.problems.is_empty() {
+                ui.label("No problems found");
+                return;
+            }
+            ui.label(format!("{} problems found", panel.problems.len()));
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for problem in &panel.problems {
+                    let text = format!("{}: {}", problem.object, problem.kind);
+                    if ui.selectable_label(false, text).clicked() {
+                        select.send(SelectObject(problem.object));
+                        if let Some((center, size)) = problem.focus {
+                            // The scene is drawn at 1/128 scale
+                            frame.send(FrameView {
+                                center: (center / 128.0).as_vec3(),
+                                size: (size / 128.0) as f32,
+                            });
+                        }
+                    }
+                }
+            });
+        });
+
+    panel.open = open;
+    if check_again {
+        panel.check(&vmf_files, &active_vmf, &classes);
+    }
+}
@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use chisel::{
+    config::{Config, GameConfig},
+    fgd::EntityClassDb,
+};
+
+use crate::{
+    textures::{GameFiles, MapTextures},
+    vmf2::res::VmfChanged,
+};
+
+use super::ErrorDialog;
+
+/// Where the game configurations are kept, next to the editor
+const CONFIG_PATH: &str = "chisel_config.txt";
+
+#[derive(Resource)]
+pub struct GameSettings {
+    pub config: Config,
+    /// The copy the settings window is changing, `None` while it's closed
+    editing: Option<Config>,
+    /// Which game the settings window is showing
+    selected: usize,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        let config = Config::load(Path::new(CONFIG_PATH)).unwrap_or_else(|e| {
+            warn!("Can't read {CONFIG_PATH}, using the default config: {e}");
+            Config::default()
+        });
+        Self {
+            config,
+            editing: None,
+            selected: 0,
+        }
+    }
+}
+
+impl GameSettings {
+    /// The game we're mapping for
+    pub fn game(&self) -> GameConfig {
+        self.config
+            .active_game()
+            .cloned()
+            .unwrap_or_else(GameConfig::fallback)
+    }
+
+    pub fn open(&mut self) {
+        self.selected = self.config.active;
+        self.editing = Some(self.config.clone());
+    }
+
+    /// Changes how many edits can be undone and saves it. The settings
+    /// window's copy gets it too, or saving that would put the old one back.
+    pub fn set_history_depth(&mut self, depth: usize) -> std::io::Result<()> {
+        self.config.history_depth = depth;
+        if let Some(editing) = &mut self.editing {
+            editing.history_depth = depth;
+        }
+        self.config.save(Path::new(CONFIG_PATH))
+    }
+}
+
+/// The entity classes from the active game's FGDs
+#[derive(Default, Resource)]
+pub struct EntityClasses(pub EntityClassDb);
+
+/// Points the game filesystem at the active game whenever it changes,
+/// including at startup, reads its FGDs and redraws the map with its
+/// materials
+pub fn apply_game_config(
+    settings: Res<GameSettings>,
+    mut applied: Local<Option<GameConfig>>,
+    game_files: Res<GameFiles>,
+    mut textures: ResMut<MapTextures>,
+    mut classes: ResMut<EntityClasses>,
+    mut changed: EventWriter<VmfChanged>,
+    mut error_dialog: ResMut<ErrorDialog>,
+) {
+    let game = settings.game();
+    if applied.as_ref() == Some(&game) {
+        return;
+    }
+
+    let (fs, mut errors) = game.file_system();
+    let (db, fgd_errors) = EntityClassDb::load(&game.fgds);
+    classes.0 = db;
+    errors.extend(fgd_errors.iter().map(ToString::to_string));
+    if !errors.is_empty() {
+        error_dialog.show(format!("Problems with {}", game.name), errors.join("\n"));
+    }
+    game_files.set(fs.clone());
+    *textures = MapTextures::new(fs);
+    changed.send(VmfChanged);
+    *applied = Some(game);
+}
+
+/// Edits the list of games. Nothing changes until Save is pressed.
+pub fn settings_window(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<GameSettings>,
+    mut error_dialog: ResMut<ErrorDialog>,
+) {
+    if settings.editing.is_none() {
+        return;
+    }
+    let settings = settings.as_mut();
+    let editing = settings.editing.as_mut().unwrap();
+
+    let mut open = true;
+    let mut save = false;
+    let mut cancel = false;
+
+    egui::Window::new("Game configurations")
+        .open(&mut open)
+        .default_width(480.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                settings.selected = settings.selected.min(editing.games.len() - 1);
+                let selected_name = editing.games[settings.selected].name.clone();
+                egui::ComboBox::from_label("")
+                    .selected_text(selected_name)
+                    .show_ui(ui, |ui| {
+                        for (i, game) in editing.games.iter().enumerate() {
+                            let name = if i == editing.active {
+                                format!("{} (active)", game.name)
+                            } else {
+                                game.name.clone()
+                            };
+                            ui.selectable_value(&mut settings.selected, i, name);
+                        }
+                    });
+                if ui.button("Add").clicked() {
+                    editing.games.push(GameConfig {
+                        name: "New game".to_owned(),
+                        ..GameConfig::fallback()
+                    });
+                    settings.selected = editing.games.len() - 1;
+                }
+                if ui
+                    .add_enabled(editing.games.len() > 1, egui::Button::new("Remove"))
+                    .clicked()
+                {
+                    editing.games.remove(settings.selected);
+                    if editing.active > settings.selected {
+                        editing.active -= 1;
+                    }
+                    editing.active = editing.active.min(editing.games.len() - 1);
+                    settings.selected = settings.selected.saturating_sub(1);
+                }
+                if ui
+                    .add_enabled(
+                        editing.active != settings.selected,
+                        egui::Button::new("Make active"),
+                    )
+                    .clicked()
+                {
+                    editing.active = settings.selected;
+                }
+            });
+            ui.separator();
+
+            let game = &mut editing.games[settings.selected];
+            egui::Grid::new("game_config")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut game.name);
+                    ui.end_row();
+
+                    ui.label("Game directory");
+                    path_edit(ui, &mut game.game_dir, Browse::Folder);
+                    ui.end_row();
+
+                    ui.label("gameinfo.txt")
+                        .on_hover_text("Leave empty to use the one in the game directory");
+                    path_edit(ui, &mut game.gameinfo, Browse::File("Game info", &["txt"]));
+                    ui.end_row();
+
+                    ui.label("Map directory");
+                    path_edit(ui, &mut game.map_dir, Browse::Folder);
+                    ui.end_row();
+
+                    ui.label("Default texture");
+                    ui.text_edit_singleline(&mut game.default_texture);
+                    ui.end_row();
+
+                    for (label, path) in [
+                        ("VBSP", &mut game.vbsp),
+                        ("VVIS", &mut game.vvis),
+                        ("VRAD", &mut game.vrad),
+                    ] {
+                        ui.label(label);
+                        path_edit(ui, path, Browse::Program);
+                        ui.end_row();
+                    }
+                });
+
+            ui.separator();
+            ui.label("Entity definitions (FGD)");
+            let mut remove = None;
+            for (i, fgd) in game.fgds.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                    path_edit(ui, fgd, Browse::File("Forge Game Data", &["fgd"]));
+                });
+            }
+            if let Some(i) = remove {
+                game.fgds.remove(i);
+            }
+            if ui.button("Add FGD...").clicked() {
+                if let Some(paths) = rfd::FileDialog::new()
+                    .add_filter("Forge Game Data (.fgd)", &["fgd"])
+                    .pick_files()
+                {
+                    game.fgds.extend(paths);
+                }
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                save = ui.button("Save").clicked();
+                cancel = ui.button("Cancel").clicked();
+            });
+        });
+
+    if save {
+        let config = settings.editing.take().unwrap();
+        if let Err(e) = config.save(Path::new(CONFIG_PATH)) {
+            error_dialog.show(format!("Failed to save {CONFIG_PATH}"), e);
+        }
+        settings.config = config;
+    } else if cancel || !open {
+        settings.editing = None;
+    }
+}
+
+enum Browse {
+    Folder,
+    /// Any file, the compile tools don't have an extension outside Windows
+    Program,
+    /// A filter name and its extensions
+    File(&'static str, &'static [&'static str]),
+}
+
+/// A path as a text box, with a button to pick it instead
+fn path_edit(ui: &mut egui::Ui, path: &mut PathBuf, browse: Browse) {
+    ui.horizontal(|ui| {
+        let mut text = path.to_string_lossy().into_owned();
+        if ui.text_edit_singleline(&mut text).changed() {
+            *path = PathBuf::from(text);
+        }
+        if ui.button("...").clicked() {
+            let mut dialog = rfd::FileDialog::new();
+            if let Some(dir) = path.parent().filter(|d| d.is_dir()) {
+                dialog = dialog.set_directory(dir);
+            }
+            let picked = match browse {
+                Browse::Folder => dialog.pick_folder(),
+                Browse::Program => dialog.pick_file(),
+                Browse::File(name, extensions) => dialog.add_filter(name, extensions).pick_file(),
+            };
+            if let Some(picked) = picked {
+                *path = picked;
+            }
+        }
+    });
+}
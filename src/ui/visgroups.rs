@@ -0,0 +1,546 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    controls::{SelectedObjects, Selection},
+    vmf2::{
+        history::Command,
+        res::{ActiveVmf, VmfChanged, VmfFile},
+        vmf::{Editor, Entity, Solid, Visgroup, Vmf},
+    },
+};
+
+use super::settings::EntityClasses;
+
+#[derive(Default, Resource)]
+pub struct VisgroupPanel {
+    pub open: bool,
+    tab: VisgroupTab,
+    /// The visgroup the buttons act on
+    selected: Option<u32>,
+    new_name: String,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum VisgroupTab {
+    /// The visgroups saved in the map
+    #[default]
+    User,
+    /// Hammer's automatic visgroups, made up from what objects are
+    Auto,
+}
+
+/// The objects in a visgroup, with their editor blocks
+#[derive(Default)]
+struct Members<'a> {
+    solids: Vec<(u32, Option<&'a Editor>)>,
+    entities: Vec<(u32, Option<&'a Editor>)>,
+}
+
+impl<'a> Members<'a> {
+    /// The solids and entities the two tests pick. Solids are given the
+    /// entity they belong to, if any.
+    fn find(
+        vmf: &'a Vmf,
+        solid: impl Fn(Option<&Entity>, &Solid) -> bool,
+        entity: impl Fn(&Entity) -> bool,
+    ) -> Self {
+        let owned = vmf
+            .entities
+            .iter()
+            .flat_map(|e| e.solids.iter().map(move |s| (Some(e), s)));
+        let solids = vmf.world.solids.iter().map(|s| (None, s)).chain(owned);
+        Self {
+            solids: solids
+                .filter(|(owner, s)| solid(*owner, s))
+                .map(|(_, s)| (s.id, s.editor.as_ref()))
+                .collect(),
+            entities: vmf
+                .entities
+                .iter()
+                .filter(|e| entity(e))
+                .map(|e| (e.id, e.editor.as_ref()))
+                .collect(),
+        }
+    }
+
+    fn extend(&mut self, other: &Members<'a>) {
+        self.solids.extend(&other.solids);
+        self.entities.extend(&other.entities);
+    }
+
+    fn len(&self) -> usize {
+        self.solids.len() + self.entities.len()
+    }
+
+    /// Whether all, none or (`None`) some of them are shown
+    fn shown(&self, auto: bool) -> Option<bool> {
+        let flags: Vec<bool> = self
+            .solids
+            .iter()
+            .chain(&self.entities)
+            .map(|(_, e)| flag(*e, auto))
+            .collect();
+        if flags.iter().all(|f| *f) {
+            Some(true)
+        } else if flags.iter().all(|f| !f) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Shows or hides all of them
+    fn set_shown(&self, auto: bool, shown: bool) -> Vec<Command> {
+        let mut done = (HashSet::new(), HashSet::new());
+        let mut commands = Vec::new();
+        for &(id, editor) in &self.solids {
+            if flag(editor, auto) != shown && done.0.insert(id) {
+                let mut editor = editor.cloned().unwrap_or_default();
+                set_flag(&mut editor, auto, shown);
+                commands.push(Command::SetSolidEditor {
+                    solid: id,
+                    editor: Some(editor),
+                });
+            }
+        }
+        for &(id, editor) in &self.entities {
+            if flag(editor, auto) != shown && done.1.insert(id) {
+                let mut editor = editor.cloned().unwrap_or_default();
+                set_flag(&mut editor, auto, shown);
+                commands.push(Command::SetEntityEditor {
+                    entity: id,
+                    editor: Some(editor),
+                });
+            }
+        }
+        commands
+    }
+}
+
+fn flag(editor: Option<&Editor>, auto: bool) -> bool {
+    editor.is_none_or(|e| {
+        if auto {
+            e.visgroup_auto_shown
+        } else {
+            e.visgroup_shown
+        }
+    })
+}
+
+fn set_flag(editor: &mut Editor, auto: bool, shown: bool) {
+    if auto {
+        editor.visgroup_auto_shown = shown;
+    } else {
+        editor.visgroup_shown = shown;
+    }
+}
+
+/// An automatic visgroup. Ones with children are made up of them.
+struct AutoGroup<'a> {
+    name: String,
+    members: Members<'a>,
+    children: Vec<AutoGroup<'a>>,
+}
+
+impl<'a> AutoGroup<'a> {
+    fn new(name: impl Into<String>, members: Members<'a>) -> Self {
+        Self {
+            name: name.into(),
+            members,
+            children: Vec::new(),
+        }
+    }
+
+    fn parent(name: impl Into<String>, children: Vec<AutoGroup<'a>>) -> Self {
+        let mut members = Members::default();
+        for child in &children {
+            members.extend(&child.members);
+        }
+        Self {
+            name: name.into(),
+            members,
+            children,
+        }
+    }
+}
+
+/// Hammer's own automatic visgroups, then the ones from the FGDs
+fn auto_groups<'a>(vmf: &'a Vmf, classes: &EntityClasses) -> Vec<AutoGroup<'a>> {
+    let no_solids = |_: Option<&Entity>, _: &Solid| false;
+    let entities = |test: &dyn Fn(&Entity) -> bool| Members::find(vmf, no_solids, test);
+
+    let mut brush_classes: Vec<&str> = vmf
+        .entities
+        .iter()
+        .filter(|e| !e.solids.is_empty())
+        .map(|e| e.classname.as_str())
+        .collect();
+    brush_classes.sort_unstable();
+    brush_classes.dedup();
+    let brush_entities = brush_classes
+        .into_iter()
+        .map(|class| {
+            AutoGroup::new(
+                class,
+                entities(&|e| !e.solids.is_empty() && e.classname == class),
+            )
+        })
+        .collect();
+
+    let mut groups = vec![
+        AutoGroup::new(
+            "World Geometry",
+            Members::find(vmf, |owner, _| owner.is_none(), |_| false),
+        ),
+        AutoGroup::parent(
+            "Entities",
+            vec![
+                AutoGroup::new("Point Entities", entities(&|e| e.solids.is_empty())),
+                AutoGroup::parent("Brush Entities", brush_entities),
+                AutoGroup::new(
+                    "Triggers",
+                    entities(&|e| e.classname.starts_with("trigger_")),
+                ),
+            ],
+        ),
+    ];
+
+    for group in &classes.0.auto_visgroups {
+        let children = group
+            .groups
+            .iter()
+            .map(|(name, classnames)| {
+                let is_in = |e: &Entity| {
+                    classnames
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(&e.classname))
+                };
+                AutoGroup::new(name, entities(&is_in))
+            })
+            .collect();
+        groups.push(AutoGroup::parent(&group.name, children));
+    }
+    groups
+}
+
+/// The visgroup tree, with checkboxes that show and hide what's in them, and
+/// buttons to put the selection in or take it out of visgroups
+pub fn visgroups_window(
+    mut contexts: EguiContexts,
+    mut panel: ResMut<VisgroupPanel>,
+    mut vmf_files: ResMut<Assets<VmfFile>>,
+    active_vmf: Res<ActiveVmf>,
+    classes: Res<EntityClasses>,
+    mut changed: EventWriter<VmfChanged>,
+    selected: SelectedObjects,
+) {
+    if !panel.open {
+        return;
+    }
+    let panel = panel.as_mut();
+    let vmf = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get(h))
+        .map(|f| &f.vmf);
+    let selection = selected.get();
+
+    let mut commands = Vec::new();
+    let mut open = true;
+    egui::Window::new("Visgroups")
+        .open(&mut open)
+        .default_width(240.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut panel.tab, VisgroupTab::User, "User");
+                ui.selectable_value(&mut panel.tab, VisgroupTab::Auto, "Auto");
+            });
+            ui.separator();
+            let Some(vmf) = vmf else {
+                ui.label("No map open");
+                return;
+            };
+
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| match panel.tab {
+                    VisgroupTab::User => {
+                        if vmf.visgroups.is_empty() {
+                            ui.label("No visgroups");
+                        }
+                        user_rows(ui, vmf, &vmf.visgroups, &mut panel.selected, &mut commands);
+                    }
+                    VisgroupTab::Auto => {
+                        auto_rows(ui, &auto_groups(vmf, &classes), &mut commands);
+                    }
+                });
+
+            if panel.tab == VisgroupTab::User {
+                ui.separator();
+                user_buttons(ui, panel, vmf, &selection, &mut commands);
+            }
+        });
+    if !open {
+        panel.open = false;
+    }
+
+    if commands.is_empty() {
+        return;
+    }
+    let Some(vmf_file) = active_vmf
+        .active
+        .as_ref()
+        .and_then(|h| vmf_files.get_mut(h))
+    else {
+        return;
+    };
+    let mut any = false;
+    for command in commands {
+        any |= vmf_file.apply(command);
+    }
+    if any {
+        changed.send(VmfChanged);
+    }
+}
+
+/// A checkbox for whether the members are shown, which is half ticked when
+/// only some of them are. Hands back what they should become.
+fn shown_checkbox(ui: &mut egui::Ui, members: &Members, auto: bool) -> Option<bool> {
+    let shown = members.shown(auto);
+    let mut checked = shown.unwrap_or(true);
+    ui.add(egui::Checkbox::without_text(&mut checked).indeterminate(shown.is_none()))
+        .clicked()
+        .then_some(shown != Some(true))
+}
+
+fn user_rows(
+    ui: &mut egui::Ui,
+    vmf: &Vmf,
+    visgroups: &[Visgroup],
+    selected: &mut Option<u32>,
+    commands: &mut Vec<Command>,
+) {
+    for visgroup in visgroups {
+        let ids = visgroup.ids();
+        let in_group = |editor: Option<&Editor>| {
+            editor.is_some_and(|e| e.visgroup_ids.iter().any(|id| ids.contains(id)))
+        };
+        let members = Members::find(
+            vmf,
+            |_, s| in_group(s.editor.as_ref()),
+            |e| in_group(e.editor.as_ref()),
+        );
+
+        ui.horizontal(|ui| {
+            if let Some(shown) = shown_checkbox(ui, &members, false) {
+                let verb = if shown { "Show" } else { "Hide" };
+                commands.push(Command::Batch {
+                    description: format!("{verb} visgroup {}", visgroup.name),
+                    commands: members.set_shown(false, shown),
+                });
+            }
+            let [r, g, b] = visgroup.color;
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+            ui.painter()
+                .rect_filled(rect, 2.0, egui::Color32::from_rgb(r, g, b));
+            if ui
+                .selectable_label(*selected == Some(visgroup.id), &visgroup.name)
+                .clicked()
+            {
+                *selected = Some(visgroup.id);
+            }
+            ui.weak(members.len().to_string());
+        });
+        if !visgroup.children.is_empty() {
+            ui.indent(visgroup.id, |ui| {
+                user_rows(ui, vmf, &visgroup.children, selected, commands);
+            });
+        }
+    }
+}
+
+fn auto_rows(ui: &mut egui::Ui, groups: &[AutoGroup], commands: &mut Vec<Command>) {
+    for group in groups {
+        ui.horizontal(|ui| {
+            if let Some(shown) = shown_checkbox(ui, &group.members, true) {
+                let verb = if shown { "Show" } else { "Hide" };
+                commands.push(Command::Batch {
+                    description: format!("{verb} {}", group.name),
+                    commands: group.members.set_shown(true, shown),
+                });
+            }
+            ui.label(&group.name);
+            ui.weak(group.members.len().to_string());
+        });
+        if !group.children.is_empty() {
+            ui.indent(&group.name, |ui| auto_rows(ui, &group.children, commands));
+        }
+    }
+}
+
+fn user_buttons(
+    ui: &mut egui::Ui,
+    panel: &mut VisgroupPanel,
+    vmf: &Vmf,
+    selection: &Selection,
+    commands: &mut Vec<Command>,
+) {
+    let has_selection = !selection.solids.is_empty() || !selection.entities.is_empty();
+    let visgroup = panel
+        .selected
+        .and_then(|id| vmf.all_visgroups().into_iter().find(|v| v.id == id));
+
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut panel.new_name).hint_text("Name"));
+        if ui
+            .add_enabled(has_selection, egui::Button::new("New from selection"))
+            .on_hover_text("Makes a visgroup with the selected objects in it")
+            .clicked()
+        {
+            let id = vmf.all_visgroups().iter().map(|v| v.id).max().unwrap_or(0) + 1;
+            let name = match panel.new_name.trim() {
+                "" => format!("Visgroup {id}"),
+                name => name.to_owned(),
+            };
+            let mut visgroups = vmf.visgroups.clone();
+            visgroups.push(Visgroup::new(id, &name, visgroup_color(id)));
+            let mut batch = vec![Command::SetVisgroups { visgroups }];
+            batch.extend(edit_selected(vmf, selection, |e| {
+                e.visgroup_ids.push(id);
+            }));
+            commands.push(Command::Batch {
+                description: format!("Create visgroup {name}"),
+                commands: batch,
+            });
+            panel.new_name.clear();
+            panel.selected = Some(id);
+        }
+    });
+
+    let Some(visgroup) = visgroup else {
+        ui.weak("Pick a visgroup to add to or remove from it");
+        return;
+    };
+    ui.horizontal(|ui| {
+        let id = visgroup.id;
+        if ui
+            .add_enabled(has_selection, egui::Button::new("Add selection"))
+            .clicked()
+        {
+            commands.push(Command::Batch {
+                description: format!("Add to visgroup {}", visgroup.name),
+                commands: edit_selected(vmf, selection, |e| {
+                    if !e.visgroup_ids.contains(&id) {
+                        e.visgroup_ids.push(id);
+                    }
+                }),
+            });
+        }
+        if ui
+            .add_enabled(has_selection, egui::Button::new("Remove selection"))
+            .clicked()
+        {
+            commands.push(Command::Batch {
+                description: format!("Remove from visgroup {}", visgroup.name),
+                commands: edit_selected(vmf, selection, |e| {
+                    e.visgroup_ids.retain(|v| *v != id);
+                }),
+            });
+        }
+        if ui
+            .button("Delete")
+            .on_hover_text("Deletes the visgroup and the ones in it, not the objects")
+            .clicked()
+        {
+            commands.push(delete_visgroup(vmf, visgroup));
+            panel.selected = None;
+        }
+    });
+}
+
+/// Changes the editor block of every selected object, making one if they
+/// don't have one yet
+fn edit_selected(vmf: &Vmf, selection: &Selection, edit: impl Fn(&mut Editor)) -> Vec<Command> {
+    let mut commands = Vec::new();
+    for &id in &selection.solids {
+        if let Some(solid) = vmf.solid(id) {
+            let mut editor = solid.editor.clone().unwrap_or_default();
+            edit(&mut editor);
+            commands.push(Command::SetSolidEditor {
+                solid: id,
+                editor: Some(editor),
+            });
+        }
+    }
+    for &id in &selection.entities {
+        if let Some(entity) = vmf.entity(id) {
+            let mut editor = entity.editor.clone().unwrap_or_default();
+            edit(&mut editor);
+            commands.push(Command::SetEntityEditor {
+                entity: id,
+                editor: Some(editor),
+            });
+        }
+    }
+    commands
+}
+
+/// Takes the visgroup out of the tree and its objects out of it. Objects it
+/// was hiding are shown again.
+fn delete_visgroup(vmf: &Vmf, visgroup: &Visgroup) -> Command {
+    fn remove(visgroups: &mut Vec<Visgroup>, id: u32) {
+        visgroups.retain(|v| v.id != id);
+        for v in visgroups {
+            remove(&mut v.children, id);
+        }
+    }
+    let mut visgroups = vmf.visgroups.clone();
+    remove(&mut visgroups, visgroup.id);
+
+    let ids = visgroup.ids();
+    let without = |editor: &Editor| {
+        let mut editor = editor.clone();
+        editor.visgroup_ids.retain(|id| !ids.contains(id));
+        if editor.visgroup_ids.is_empty() {
+            editor.visgroup_shown = true;
+        }
+        editor
+    };
+    let in_group = |editor: &Option<Editor>| {
+        editor
+            .as_ref()
+            .is_some_and(|e| e.visgroup_ids.iter().any(|id| ids.contains(id)))
+    };
+
+    let mut commands = vec![Command::SetVisgroups { visgroups }];
+    let owned = vmf.entities.iter().flat_map(|e| &e.solids);
+    for solid in vmf.world.solids.iter().chain(owned) {
+        if in_group(&solid.editor) {
+            commands.push(Command::SetSolidEditor {
+                solid: solid.id,
+                editor: solid.editor.as_ref().map(without),
+            });
+        }
+    }
+    for entity in &vmf.entities {
+        if in_group(&entity.editor) {
+            commands.push(Command::SetEntityEditor {
+                entity: entity.id,
+                editor: entity.editor.as_ref().map(without),
+            });
+        }
+    }
+    Command::Batch {
+        description: format!("Delete visgroup {}", visgroup.name),
+        commands,
+    }
+}
+
+/// A colour for a new visgroup, different enough from the ones before it
+fn visgroup_color(id: u32) -> [u8; 3] {
+    let [r, g, b, _] = Color::hsl((id as f32 * 137.5) % 360.0, 0.6, 0.6).as_rgba_u8();
+    [r, g, b]
+}
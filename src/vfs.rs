@@ -0,0 +1,253 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::vpk::{Vpk, VpkError};
+
+/*
+The game's files the way the engine sees them: a stack of loose directories
+and packs, searched in the order gameinfo.txt lists them. The first one that
+has a file wins, so a mod's loose materials override the ones in hl2's packs.
+
+Paths are relative to the game directory (`materials/tools/toolsnodraw.vmt`)
+and case insensitive, like they are on Windows.
+*/
+
+/// One place the filesystem looks for files
+#[derive(Clone, Debug)]
+pub enum Mount {
+    Dir(PathBuf),
+    Vpk(Arc<Vpk>),
+}
+
+impl Mount {
+    /// The directory or `_dir.vpk` this reads from
+    pub fn path(&self) -> &Path {
+        match self {
+            Mount::Dir(dir) => dir,
+            Mount::Vpk(vpk) => vpk.path(),
+        }
+    }
+
+    fn open(&self, path: &str) -> Option<std::io::Result<Box<dyn Read + Send>>> {
+        match self {
+            Mount::Dir(dir) => {
+                let path = find_file(dir, path)?;
+                Some(File::open(path).map(|f| Box::new(f) as Box<dyn Read + Send>))
+            }
+            Mount::Vpk(vpk) => vpk.open_file(path),
+        }
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        match self {
+            Mount::Dir(dir) => find_file(dir, path).is_some(),
+            Mount::Vpk(vpk) => vpk.contains(path),
+        }
+    }
+}
+
+/// Loose directories and VPKs, searched in the order they were mounted.
+/// Cloning is cheap, packs are shared.
+#[derive(Clone, Debug, Default)]
+pub struct FileSystem {
+    mounts: Vec<Mount>,
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Just one directory, e.g. an extracted `materials` tree's parent
+    pub fn from_dir(dir: impl Into<PathBuf>) -> Self {
+        let mut fs = Self::new();
+        fs.mount_dir(dir);
+        fs
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Searches `dir` after everything already mounted
+    pub fn mount_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.mounts.push(Mount::Dir(dir.into()));
+    }
+
+    /// Searches a pack after everything already mounted. Like gameinfo.txt,
+    /// `pak01.vpk` means `pak01_dir.vpk`, unless there is a `pak01.vpk` (packs
+    /// in `custom/` are often a single file).
+    pub fn mount_vpk(&mut self, path: impl AsRef<Path>) -> Result<(), VpkError> {
+        let path = path.as_ref();
+        let dir_path = match path.to_str().and_then(|p| p.strip_suffix(".vpk")) {
+            Some(stem) if !stem.ends_with("_dir") && !path.is_file() => {
+                PathBuf::from(format!("{stem}_dir.vpk"))
+            }
+            _ => path.to_owned(),
+        };
+        self.mounts.push(Mount::Vpk(Arc::new(Vpk::open(dir_path)?)));
+        Ok(())
+    }
+
+    /// Mounts a `.vpk` as a pack and anything else as a directory
+    pub fn mount(&mut self, path: impl AsRef<Path>) -> Result<(), VpkError> {
+        let path = path.as_ref();
+        let is_vpk = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("vpk"));
+        if is_vpk {
+            self.mount_vpk(path)
+        } else {
+            self.mount_dir(path);
+            Ok(())
+        }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.mounts.iter().any(|m| m.contains(path))
+    }
+
+    /// Which mount a file comes from
+    pub fn find(&self, path: &str) -> Option<&Mount> {
+        self.mounts.iter().find(|m| m.contains(path))
+    }
+
+    /// Opens a file from the first mount that has it
+    pub fn open(&self, path: &str) -> std::io::Result<Box<dyn Read + Send>> {
+        self.mounts
+            .iter()
+            .find_map(|m| m.open(path))
+            .unwrap_or_else(|| {
+                Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{path} not found in any search path"),
+                ))
+            })
+    }
+
+    pub fn read(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads a text file. Valve's text files aren't always UTF-8, anything
+    /// that isn't is replaced rather than failing the whole file.
+    pub fn read_to_string(&self, path: &str) -> std::io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.read(path)?).into_owned())
+    }
+}
+
+/// Source paths are case insensitive, but most filesystems outside of
+/// Windows are not. Tries the path as it is, then matches each part of it
+/// against the directory listing regardless of case.
+pub fn find_file(dir: &Path, relative: &str) -> Option<PathBuf> {
+    let exact = dir.join(relative);
+    if exact.is_file() {
+        return Some(exact);
+    }
+
+    let parts: Vec<&str> = relative.split('/').filter(|p| !p.is_empty()).collect();
+    find_parts(dir, &parts)
+}
+
+/// `Dev/` and `dev/` can both exist, so every match has to be tried
+fn find_parts(dir: &Path, parts: &[&str]) -> Option<PathBuf> {
+    let Some((part, rest)) = parts.split_first() else {
+        return dir.is_file().then(|| dir.to_owned());
+    };
+    std::fs::read_dir(dir).ok()?.find_map(|e| {
+        let e = e.ok()?;
+        if !e.file_name().to_str()?.eq_ignore_ascii_case(part) {
+            return None;
+        }
+        find_parts(&e.path(), rest)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vpk::tests::{temp_dir, write_vpk};
+
+    fn write(dir: &Path, path: &str, text: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn first_mount_wins() {
+        let root = temp_dir("vfs-order");
+        write(&root, "mod/materials/brick.vmt", "mod");
+        write(&root, "mod/materials/only_mod.vmt", "mod");
+        write_vpk(
+            &root,
+            "pak01",
+            2,
+            &[
+                ("materials/brick.vmt", b"pak"),
+                ("materials/only_pak.vmt", b"pak"),
+            ],
+        );
+        write(&root, "hl2/materials/brick.vmt", "hl2");
+        write(&root, "hl2/materials/only_hl2.vmt", "hl2");
+
+        let mut fs = FileSystem::new();
+        fs.mount(root.join("mod")).unwrap();
+        // Without the _dir, like gameinfo.txt writes it
+        fs.mount(root.join("pak01.vpk")).unwrap();
+        fs.mount(root.join("hl2")).unwrap();
+        assert!(matches!(fs.mounts()[1], Mount::Vpk(_)));
+
+        let read = |path| fs.read_to_string(path).unwrap();
+        assert_eq!(read("materials/brick.vmt"), "mod");
+        assert_eq!(read("materials/only_mod.vmt"), "mod");
+        assert_eq!(read("materials/only_pak.vmt"), "pak");
+        assert_eq!(read("materials/only_hl2.vmt"), "hl2");
+        assert_eq!(
+            fs.find("materials/only_pak.vmt").unwrap().path(),
+            root.join("pak01_dir.vpk")
+        );
+        assert!(!fs.contains("materials/missing.vmt"));
+        let missing = fs.read("materials/missing.vmt").unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+        // The other way around, the pack comes first
+        let mut fs = FileSystem::new();
+        fs.mount_vpk(root.join("pak01_dir.vpk")).unwrap();
+        fs.mount_dir(root.join("mod"));
+        assert_eq!(fs.read_to_string("materials/brick.vmt").unwrap(), "pak");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn lookups_ignore_case() {
+        let root = temp_dir("vfs-case");
+        write(&root, "Materials/Tools/ToolsNodraw.VMT", "nodraw");
+        let fs = FileSystem::from_dir(&root);
+        assert_eq!(
+            fs.read_to_string("materials/tools/toolsnodraw.vmt")
+                .unwrap(),
+            "nodraw"
+        );
+        assert!(fs.contains("MATERIALS/TOOLS/TOOLSNODRAW.vmt"));
+        assert!(!fs.contains("materials/tools/toolsclip.vmt"));
+        // Only files, not directories
+        assert!(!fs.contains("materials/tools"));
+
+        // Two directories that only differ in case, the file is in the second
+        write(&root, "dev/other.vmt", "");
+        write(&root, "Dev/dev_measure.vmt", "measure");
+        assert_eq!(
+            find_file(&root, "DEV/Dev_Measure.vmt"),
+            Some(root.join("Dev/dev_measure.vmt"))
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -98,6 +98,7 @@ impl Plugin for CameraControllerPlugin {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn camera_controller(
     active_split: Res<ActiveSplit>,
     time: Res<Time>,
@@ -27,6 +27,8 @@ impl Plugin for ChiselCamerasPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_cameras)
             .init_resource::<ActiveSplit>()
+            .add_event::<FrameView>()
+            .add_systems(Update, frame_views)
             .add_systems(Update, update_cameras)
             .add_systems(Update, update_active_split)
             .add_plugins(CameraControllerPlugin)
@@ -106,6 +108,40 @@ pub fn setup_cameras(mut commands: Commands) {
     );
 }
 
+/// Moves every view to look at `center`, zoomed so about `size` fits in them
+#[derive(Event)]
+pub struct FrameView {
+    pub center: Vec3,
+    pub size: f32,
+}
+
+pub fn frame_views(
+    mut events: EventReader<FrameView>,
+    mut view_3d: Query<&mut Transform, With<View3DCamera>>,
+    mut orthos: Query<(&mut Transform, &mut Projection), Without<View3DCamera>>,
+) {
+    let Some(FrameView { center, size }) = events.read().last() else {
+        return;
+    };
+
+    for mut transform in &mut view_3d {
+        // Keep looking the same way, just back off far enough to see it
+        let back = transform.back();
+        transform.translation = *center + back * (size * 1.5).max(0.5);
+    }
+
+    for (mut transform, mut projection) in &mut orthos {
+        // Ortho cameras only slide across, how deep they are doesn't matter
+        let forward = transform.forward();
+        let depth = forward.dot(transform.translation - *center);
+        transform.translation = *center + forward * depth;
+
+        if let Projection::Orthographic(p) = &mut *projection {
+            p.scale = (size * 0.75).max(1.0);
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct View3DCamera;
 
@@ -134,10 +170,7 @@ impl ActiveSplit {
     // }
 
     pub fn is(&self, v: CameraView) -> bool {
-        match self {
-            ActiveSplit::View(view, _) if v == *view => true,
-            _ => false,
-        }
+        matches!(self, ActiveSplit::View(view, _) if v == *view)
     }
 
     pub fn is_ortho(&self) -> bool {
@@ -206,6 +239,7 @@ pub fn update_active_split(
     };
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_cameras(
     occupied_screen_space: Res<OccupiedScreenSpace>,
     windows: Query<&Window, With<PrimaryWindow>>,
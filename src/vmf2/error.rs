@@ -0,0 +1,78 @@
+use std::fmt::{Debug, Display};
+
+/// A line/column pair into the source text, both starting at 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum VmfError {
+    Io(std::io::Error),
+    /// The text isn't valid KeyValues at all
+    Syntax {
+        position: Position,
+        message: String,
+    },
+    /// A block is missing a key we can't do without (a side without a plane, ...)
+    MissingKey {
+        position: Position,
+        key: String,
+    },
+    /// A block is missing a child block we can't do without (no `world`, ...)
+    MissingBlock {
+        position: Position,
+        name: String,
+    },
+    /// A key is there, but its value doesn't parse. The position is the key's.
+    InvalidValue {
+        position: Position,
+        key: String,
+        value: String,
+    },
+}
+
+impl Display for VmfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmfError::Io(e) => write!(f, "{e}"),
+            VmfError::Syntax { position, message } => write!(f, "{position}: {message}"),
+            VmfError::MissingKey { position, key } => {
+                write!(f, "block starting at {position} is missing \"{key}\"")
+            }
+            VmfError::MissingBlock { position, name } => {
+                write!(
+                    f,
+                    "block starting at {position} is missing a \"{name}\" block"
+                )
+            }
+            VmfError::InvalidValue {
+                position,
+                key,
+                value,
+            } => write!(f, "{position}: invalid \"{key}\": \"{value}\""),
+        }
+    }
+}
+
+impl std::error::Error for VmfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VmfError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VmfError {
+    fn from(e: std::io::Error) -> Self {
+        VmfError::Io(e)
+    }
+}
@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+
+use super::{
+    error::{Position, VmfError},
+    tokenizer::{evaluate_conditional, ParseOptions, Token, Tokenizer},
+};
+
+/// One step through a KeyValues file. Strings borrow from the input unless
+/// an escape sequence forced a copy.
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    KeyValue {
+        key: Cow<'a, str>,
+        value: Cow<'a, str>,
+        /// Where the key is
+        position: Position,
+    },
+    BlockStart {
+        name: Cow<'a, str>,
+        position: Position,
+    },
+    BlockEnd,
+}
+
+/// A pull parser over KeyValues text. It doesn't recurse or build a tree, so
+/// it is the thing to reach for when a map is too big to want a
+/// `GenericNode` for all of it (or just to stream through it once).
+///
+/// Entries behind a false conditional are skipped and never show up as events.
+pub struct EventReader<'a> {
+    tokens: Tokenizer<'a>,
+    depth: usize,
+    done: bool,
+}
+
+impl<'a> EventReader<'a> {
+    pub fn new(input: &'a str, options: ParseOptions) -> Self {
+        Self {
+            tokens: Tokenizer::new(input, options),
+            depth: 0,
+            done: false,
+        }
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event<'a>>, VmfError> {
+        loop {
+            let Some((token, position)) = self.tokens.next_token()? else {
+                if self.depth > 0 {
+                    return Err(self.tokens.error("unexpected end of file, expected '}'"));
+                }
+                return Ok(None);
+            };
+
+            let key = match token {
+                Token::String(key) => key,
+                Token::CloseBrace if self.depth > 0 => {
+                    self.depth -= 1;
+                    return Ok(Some(Event::BlockEnd));
+                }
+                Token::CloseBrace => return Err(self.tokens.error("unexpected '}'")),
+                Token::OpenBrace => {
+                    return Err(self.tokens.error("unexpected '{', expected a key"))
+                }
+                Token::Conditional(_) => {
+                    return Err(self.tokens.error("unexpected conditional, expected a key"))
+                }
+            };
+
+            let mut next = self.tokens.next_token()?;
+            let mut condition = true;
+
+            // Blocks can have their conditional between the name and the `{`
+            if let Some((Token::Conditional(expr), _)) = next {
+                condition = evaluate_conditional(expr);
+                next = self.tokens.next_token()?;
+            }
+
+            match next {
+                Some((Token::String(value), _)) => {
+                    if let Some(expr) = self.tokens.next_conditional() {
+                        condition &= evaluate_conditional(expr);
+                    }
+                    if condition {
+                        return Ok(Some(Event::KeyValue {
+                            key,
+                            value,
+                            position,
+                        }));
+                    }
+                }
+                Some((Token::OpenBrace, _)) => {
+                    if condition {
+                        self.depth += 1;
+                        return Ok(Some(Event::BlockStart {
+                            name: key,
+                            position,
+                        }));
+                    }
+                    self.skip_block()?;
+                }
+                _ => {
+                    return Err(self
+                        .tokens
+                        .error(format!("expected a value or '{{' after \"{key}\"")))
+                }
+            }
+        }
+    }
+
+    /// Skips to the `}` matching a `{` we have just read
+    fn skip_block(&mut self) -> Result<(), VmfError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.tokens.next_token()? {
+                Some((Token::OpenBrace, _)) => depth += 1,
+                Some((Token::CloseBrace, _)) => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.tokens.error("unexpected end of file, expected '}'")),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for EventReader<'a> {
+    type Item = Result<Event<'a>, VmfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = self.read_event().transpose();
+        // Stop after the first error, there is no sensible way to carry on
+        if !matches!(event, Some(Ok(_))) {
+            self.done = true;
+        }
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(input: &str) -> Result<Vec<Event<'_>>, VmfError> {
+        EventReader::new(input, ParseOptions::default()).collect()
+    }
+
+    fn kv<'a>(key: &'a str, value: &'a str, line: usize, column: usize) -> Event<'a> {
+        Event::KeyValue {
+            key: key.into(),
+            value: value.into(),
+            position: Position { line, column },
+        }
+    }
+
+    fn start(name: &str, line: usize, column: usize) -> Event<'_> {
+        Event::BlockStart {
+            name: name.into(),
+            position: Position { line, column },
+        }
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let input =
+            "\"a\" \"1\"\nouter\n{\n\tinner\n\t{\n\t\t\"b\" \"2\"\n\t}\n\t\"c\" \"3\"\n}\nlast {}";
+        assert_eq!(
+            events(input).unwrap(),
+            [
+                kv("a", "1", 1, 1),
+                start("outer", 2, 1),
+                start("inner", 4, 2),
+                kv("b", "2", 6, 3),
+                Event::BlockEnd,
+                kv("c", "3", 8, 2),
+                Event::BlockEnd,
+                start("last", 10, 1),
+                Event::BlockEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn false_conditionals_are_skipped() {
+        let input = "\"a\" \"pc\" [$WIN32]\n\
+                     \"a\" \"console\" [$X360]\n\
+                     console [$X360] { \"b\" \"1\" nested { \"c\" \"2\" } }\n\
+                     pc [!$X360] { \"d\" \"3\" }\n\
+                     \"e\" \"4\"";
+        assert_eq!(
+            events(input).unwrap(),
+            [
+                kv("a", "pc", 1, 1),
+                start("pc", 4, 1),
+                kv("d", "3", 4, 15),
+                Event::BlockEnd,
+                kv("e", "4", 5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn unbalanced_braces() {
+        let Err(VmfError::Syntax { position, .. }) = events("a\n{\n\t\"b\" \"c\"\n") else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(position.line, 4);
+
+        let Err(VmfError::Syntax { position, message }) = events("\"a\" \"b\"\n}") else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!((position.line, message.as_str()), (2, "unexpected '}'"));
+
+        assert!(events("{ \"a\" \"b\" }").is_err());
+        assert!(events("\"key\"").is_err());
+        assert!(events("skipped [$X360] { \"a\" \"b\"").is_err());
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let mut reader = EventReader::new("\"a\" \"1\" } \"b\" \"2\"", ParseOptions::default());
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        assert!(matches!(reader.next(), Some(Err(_))));
+        assert!(reader.next().is_none());
+    }
+}
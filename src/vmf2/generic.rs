@@ -1,32 +1,278 @@
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+use super::{
+    error::{Position, VmfError},
+    events::{Event, EventReader},
+    tokenizer::ParseOptions,
+};
+
+/// How deep `#include` and `#base` files can go, more than this is a loop
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A KeyValues block. Entries are kept in the order they were read, so that
+/// saving an untouched file writes it back out byte for byte.
+#[derive(Clone, Debug, Default)]
 pub struct GenericNode {
-    pub key_value_pairs: HashMap<String, Vec<String>>,
-    pub children_nodes: HashMap<String, Vec<GenericNode>>,
+    pub entries: Vec<Entry>,
+    /// Where this block started in the file it was parsed from, for error messages
+    pub position: Position,
+}
+
+#[derive(Clone, Debug)]
+pub enum Entry {
+    /// A key, its value, and where the key was in the file. Keys set in code
+    /// have the default position.
+    KeyValue(String, String, Position),
+    Child(String, GenericNode),
+    /// A key/value that a typed parser took out. It marks the spot where
+    /// `set_value` puts the key back, and remembers the original text so an
+    /// unchanged value can be written exactly as it was.
+    TakenValue(String, String),
+    /// Same as `TakenValue`, for child blocks
+    TakenChild(String),
 }
 
 impl GenericNode {
-    pub fn get_value(&self, key: &str) -> &String {
-        self.key_value_pairs.get(key).unwrap().get(0).unwrap()
+    /// Removes the first value for `key`, leaving any others in place
+    pub fn take_value(&mut self, key: &str) -> Result<String, VmfError> {
+        self.try_take_value(key)
+            .ok_or_else(|| self.missing_key(key))
+    }
+
+    pub fn try_take_value(&mut self, key: &str) -> Option<String> {
+        self.try_take_positioned(key).map(|(value, _)| value)
+    }
+
+    /// Takes a value along with where its key was, for error messages
+    fn try_take_positioned(&mut self, key: &str) -> Option<(String, Position)> {
+        let i = self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Entry::KeyValue(k, ..) if k == key))?;
+        let taken = Entry::TakenValue(key.to_owned(), String::new());
+        match std::mem::replace(&mut self.entries[i], taken) {
+            Entry::KeyValue(_, value, position) => {
+                self.entries[i] = Entry::TakenValue(key.to_owned(), value.clone());
+                Some((value, position))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The first value for `key` that is still here (not taken)
+    pub fn get_value(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find_map(|e| match e {
+            Entry::KeyValue(k, v, _) if k == key => Some(v.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Removes every value for `key`
+    pub fn remove_value(&mut self, key: &str) {
+        self.entries
+            .retain(|e| !matches!(e, Entry::KeyValue(k, ..) if k == key));
+    }
+
+    pub fn take_parsed<T: FromStr>(&mut self, key: &str) -> Result<T, VmfError> {
+        self.take_with(key, |s| s.parse().ok())
+    }
+
+    /// Removes a key and runs its value through `parse`, which returns `None` if the value is invalid
+    pub fn take_with<T>(
+        &mut self,
+        key: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Result<T, VmfError> {
+        let (value, position) = self
+            .try_take_positioned(key)
+            .ok_or_else(|| self.missing_key(key))?;
+        parse(&value).ok_or_else(|| invalid_value(position, key, &value))
     }
 
+    /// Like `take_with`, but a missing key is fine
+    pub fn try_take_with<T>(
+        &mut self,
+        key: &str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Result<Option<T>, VmfError> {
+        match self.try_take_positioned(key) {
+            Some((value, position)) => parse(&value)
+                .map(Some)
+                .ok_or_else(|| invalid_value(position, key, &value)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes every value for `key`, for keys that can be there more than once
+    pub fn take_values(&mut self, key: &str) -> Vec<String> {
+        let mut values = Vec::new();
+        while let Some(value) = self.try_take_value(key) {
+            values.push(value);
+        }
+        values
+    }
+
+    /// Whether `key` was there before it was taken out, or still is
+    pub fn had_value(&self, key: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| matches!(e, Entry::KeyValue(k, ..) | Entry::TakenValue(k, _) if k == key))
+    }
+
+    pub fn take_child(&mut self, name: &str) -> Result<GenericNode, VmfError> {
+        self.try_take_child(name)
+            .ok_or_else(|| VmfError::MissingBlock {
+                position: self.position,
+                name: name.to_owned(),
+            })
+    }
+
+    pub fn try_take_child(&mut self, name: &str) -> Option<GenericNode> {
+        let i = self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Entry::Child(n, _) if n == name))?;
+        match std::mem::replace(&mut self.entries[i], Entry::TakenChild(name.to_owned())) {
+            Entry::Child(_, child) => Some(child),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The first child block called `name`, left where it is. Values taken
+    /// out of it still remember their text, see `set_parsed`.
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut GenericNode> {
+        self.entries.iter_mut().find_map(|e| match e {
+            Entry::Child(n, child) if n == name => Some(child),
+            _ => None,
+        })
+    }
+
+    pub fn take_children(&mut self, name: &str) -> Vec<GenericNode> {
+        let mut children = Vec::new();
+        for entry in &mut self.entries {
+            if matches!(entry, Entry::Child(n, _) if n == name) {
+                if let Entry::Child(_, child) =
+                    std::mem::replace(entry, Entry::TakenChild(name.to_owned()))
+                {
+                    children.push(child);
+                }
+            }
+        }
+        children
+    }
+
+    /// A key that isn't there has no position, so this points at the block
+    fn missing_key(&self, key: &str) -> VmfError {
+        VmfError::MissingKey {
+            position: self.position,
+            key: key.to_owned(),
+        }
+    }
+
+    /// Sets `key` to a single value. It goes where the key was (or was taken
+    /// from), otherwise at the end.
     pub fn set_value(&mut self, key: impl ToString, value: impl ToString) {
-        self.key_value_pairs
-            .insert(key.to_string(), vec![value.to_string()]);
+        let key = key.to_string();
+        let is_slot =
+            |e: &Entry| matches!(e, Entry::KeyValue(k, ..) | Entry::TakenValue(k, _) if *k == key);
+
+        match self.entries.iter().position(is_slot) {
+            Some(i) => {
+                // Any other values for this key go away, like they would in a map
+                let rest = self.entries.split_off(i + 1);
+                self.entries
+                    .extend(rest.into_iter().filter(|e| !is_slot(e)));
+                self.entries[i] =
+                    Entry::KeyValue(key.clone(), value.to_string(), Position::default());
+            }
+            None => self.entries.push(Entry::KeyValue(
+                key.clone(),
+                value.to_string(),
+                Position::default(),
+            )),
+        }
+    }
+
+    /// Like `set_value`, but if `value` is the same as what was originally
+    /// taken out of this slot, the original text is kept. This is what keeps
+    /// things like `"0.50"` from turning into `"0.5"` on save.
+    pub fn set_parsed<T>(&mut self, key: impl ToString, value: &T)
+    where
+        T: FromStr + PartialEq + Display,
+    {
+        let key = key.to_string();
+        let original = self.entries.iter().find_map(|e| match e {
+            Entry::TakenValue(k, original) if *k == key => Some(original),
+            _ => None,
+        });
+
+        match original {
+            Some(original) if original.parse::<T>().ok().as_ref() == Some(value) => {
+                let original = original.clone();
+                self.set_value(key, original)
+            }
+            _ => self.set_value(key, value),
+        }
+    }
+
+    /// Replaces every value for `key`, the way `set_children` does for blocks
+    pub fn set_values(&mut self, key: impl ToString, values: Vec<String>) {
+        let key = key.to_string();
+        let is_slot =
+            |e: &Entry| matches!(e, Entry::KeyValue(k, ..) | Entry::TakenValue(k, _) if *k == key);
+
+        let slots: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| is_slot(e))
+            .map(|(i, _)| i)
+            .collect();
+        let insert_at = slots.last().map_or(self.entries.len(), |i| i + 1);
+
+        let mut values = values.into_iter();
+        for &i in &slots {
+            self.entries[i] = match values.next() {
+                Some(value) => Entry::KeyValue(key.clone(), value, Position::default()),
+                None => Entry::TakenValue(key.clone(), String::new()),
+            };
+        }
+        let extra: Vec<Entry> = values
+            .map(|v| Entry::KeyValue(key.clone(), v, Position::default()))
+            .collect();
+        self.entries.splice(insert_at..insert_at, extra);
     }
 
     pub fn set_child(&mut self, name: impl ToString, child: GenericNode) {
         self.set_children(name, vec![child]);
     }
 
+    /// Replaces every child called `name`. The new children fill the spots of
+    /// the old ones in order, any extras go after the last of them.
     pub fn set_children(&mut self, name: impl ToString, children: Vec<GenericNode>) {
-        self.children_nodes.insert(name.to_string(), children);
-    }
+        let name = name.to_string();
+        let is_slot =
+            |e: &Entry| matches!(e, Entry::Child(n, _) | Entry::TakenChild(n) if *n == name);
+
+        let slots: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| is_slot(e))
+            .map(|(i, _)| i)
+            .collect();
+        let insert_at = slots.last().map_or(self.entries.len(), |i| i + 1);
 
-    pub fn to_string(&self) -> String {
-        self.to_text(0)
+        let mut children = children.into_iter();
+        for &i in &slots {
+            self.entries[i] = match children.next() {
+                Some(child) => Entry::Child(name.clone(), child),
+                None => Entry::TakenChild(name.clone()),
+            };
+        }
+        let extra: Vec<Entry> = children.map(|c| Entry::Child(name.clone(), c)).collect();
+        self.entries.splice(insert_at..insert_at, extra);
     }
 
     pub fn to_text(&self, indent_level: u32) -> String {
@@ -45,30 +291,30 @@ impl GenericNode {
             };
         }
 
-        for (key, values) in &self.key_value_pairs {
-            for value in values {
-                indent!();
-                buf += "\"";
-                buf += key;
-                buf += "\" \"";
-                buf += value;
-                buf += "\"";
-                crlf!();
-            }
-        }
-
-        for (name, nodes) in &self.children_nodes {
-            for node in nodes {
-                indent!();
-                buf += name;
-                crlf!();
-                indent!();
-                buf += "{";
-                crlf!();
-                buf += &node.to_text(indent_level + 1);
-                indent!();
-                buf += "}";
-                crlf!();
+        for entry in &self.entries {
+            match entry {
+                Entry::KeyValue(key, value, _) => {
+                    indent!();
+                    buf += "\"";
+                    buf += key;
+                    buf += "\" \"";
+                    buf += value;
+                    buf += "\"";
+                    crlf!();
+                }
+                Entry::Child(name, node) => {
+                    indent!();
+                    buf += name;
+                    crlf!();
+                    indent!();
+                    buf += "{";
+                    crlf!();
+                    buf += &node.to_text(indent_level + 1);
+                    indent!();
+                    buf += "}";
+                    crlf!();
+                }
+                Entry::TakenValue(..) | Entry::TakenChild(_) => {}
             }
         }
 
@@ -76,76 +322,207 @@ impl GenericNode {
     }
 
     pub fn new() -> Self {
-        Self {
-            key_value_pairs: HashMap::new(),
-            children_nodes: HashMap::new(),
-        }
+        Self::default()
     }
 
-    pub fn parse(input: &str) -> Result<GenericNode, String> {
-        let tmp = read_tree(input)?;
-
-        if !tmp.leftover.is_empty() {
-            Err("Leftover input: ".to_owned() + tmp.leftover)
-        } else {
-            Ok(tmp.subtree)
-        }
+    pub fn parse(input: &str) -> Result<GenericNode, VmfError> {
+        Self::parse_with(input, ParseOptions::default())
     }
-}
 
-struct ParseStep<'a> {
-    subtree: GenericNode,
-    leftover: &'a str,
-}
+    pub fn parse_with(input: &str, options: ParseOptions) -> Result<GenericNode, VmfError> {
+        let mut node = GenericNode::new();
+        node.position = Position { line: 1, column: 1 };
 
-fn read_tree(mut input: &str) -> Result<ParseStep, String> {
-    let mut node = GenericNode::new();
+        // The blocks we are inside of, with their names. `node` is the innermost one.
+        let mut parents: Vec<(String, GenericNode)> = Vec::new();
 
-    loop {
-        input = input.trim();
+        for event in EventReader::new(input, options) {
+            match event? {
+                Event::KeyValue {
+                    key,
+                    value,
+                    position,
+                } => node.entries.push(Entry::KeyValue(
+                    key.into_owned(),
+                    value.into_owned(),
+                    position,
+                )),
+                Event::BlockStart { name, position } => {
+                    let mut child = GenericNode::new();
+                    child.position = position;
+                    let parent = std::mem::replace(&mut node, child);
+                    parents.push((name.into_owned(), parent));
+                }
+                Event::BlockEnd => {
+                    // EventReader only gives us balanced blocks
+                    let (name, parent) = parents.pop().unwrap();
+                    let child = std::mem::replace(&mut node, parent);
+                    node.entries.push(Entry::Child(name, child));
+                }
+            }
+        }
+
+        Ok(node)
+    }
 
-        match input.chars().next() {
-            None => break,
-            Some('"') => {
-                // Not extremely efficient. I think I could unpack into a tuple with a crate called "itertools"
-                let split: Vec<&str> = input.splitn(5, '"').collect();
+    /// Replaces the `#include` and `#base` directives at the root of this
+    /// tree with the files they point to. `load` is handed the path exactly as
+    /// written, it is up to the caller to decide what it is relative to.
+    ///
+    /// `#include` adds everything from the other file, `#base` only adds the
+    /// keys and blocks that aren't already here. Files that keep including
+    /// each other are an error.
+    pub fn resolve_includes(
+        &mut self,
+        mut load: impl FnMut(&str) -> Result<GenericNode, VmfError>,
+    ) -> Result<(), VmfError> {
+        self.resolve_includes_with(&mut load, 0)
+    }
 
-                let key = split[1].to_owned();
-                let value = split[3].to_owned();
+    fn resolve_includes_with(
+        &mut self,
+        load: &mut dyn FnMut(&str) -> Result<GenericNode, VmfError>,
+        depth: usize,
+    ) -> Result<(), VmfError> {
+        let mut includes = Vec::new();
+        let mut bases = Vec::new();
 
-                match node.key_value_pairs.get_mut(&key) {
-                    Some(values) => values.push(value),
-                    None => {
-                        node.key_value_pairs.insert(key, vec![value]);
-                    }
-                }
+        self.entries.retain(|e| match e {
+            Entry::KeyValue(k, v, position) if k.eq_ignore_ascii_case("#include") => {
+                includes.push((v.clone(), *position));
+                false
+            }
+            Entry::KeyValue(k, v, position) if k.eq_ignore_ascii_case("#base") => {
+                bases.push((v.clone(), *position));
+                false
+            }
+            _ => true,
+        });
 
-                input = split[4];
+        if depth >= MAX_INCLUDE_DEPTH {
+            if let Some((path, position)) = includes.first().or(bases.first()) {
+                return Err(invalid_value(*position, "#include", path));
             }
-            Some('a'..='z' | 'A'..='Z') => {
-                let (name, rest) = input.split_once('{').ok_or("Expected {")?;
-                let name = name.trim();
+        }
+        for (path, _) in includes {
+            let mut included = load(&path)?;
+            included.resolve_includes_with(load, depth + 1)?;
+            self.entries.append(&mut included.entries);
+        }
+        for (path, _) in bases {
+            let mut base = load(&path)?;
+            base.resolve_includes_with(load, depth + 1)?;
+            self.merge_base(base);
+        }
 
-                let ParseStep { subtree, leftover } = read_tree(rest)?;
-                input = leftover;
+        Ok(())
+    }
 
-                match node.children_nodes.get_mut(name) {
-                    Some(children) => children.push(subtree),
-                    None => {
-                        node.children_nodes.insert(name.to_owned(), vec![subtree]);
+    fn merge_base(&mut self, base: GenericNode) {
+        for entry in base.entries {
+            match entry {
+                Entry::KeyValue(key, value, position) => {
+                    let exists = self.entries.iter().any(
+                        |e| matches!(e, Entry::KeyValue(k, ..) if k.eq_ignore_ascii_case(&key)),
+                    );
+                    if !exists {
+                        self.entries.push(Entry::KeyValue(key, value, position));
                     }
                 }
+                Entry::Child(name, child) => {
+                    let existing = self.entries.iter_mut().find_map(|e| match e {
+                        Entry::Child(n, c) if n.eq_ignore_ascii_case(&name) => Some(c),
+                        _ => None,
+                    });
+                    match existing {
+                        Some(existing) => existing.merge_base(child),
+                        None => self.entries.push(Entry::Child(name, child)),
+                    }
+                }
+                Entry::TakenValue(..) | Entry::TakenChild(_) => {}
             }
-            Some('}') => {
-                input = &input[1..];
-                break;
-            }
-            _ => unreachable!(),
         }
     }
+}
+
+fn invalid_value(position: Position, key: &str, value: &str) -> VmfError {
+    VmfError::InvalidValue {
+        position,
+        key: key.to_owned(),
+        value: value.to_owned(),
+    }
+}
 
-    Ok(ParseStep {
-        subtree: node,
-        leftover: input,
-    })
+impl Display for GenericNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_text(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(node: &GenericNode) -> Vec<(&str, &str)> {
+        node.entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::KeyValue(k, v, _) => Some((k.as_str(), v.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn includes_add_everything_and_bases_only_whats_missing() {
+        let mut root =
+            GenericNode::parse("\"#include\" \"a.txt\"\n\"#base\" \"b.txt\"\n\"key\" \"root\"\n")
+                .unwrap();
+        root.resolve_includes(|path| match path {
+            "a.txt" => GenericNode::parse("\"from_a\" \"1\"\n"),
+            "b.txt" => GenericNode::parse("\"key\" \"base\"\n\"from_b\" \"2\"\n"),
+            _ => panic!("unexpected include {path}"),
+        })
+        .unwrap();
+        assert_eq!(
+            keys(&root),
+            [("key", "root"), ("from_a", "1"), ("from_b", "2")]
+        );
+    }
+
+    #[test]
+    fn include_loops_are_an_error() {
+        let mut root = GenericNode::parse("\"#include\" \"self.txt\"\n").unwrap();
+        let mut loads = 0;
+        let result = root.resolve_includes(|_| {
+            loads += 1;
+            GenericNode::parse("\"#include\" \"self.txt\"\n")
+        });
+        assert!(result.is_err());
+        assert_eq!(loads, MAX_INCLUDE_DEPTH);
+    }
+
+    #[test]
+    fn errors_point_at_the_key() {
+        let mut node = GenericNode::parse("block\n{\n\t\"id\" \"1\"\n\t\"count\" \"many\"\n}\n")
+            .unwrap()
+            .take_child("block")
+            .unwrap();
+        assert_eq!(node.take_parsed::<u32>("id").unwrap(), 1);
+
+        let Err(VmfError::InvalidValue {
+            position, value, ..
+        }) = node.take_parsed::<u32>("count")
+        else {
+            panic!("expected an invalid value");
+        };
+        assert_eq!(position, Position { line: 4, column: 2 });
+        assert_eq!(value, "many");
+
+        // There is no key to point at, so it's the block
+        let Err(VmfError::MissingKey { position, .. }) = node.take_value("missing") else {
+            panic!("expected a missing key");
+        };
+        assert_eq!(position, Position { line: 1, column: 1 });
+    }
 }
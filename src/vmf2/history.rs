@@ -0,0 +1,520 @@
+use std::collections::VecDeque;
+
+use super::vmf::{Editor, Group, Output, Plane, Solid, Visgroup, Vmf, UV};
+
+/*
+Every edit to a map goes through a Command. Applying one hands back the
+command that puts things back the way they were, so the undo stack is just a
+list of those, and undoing one gives us what goes on the redo stack.
+
+Objects are referred to by id rather than index or reference, ids are the one
+thing that stays put while solids come and go around them.
+*/
+
+/// How many edits are kept unless something says otherwise
+pub const DEFAULT_DEPTH: usize = 100;
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Gives a face a new plane
+    MoveSide {
+        side: u32,
+        plane: Plane,
+    },
+    /// Puts a solid in the world (`owner: None`) or in a brush entity, at
+    /// `index` in its list of solids
+    CreateSolid {
+        owner: Option<u32>,
+        index: usize,
+        solid: Solid,
+    },
+    DeleteSolid {
+        solid: u32,
+    },
+    SetMaterial {
+        side: u32,
+        material: String,
+    },
+    /// Moves, scales or turns the texture on a face
+    SetFaceTexture {
+        side: u32,
+        u_axis: UV,
+        v_axis: UV,
+        rotation: f32,
+        lightmap_scale: u32,
+    },
+    /// Sets a keyvalue on an entity, `None` removes it
+    SetEntityKey {
+        entity: u32,
+        key: String,
+        value: Option<String>,
+    },
+    /// Hides or shows solids and entities, each id with whether it should be
+    /// hidden
+    SetHidden {
+        solids: Vec<(u32, bool)>,
+        entities: Vec<(u32, bool)>,
+    },
+    /// Replaces an entity's outputs, `None` removes its `connections` block
+    SetConnections {
+        entity: u32,
+        connections: Option<Vec<Output>>,
+    },
+    /// Replaces the whole visgroup tree
+    SetVisgroups {
+        visgroups: Vec<Visgroup>,
+    },
+    /// Replaces the world's list of groups
+    SetGroups {
+        groups: Vec<Group>,
+    },
+    /// Replaces a solid's `editor` block, `None` removes it
+    SetSolidEditor {
+        solid: u32,
+        editor: Option<Editor>,
+    },
+    SetEntityEditor {
+        entity: u32,
+        editor: Option<Editor>,
+    },
+    /// Several edits that are done and undone as one
+    Batch {
+        description: String,
+        commands: Vec<Command>,
+    },
+}
+
+impl Command {
+    /// What to call this in the history panel
+    pub fn description(&self) -> String {
+        match self {
+            Command::MoveSide { side, .. } => format!("Move face {side}"),
+            Command::CreateSolid { solid, .. } => format!("Create solid {}", solid.id),
+            Command::DeleteSolid { solid } => format!("Delete solid {solid}"),
+            Command::SetMaterial { side, material } => {
+                format!("Set material of face {side} to {material}")
+            }
+            Command::SetFaceTexture { side, .. } => format!("Align texture of face {side}"),
+            Command::SetEntityKey {
+                entity,
+                key,
+                value: Some(value),
+            } => format!("Set {key} of entity {entity} to \"{value}\""),
+            Command::SetEntityKey {
+                entity,
+                key,
+                value: None,
+            } => format!("Remove {key} from entity {entity}"),
+            Command::SetConnections { entity, .. } => format!("Edit outputs of entity {entity}"),
+            Command::SetHidden { solids, entities } => {
+                let count = solids.len() + entities.len();
+                let all = solids.iter().chain(entities);
+                if all.clone().all(|(_, hidden)| *hidden) {
+                    format!("Hide {count} objects")
+                } else if all.clone().all(|(_, hidden)| !hidden) {
+                    format!("Unhide {count} objects")
+                } else {
+                    format!("Hide and unhide {count} objects")
+                }
+            }
+            Command::SetVisgroups { .. } => "Edit visgroups".to_owned(),
+            Command::SetGroups { .. } => "Edit groups".to_owned(),
+            Command::SetSolidEditor { solid, .. } => format!("Edit solid {solid}"),
+            Command::SetEntityEditor { entity, .. } => format!("Edit entity {entity}"),
+            Command::Batch { description, .. } => description.clone(),
+        }
+    }
+
+    /// Makes the change and returns the command that undoes it. If the thing
+    /// it refers to doesn't exist, the map is left alone and we get `None`.
+    pub fn apply(self, vmf: &mut Vmf) -> Option<Command> {
+        match self {
+            Command::MoveSide { side, plane } => {
+                let plane = std::mem::replace(&mut vmf.side_mut(side)?.plane, plane);
+                Some(Command::MoveSide { side, plane })
+            }
+            Command::CreateSolid {
+                owner,
+                index,
+                solid,
+            } => {
+                let solids = vmf.solids_mut(owner)?;
+                let id = solid.id;
+                solids.insert(index.min(solids.len()), solid);
+                Some(Command::DeleteSolid { solid: id })
+            }
+            Command::DeleteSolid { solid } => {
+                let (owner, index) = vmf.find_solid(solid)?;
+                let solid = vmf.solids_mut(owner)?.remove(index);
+                Some(Command::CreateSolid {
+                    owner,
+                    index,
+                    solid,
+                })
+            }
+            Command::SetMaterial { side, material } => {
+                let material = std::mem::replace(&mut vmf.side_mut(side)?.material, material);
+                Some(Command::SetMaterial { side, material })
+            }
+            Command::SetFaceTexture {
+                side,
+                u_axis,
+                v_axis,
+                rotation,
+                lightmap_scale,
+            } => {
+                let s = vmf.side_mut(side)?;
+                Some(Command::SetFaceTexture {
+                    side,
+                    u_axis: std::mem::replace(&mut s.u_axis, u_axis),
+                    v_axis: std::mem::replace(&mut s.v_axis, v_axis),
+                    rotation: std::mem::replace(&mut s.rotation, rotation),
+                    lightmap_scale: std::mem::replace(&mut s.lightmap_scale, lightmap_scale),
+                })
+            }
+            Command::SetEntityKey { entity, key, value } => {
+                let e = vmf.entity_mut(entity)?;
+                let old = e.key(&key);
+                e.set_key(&key, value);
+                Some(Command::SetEntityKey {
+                    entity,
+                    key,
+                    value: old,
+                })
+            }
+            Command::SetHidden { solids, entities } => {
+                // Objects that are gone are skipped, the rest still change
+                let solids: Vec<(u32, bool)> = solids
+                    .into_iter()
+                    .filter_map(|(id, hidden)| {
+                        let (owner, i) = vmf.find_solid(id)?;
+                        let solid = &mut vmf.solids_mut(owner)?[i];
+                        Some((id, std::mem::replace(&mut solid.hidden, hidden)))
+                    })
+                    .collect();
+                let entities: Vec<(u32, bool)> = entities
+                    .into_iter()
+                    .filter_map(|(id, hidden)| {
+                        let entity = vmf.entity_mut(id)?;
+                        Some((id, std::mem::replace(&mut entity.hidden, hidden)))
+                    })
+                    .collect();
+                if solids.is_empty() && entities.is_empty() {
+                    return None;
+                }
+                Some(Command::SetHidden { solids, entities })
+            }
+            Command::SetConnections {
+                entity,
+                connections,
+            } => {
+                let e = vmf.entity_mut(entity)?;
+                Some(Command::SetConnections {
+                    entity,
+                    connections: std::mem::replace(&mut e.connections, connections),
+                })
+            }
+            Command::SetVisgroups { visgroups } => Some(Command::SetVisgroups {
+                visgroups: std::mem::replace(&mut vmf.visgroups, visgroups),
+            }),
+            Command::SetGroups { groups } => Some(Command::SetGroups {
+                groups: std::mem::replace(&mut vmf.world.groups, groups),
+            }),
+            Command::SetSolidEditor { solid, editor } => {
+                let (owner, i) = vmf.find_solid(solid)?;
+                let s = &mut vmf.solids_mut(owner)?[i];
+                Some(Command::SetSolidEditor {
+                    solid,
+                    editor: std::mem::replace(&mut s.editor, editor),
+                })
+            }
+            Command::SetEntityEditor { entity, editor } => {
+                let e = vmf.entity_mut(entity)?;
+                Some(Command::SetEntityEditor {
+                    entity,
+                    editor: std::mem::replace(&mut e.editor, editor),
+                })
+            }
+            Command::Batch {
+                description,
+                commands,
+            } => {
+                // Undone in the opposite order. Commands whose object is
+                // gone are skipped, like they are on their own.
+                let mut inverses: Vec<Command> =
+                    commands.into_iter().filter_map(|c| c.apply(vmf)).collect();
+                if inverses.is_empty() {
+                    return None;
+                }
+                inverses.reverse();
+                Some(Command::Batch {
+                    description,
+                    commands: inverses,
+                })
+            }
+        }
+    }
+
+    /// Whether this changes the same thing as `other`, so that one can stand
+    /// in for both
+    fn same_target(&self, other: &Command) -> bool {
+        match (self, other) {
+            (
+                Command::SetEntityKey { entity, key, .. },
+                Command::SetEntityKey {
+                    entity: other_entity,
+                    key: other_key,
+                    ..
+                },
+            ) => entity == other_entity && key == other_key,
+            (
+                Command::SetConnections { entity, .. },
+                Command::SetConnections { entity: other, .. },
+            ) => entity == other,
+            (Command::MoveSide { side, .. }, Command::MoveSide { side: other, .. })
+            | (Command::SetMaterial { side, .. }, Command::SetMaterial { side: other, .. })
+            | (Command::SetFaceTexture { side, .. }, Command::SetFaceTexture { side: other, .. }) => {
+                side == other
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Edit {
+    description: String,
+    /// Takes the map back to before (or, on the redo stack, after) this edit
+    inverse: Command,
+}
+
+/// The undo and redo stacks for one map
+#[derive(Debug)]
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    depth: usize,
+    /// How many edits were on the undo stack when the map was last saved,
+    /// `None` once there is no way back to that state
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEPTH)
+    }
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+            saved: Some(0),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Changes how many edits can be undone, forgetting the oldest ones if
+    /// there are now too many
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+            self.saved = self.saved.and_then(|s| s.checked_sub(1));
+        }
+        // The next redo is at the back, so the furthest ones go
+        let extra = self.redo.len().saturating_sub(self.depth);
+        self.redo.drain(..extra);
+        if self
+            .saved
+            .is_some_and(|s| s > self.undo.len() + self.redo.len())
+        {
+            self.saved = None;
+        }
+    }
+
+    /// Remembers the map as it is now as the saved one
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    /// Whether undoing and redoing got back to the map as it was saved
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    /// Applies `command` to `vmf` and remembers how to undo it. Returns false
+    /// if the command didn't apply.
+    pub fn apply(&mut self, vmf: &mut Vmf, command: Command) -> bool {
+        let description = command.description();
+        let Some(inverse) = command.apply(vmf) else {
+            return false;
+        };
+        // The saved state was one of the redos
+        if self.saved.is_some_and(|s| s > self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+        self.undo.push_back(Edit {
+            description,
+            inverse,
+        });
+        self.set_depth(self.depth);
+        true
+    }
+
+    /// Like `apply`, but if the last edit changed the same thing this one
+    /// does, the two become one edit. For changes that happen a little at a
+    /// time, like dragging a colour picker around.
+    pub fn apply_merged(&mut self, vmf: &mut Vmf, command: Command) -> bool {
+        let Some(last) = self.undo.back() else {
+            return self.apply(vmf, command);
+        };
+        if !self.redo.is_empty() || !last.inverse.same_target(&command) {
+            return self.apply(vmf, command);
+        }
+
+        let description = command.description();
+        if command.apply(vmf).is_none() {
+            return false;
+        }
+        if self.is_saved() {
+            self.saved = None;
+        }
+        // Undoing still goes back to before the first of them
+        self.undo.back_mut().unwrap().description = description;
+        true
+    }
+
+    pub fn undo(&mut self, vmf: &mut Vmf) -> bool {
+        let Some(edit) = self.undo.pop_back() else {
+            return false;
+        };
+        match edit.inverse.apply(vmf) {
+            Some(redo) => {
+                self.redo.push(Edit {
+                    description: edit.description,
+                    inverse: redo,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, vmf: &mut Vmf) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        match edit.inverse.apply(vmf) {
+            Some(undo) => {
+                self.undo.push_back(Edit {
+                    description: edit.description,
+                    inverse: undo,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.saved = self.is_saved().then_some(0);
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// What can be undone, oldest first
+    pub fn undo_descriptions(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.undo.iter().map(|e| e.description.as_str())
+    }
+
+    /// What can be redone, the next one to be redone first
+    pub fn redo_descriptions(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.redo.iter().rev().map(|e| e.description.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmf2::generic::GenericNode;
+
+    const MAP: &str = "versioninfo\r\n{\r\n\t\"editorversion\" \"400\"\r\n\t\"editorbuild\" \"8864\"\r\n\t\"mapversion\" \"1\"\r\n\t\"formatversion\" \"100\"\r\n\t\"prefab\" \"0\"\r\n}\r\nworld\r\n{\r\n\t\"id\" \"1\"\r\n\t\"classname\" \"worldspawn\"\r\n}\r\nentity\r\n{\r\n\t\"id\" \"2\"\r\n\t\"classname\" \"info_target\"\r\n}\r\n";
+
+    fn map() -> Vmf {
+        Vmf::parse(GenericNode::parse(MAP).unwrap()).unwrap()
+    }
+
+    fn set_name(name: &str) -> Command {
+        Command::SetEntityKey {
+            entity: 2,
+            key: "targetname".to_owned(),
+            value: Some(name.to_owned()),
+        }
+    }
+
+    fn name(vmf: &Vmf) -> Option<String> {
+        vmf.entity(2).unwrap().key("targetname")
+    }
+
+    #[test]
+    fn set_depth_keeps_the_nearest_redos() {
+        let mut vmf = map();
+        let mut history = History::new(10);
+        for name in ["a", "b", "c", "d"] {
+            history.apply(&mut vmf, set_name(name));
+        }
+        for _ in 0..4 {
+            history.undo(&mut vmf);
+        }
+        history.set_depth(2);
+        assert!(history.redo(&mut vmf));
+        assert_eq!(name(&vmf).as_deref(), Some("a"));
+        assert!(history.redo(&mut vmf));
+        assert_eq!(name(&vmf).as_deref(), Some("b"));
+        assert!(!history.redo(&mut vmf));
+    }
+
+    #[test]
+    fn undoing_back_to_the_save_is_saved() {
+        let mut vmf = map();
+        let mut history = History::new(10);
+        history.apply(&mut vmf, set_name("a"));
+        history.mark_saved();
+        history.apply(&mut vmf, set_name("b"));
+        assert!(!history.is_saved());
+        history.undo(&mut vmf);
+        assert!(history.is_saved());
+        history.undo(&mut vmf);
+        assert!(!history.is_saved());
+        history.redo(&mut vmf);
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn saved_state_can_be_lost() {
+        let mut vmf = map();
+        let mut history = History::new(10);
+        history.apply(&mut vmf, set_name("a"));
+        history.mark_saved();
+        history.undo(&mut vmf);
+        // A new edit throws away the redo that led to the saved map
+        history.apply(&mut vmf, set_name("b"));
+        history.undo(&mut vmf);
+        assert!(!history.is_saved());
+
+        // So does forgetting the edit before it
+        let mut history = History::new(2);
+        history.apply(&mut vmf, set_name("c"));
+        history.mark_saved();
+        history.undo(&mut vmf);
+        history.set_depth(0);
+        assert!(!history.is_saved());
+    }
+}
@@ -1,3 +1,7 @@
+pub mod error;
+pub mod events;
 pub mod generic;
+pub mod history;
 pub mod res;
+pub mod tokenizer;
 pub mod vmf;
@@ -1,38 +1,149 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::SystemTime};
 
-use bevy::prelude::*;
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
 
-use super::{generic::GenericNode, vmf::Vmf};
+use super::{
+    error::VmfError,
+    generic::GenericNode,
+    history::{Command, History},
+    vmf::Vmf,
+};
 
 #[derive(Debug, Resource, Default)]
 pub struct ActiveVmf {
     pub active: Option<Handle<VmfFile>>,
+    /// A map that is still being read in the background. It becomes `active`
+    /// once it has finished loading.
+    pub loading: Option<Handle<VmfFile>>,
 }
 
 #[derive(Asset, TypePath, Debug)]
 pub struct VmfFile {
     pub path: PathBuf,
     pub vmf: Vmf,
+    pub history: History,
+    /// The modification time of the file right after we last saved it, so
+    /// a file watcher can tell our saves from other programs' changes
+    pub saved_at: Option<SystemTime>,
 }
 
-impl VmfFile {
-    pub fn open(path: PathBuf) -> VmfFile {
-        let in_file = std::fs::read_to_string(&path).unwrap();
+/// Sent when the active map was changed by something that didn't also update
+/// the scene (undo, redo, ...), so it gets rebuilt from the map.
+#[derive(Event)]
+pub struct VmfChanged;
 
-        let generic = GenericNode::parse(&in_file).unwrap();
+impl VmfFile {
+    pub fn parse(path: PathBuf, text: &str) -> Result<VmfFile, VmfError> {
+        let generic = GenericNode::parse(text)?;
 
-        let vmf = Vmf::parse(generic);
+        let vmf = Vmf::parse(generic)?;
 
-        Self { path, vmf }
+        Ok(Self {
+            path,
+            vmf,
+            history: History::default(),
+            saved_at: None,
+        })
     }
 
-    pub fn save(&self) {
+    pub fn save(&mut self) -> std::io::Result<()> {
         // println!("{:?}",self.path);
-        std::fs::write(&self.path, self.vmf.as_generic().to_string().as_bytes()).unwrap();
+        self.save_as(self.path.clone())
     }
 
-    pub fn save_as(&mut self, path: PathBuf) {
+    /// Writes the map to `path`. It only becomes the map's path once the
+    /// write worked, so a failed Save As leaves everything as it was.
+    pub fn save_as(&mut self, path: PathBuf) -> std::io::Result<()> {
+        std::fs::write(&path, self.vmf.as_generic().to_string().as_bytes())?;
         self.path = path;
-        self.save();
+        self.saved_at = std::fs::metadata(&self.path)?.modified().ok();
+        self.history.mark_saved();
+        Ok(())
+    }
+
+    /// Whether the map is different from what was last saved (or loaded).
+    /// Undoing back to the saved map counts as no changes.
+    pub fn unsaved_changes(&self) -> bool {
+        !self.history.is_saved()
+    }
+
+    /// Makes an edit that can be undone
+    pub fn apply(&mut self, command: Command) -> bool {
+        self.history.apply(&mut self.vmf, command)
+    }
+
+    /// Makes an edit that is undone together with the last one if they
+    /// change the same thing, see `History::apply_merged`
+    pub fn apply_merged(&mut self, command: Command) -> bool {
+        self.history.apply_merged(&mut self.vmf, command)
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.history.undo(&mut self.vmf)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.history.redo(&mut self.vmf)
+    }
+}
+
+/// Reads and parses `.vmf` files on the asset server's IO threads, so opening
+/// a big map doesn't freeze the window.
+#[derive(Default)]
+pub struct VmfLoader;
+
+impl AssetLoader for VmfLoader {
+    type Asset = VmfFile;
+    type Settings = ();
+    type Error = VmfError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<VmfFile, VmfError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let text = String::from_utf8(bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+            VmfFile::parse(load_context.path().to_owned(), &text)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vmf"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "versioninfo\r\n{\r\n\t\"editorversion\" \"400\"\r\n\t\"editorbuild\" \"8864\"\r\n\t\"mapversion\" \"1\"\r\n\t\"formatversion\" \"100\"\r\n\t\"prefab\" \"0\"\r\n}\r\nworld\r\n{\r\n\t\"id\" \"1\"\r\n\t\"classname\" \"worldspawn\"\r\n}\r\n";
+
+    #[test]
+    fn failed_save_as_keeps_the_path() {
+        let dir = std::env::temp_dir().join(format!("chisel-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut file = VmfFile::parse(dir.join("a.vmf"), MAP).unwrap();
+
+        let missing = dir.join("missing").join("b.vmf");
+        assert!(file.save_as(missing).is_err());
+        assert_eq!(file.path, dir.join("a.vmf"));
+        assert_eq!(file.saved_at, None);
+
+        file.save_as(dir.join("b.vmf")).unwrap();
+        let written = std::fs::read_to_string(dir.join("b.vmf"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(file.path, dir.join("b.vmf"));
+        assert!(file.saved_at.is_some());
+        assert_eq!(written.unwrap(), MAP);
     }
 }
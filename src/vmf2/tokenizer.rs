@@ -0,0 +1,363 @@
+use std::borrow::Cow;
+
+use super::error::{Position, VmfError};
+
+/*
+KeyValues1, as read by the Source engine:
+
+    // comments run to the end of the line
+    "quoted key" "quoted value"
+    unquoted_key unquoted_value
+    "key" "value" [$WIN32]          <- only kept if the conditional is true
+    block_name [!$X360] { ... }
+    #include "other_file.txt"
+    #base "other_file.txt"
+
+Escape sequences (\n \t \\ \") are only understood when asked for, since
+VMTs and VMFs are full of paths like "brick\brickwall001".
+*/
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ParseOptions {
+    pub escapes: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Token<'a> {
+    /// Quoted or not, both mean the same thing
+    String(Cow<'a, str>),
+    OpenBrace,
+    CloseBrace,
+    /// The expression between the `[` `]`
+    Conditional(&'a str),
+}
+
+pub struct Tokenizer<'a> {
+    rest: &'a str,
+    position: Position,
+    options: ParseOptions,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str, options: ParseOptions) -> Self {
+        Self {
+            rest: input,
+            position: Position { line: 1, column: 1 },
+            options,
+        }
+    }
+
+    pub fn error(&self, message: impl ToString) -> VmfError {
+        VmfError::Syntax {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    /// Consumes `len` bytes, keeping track of the line and column as we go.
+    /// Every byte is only ever looked at once here, which keeps the whole
+    /// parse linear in the size of the input.
+    fn advance(&mut self, len: usize) -> &'a str {
+        let (taken, rest) = self.rest.split_at(len);
+        for &b in taken.as_bytes() {
+            if b == b'\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else if b & 0xC0 != 0x80 {
+                // Only count the first byte of each UTF-8 character
+                self.position.column += 1;
+            }
+        }
+        self.rest = rest;
+        taken
+    }
+
+    /// Skips whitespace and `//` comments
+    fn skip_trivia(&mut self) {
+        loop {
+            let len = self.rest.len() - self.rest.trim_start().len();
+            self.advance(len);
+
+            if self.rest.starts_with("//") {
+                let len = self.rest.find('\n').unwrap_or(self.rest.len());
+                self.advance(len);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// The next token and where it starts, or `None` at the end of the input
+    pub fn next_token(&mut self) -> Result<Option<(Token<'a>, Position)>, VmfError> {
+        self.skip_trivia();
+        let position = self.position;
+
+        let token = match self.rest.chars().next() {
+            None => return Ok(None),
+            Some('{') => {
+                self.advance(1);
+                Token::OpenBrace
+            }
+            Some('}') => {
+                self.advance(1);
+                Token::CloseBrace
+            }
+            Some('[') => {
+                let Some(end) = self.rest.find(']') else {
+                    return Err(self.error("unterminated conditional"));
+                };
+                let expr = &self.advance(end + 1)[1..end];
+                Token::Conditional(expr.trim())
+            }
+            Some('"') => Token::String(self.read_quoted()?),
+            Some(_) => {
+                let len = self
+                    .rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '"'))
+                    .unwrap_or(self.rest.len());
+                Token::String(Cow::Borrowed(self.advance(len)))
+            }
+        };
+
+        Ok(Some((token, position)))
+    }
+
+    /// Consumes a `[...]` conditional if that is what comes next
+    pub fn next_conditional(&mut self) -> Option<&'a str> {
+        self.skip_trivia();
+        if self.rest.starts_with('[') {
+            match self.next_token() {
+                Ok(Some((Token::Conditional(expr), _))) => Some(expr),
+                // an unterminated `[` is left for next_token to complain about
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn read_quoted(&mut self) -> Result<Cow<'a, str>, VmfError> {
+        let start = self.position;
+        self.advance(1);
+
+        if !self.options.escapes {
+            return match self.rest.find('"') {
+                Some(end) => {
+                    let s = self.advance(end);
+                    self.advance(1);
+                    Ok(Cow::Borrowed(s))
+                }
+                None => Err(VmfError::Syntax {
+                    position: start,
+                    message: "unterminated string".to_owned(),
+                }),
+            };
+        }
+
+        let mut out = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.advance(i + 1);
+                    return Ok(Cow::Owned(out));
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, 'v')) => out.push('\u{0B}'),
+                    Some((_, 'b')) => out.push('\u{08}'),
+                    Some((_, 'r')) => out.push('\r'),
+                    Some((_, 'f')) => out.push('\u{0C}'),
+                    Some((_, 'a')) => out.push('\u{07}'),
+                    Some((_, '\\')) => out.push('\\'),
+                    Some((_, '?')) => out.push('?'),
+                    Some((_, '\'')) => out.push('\''),
+                    Some((_, '"')) => out.push('"'),
+                    // Unknown escapes are kept as they were, like Valve's reader does
+                    Some((_, other)) => {
+                        out.push('\\');
+                        out.push(other);
+                    }
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+
+        Err(VmfError::Syntax {
+            position: start,
+            message: "unterminated string".to_owned(),
+        })
+    }
+}
+
+/// Evaluates a conditional like `$WIN32`, `!$X360` or `$WINDOWS || $OSX` for
+/// the platform we are running on.
+pub fn evaluate_conditional(expr: &str) -> bool {
+    expr.split("||").any(|all| {
+        all.split("&&").all(|term| {
+            let term = term.trim();
+            match term.strip_prefix('!') {
+                Some(term) => !platform_define(term.trim()),
+                None => platform_define(term),
+            }
+        })
+    })
+}
+
+fn platform_define(name: &str) -> bool {
+    match name.to_ascii_uppercase().as_str() {
+        // Like the engine, $WIN32 really means "any PC"
+        "$WIN32" => true,
+        "$WIN64" => cfg!(all(windows, target_pointer_width = "64")),
+        "$WINDOWS" => cfg!(windows),
+        "$OSX" => cfg!(target_os = "macos"),
+        "$LINUX" => cfg!(target_os = "linux"),
+        "$POSIX" => cfg!(unix),
+        // $X360, $PS3, $GAMECONSOLE, $DECK, ...
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str, options: ParseOptions) -> Result<Vec<Token<'_>>, VmfError> {
+        let mut tokenizer = Tokenizer::new(input, options);
+        let mut tokens = Vec::new();
+        while let Some((token, _)) = tokenizer.next_token()? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    fn strings(input: &str, options: ParseOptions) -> Vec<String> {
+        tokens(input, options)
+            .unwrap()
+            .into_iter()
+            .map(|t| match t {
+                Token::String(s) => s.into_owned(),
+                other => panic!("expected a string, found {other:?}"),
+            })
+            .collect()
+    }
+
+    const ESCAPES: ParseOptions = ParseOptions { escapes: true };
+
+    #[test]
+    fn quoted_and_unquoted() {
+        assert_eq!(
+            tokens(
+                "\"a key\" value\nblock{\"x\"\"y\"}",
+                ParseOptions::default()
+            )
+            .unwrap(),
+            [
+                Token::String("a key".into()),
+                Token::String("value".into()),
+                Token::String("block".into()),
+                Token::OpenBrace,
+                Token::String("x".into()),
+                Token::String("y".into()),
+                Token::CloseBrace,
+            ]
+        );
+        assert_eq!(strings("\"\" \"\"", ParseOptions::default()), ["", ""]);
+    }
+
+    #[test]
+    fn comments() {
+        let input = "// a comment\n\"key\" \"value\" // another\n// last line";
+        assert_eq!(strings(input, ParseOptions::default()), ["key", "value"]);
+        // Only outside of quotes
+        assert_eq!(
+            strings("\"http://example.com\"", ParseOptions::default()),
+            ["http://example.com"]
+        );
+    }
+
+    #[test]
+    fn escapes_only_when_asked_for() {
+        // \w isn't an escape, so it stays as it is
+        let input = r#""brick\wall001" "a\"b\\c\nd\qe""#;
+        assert_eq!(
+            strings(input, ESCAPES),
+            ["brick\\wall001", "a\"b\\c\nd\\qe"]
+        );
+
+        let input = r#""brick\brickwall001" "a\nb""#;
+        assert_eq!(
+            strings(input, ParseOptions::default()),
+            ["brick\\brickwall001", "a\\nb"]
+        );
+        // Without escapes a backslash doesn't hide the closing quote
+        assert_eq!(
+            strings(r#""a\" "b""#, ParseOptions::default()),
+            ["a\\", "b"]
+        );
+    }
+
+    #[test]
+    fn strings_are_borrowed_without_escapes() {
+        let tokens = tokens("\"quoted\" unquoted", ParseOptions::default()).unwrap();
+        assert!(matches!(tokens[0], Token::String(Cow::Borrowed(_))));
+        assert!(matches!(tokens[1], Token::String(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(
+            tokens("\"a\" \"b\" [ $WIN32 ]", ParseOptions::default()).unwrap(),
+            [
+                Token::String("a".into()),
+                Token::String("b".into()),
+                Token::Conditional("$WIN32"),
+            ]
+        );
+        assert!(tokens("\"a\" \"b\" [$WIN32", ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn evaluates_conditionals() {
+        assert!(evaluate_conditional("$WIN32"));
+        assert!(evaluate_conditional("$win32"));
+        assert!(!evaluate_conditional("$X360"));
+        assert!(evaluate_conditional("!$X360"));
+        assert!(evaluate_conditional("! $X360"));
+        assert!(!evaluate_conditional("!$WIN32"));
+        assert!(evaluate_conditional("$X360 || $WIN32"));
+        assert!(!evaluate_conditional("$X360 || $PS3"));
+        assert!(evaluate_conditional("$WIN32 && !$X360"));
+        assert!(!evaluate_conditional("$WIN32 && $X360"));
+        assert_eq!(evaluate_conditional("$LINUX"), cfg!(target_os = "linux"));
+        assert!(evaluate_conditional("$POSIX || $WINDOWS"));
+    }
+
+    #[test]
+    fn unterminated_strings_point_at_the_quote() {
+        for options in [ParseOptions::default(), ESCAPES] {
+            let Err(VmfError::Syntax { position, message }) =
+                tokens("\"key\"\n  \"never ends", options)
+            else {
+                panic!("expected a syntax error");
+            };
+            assert_eq!(position, Position { line: 2, column: 3 });
+            assert_eq!(message, "unterminated string");
+        }
+        // A backslash at the very end can't escape anything
+        assert!(tokens("\"ends in \\", ESCAPES).is_err());
+    }
+
+    #[test]
+    fn positions() {
+        let mut tokenizer = Tokenizer::new("a\n\t\"é\" b\r\n  {", ParseOptions::default());
+        let mut positions = Vec::new();
+        while let Some((_, position)) = tokenizer.next_token().unwrap() {
+            positions.push((position.line, position.column));
+        }
+        // Columns count characters, not bytes
+        assert_eq!(positions, [(1, 1), (2, 2), (2, 6), (3, 3)]);
+    }
+}
@@ -1,53 +1,182 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
-use bevy::prelude::Vec3;
+use bevy::math::{DQuat, DVec3, Vec3};
 
-use super::generic::GenericNode;
+use super::{
+    error::{Position, VmfError},
+    generic::{Entry, GenericNode},
+};
 
 /*
-TODO:
+Hammer wraps hidden solids and entities in a block of their own, in the
+place the solid or entity would otherwise be:
 
-Hidden Solids
-Entities
-Hidden Entities
+    world
+    {
+        solid { ... }
+        hidden
+        {
+            solid { ... }
+        }
+    }
+    hidden
+    {
+        entity { ... }
+    }
 
+They're read into the same lists as everything else, with `hidden` set.
 */
 
 #[derive(Debug)]
 pub struct Vmf {
     pub version_info: VersionInfo,
+    /// The user's visgroups, the top level of the tree
+    pub visgroups: Vec<Visgroup>,
     pub world: World,
+    pub entities: Vec<Entity>,
     pub rest: GenericNode,
 }
 
 impl Vmf {
-    pub fn parse(mut g: GenericNode) -> Self {
-        let version_info = g
-            .children_nodes
-            .remove("versioninfo")
-            .unwrap()
-            .pop()
-            .unwrap();
-        let world = g.children_nodes.remove("world").unwrap().pop().unwrap();
-
-        let version_info = VersionInfo::parse(version_info);
-        let world = World::parse(world);
+    pub fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let version_info = VersionInfo::parse(g.take_child("versioninfo")?)?;
+        let visgroups = match g.try_take_child("visgroups") {
+            Some(mut v) => Visgroup::parse_all(&mut v)?,
+            None => Vec::new(),
+        };
+        let world = World::parse(g.take_child("world")?)?;
+        let entities = parse_with_hidden(&mut g, "entity", Entity::parse, |e| {
+            e.hidden = true;
+        })?;
 
-        Self {
+        Ok(Self {
             version_info,
+            visgroups,
             world,
+            entities,
             rest: g,
-        }
+        })
     }
 
     pub fn as_generic(&self) -> GenericNode {
         let mut g = self.rest.clone();
 
         g.set_child("versioninfo", self.version_info.as_generic());
+        let had_visgroups = g
+            .entries
+            .iter()
+            .any(|e| matches!(e, Entry::TakenChild(n) if n == "visgroups"));
+        if had_visgroups || !self.visgroups.is_empty() {
+            let mut visgroups = GenericNode::new();
+            Visgroup::set_all(&mut visgroups, &self.visgroups);
+            g.set_child("visgroups", visgroups);
+        }
         g.set_child("world", self.world.as_generic());
+        set_with_hidden(&mut g, "entity", &self.entities, |e| {
+            (e.as_generic(), e.hidden)
+        });
 
         g
     }
+
+    /// The solids of the world (`None`) or of the brush entity with id `owner`
+    pub fn solids_mut(&mut self, owner: Option<u32>) -> Option<&mut Vec<Solid>> {
+        match owner {
+            None => Some(&mut self.world.solids),
+            Some(id) => self.entity_mut(id).map(|e| &mut e.solids),
+        }
+    }
+
+    /// Where the solid with id `id` lives: its owner (see `solids_mut`) and index
+    pub fn find_solid(&self, id: u32) -> Option<(Option<u32>, usize)> {
+        let owners = std::iter::once((None, &self.world.solids))
+            .chain(self.entities.iter().map(|e| (Some(e.id), &e.solids)));
+        for (owner, solids) in owners {
+            if let Some(i) = solids.iter().position(|s| s.id == id) {
+                return Some((owner, i));
+            }
+        }
+        None
+    }
+
+    pub fn solid(&self, id: u32) -> Option<&Solid> {
+        match self.find_solid(id)? {
+            (None, i) => self.world.solids.get(i),
+            (Some(owner), i) => self.entity(owner)?.solids.get(i),
+        }
+    }
+
+    /// Every visgroup in the tree, parents before their children
+    pub fn all_visgroups(&self) -> Vec<&Visgroup> {
+        fn walk<'a>(groups: &'a [Visgroup], out: &mut Vec<&'a Visgroup>) {
+            for group in groups {
+                out.push(group);
+                walk(&group.children, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(&self.visgroups, &mut out);
+        out
+    }
+
+    pub fn group(&self, id: u32) -> Option<&Group> {
+        self.world.groups.iter().find(|g| g.id == id)
+    }
+
+    /// The outermost group something with this `editor` block is in, going
+    /// up through the groups groups are in
+    pub fn top_group(&self, editor: Option<&Editor>) -> Option<u32> {
+        let mut id = editor?.group_id?;
+        // Bounded, in case a broken file has groups that are in each other
+        for _ in 0..self.world.groups.len() {
+            match self.group(id).and_then(|g| g.editor.as_ref()?.group_id) {
+                Some(parent) => id = parent,
+                None => break,
+            }
+        }
+        Some(id)
+    }
+
+    /// An id no solid, entity or group has yet. Hammer counts these three
+    /// together, sides have their own ids.
+    pub fn next_object_id(&self) -> u32 {
+        let solids = self
+            .world
+            .solids
+            .iter()
+            .chain(self.entities.iter().flat_map(|e| &e.solids))
+            .map(|s| s.id);
+        let entities = self.entities.iter().map(|e| e.id);
+        let groups = self.world.groups.iter().map(|g| g.id);
+        solids.chain(entities).chain(groups).max().unwrap_or(1) + 1
+    }
+
+    pub fn entity(&self, id: u32) -> Option<&Entity> {
+        self.entities.iter().find(|e| e.id == id)
+    }
+
+    pub fn entity_mut(&mut self, id: u32) -> Option<&mut Entity> {
+        self.entities.iter_mut().find(|e| e.id == id)
+    }
+
+    pub fn side(&self, id: u32) -> Option<&Side> {
+        self.world
+            .solids
+            .iter()
+            .chain(self.entities.iter().flat_map(|e| e.solids.iter()))
+            .flat_map(|s| s.sides.iter())
+            .find(|s| s.id == id)
+    }
+
+    pub fn side_mut(&mut self, id: u32) -> Option<&mut Side> {
+        self.world
+            .solids
+            .iter_mut()
+            .chain(self.entities.iter_mut().flat_map(|e| e.solids.iter_mut()))
+            .flat_map(|s| s.sides.iter_mut())
+            .find(|s| s.id == id)
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +186,7 @@ pub struct VersionInfo {
     pub map_version: u32,
     pub format_version: u32,
     pub prefab: u32,
+    pub rest: GenericNode,
 }
 
 /*
@@ -67,24 +197,25 @@ I need to rename a lot of things, and find out if I can do the macro thing
 */
 
 impl VersionInfo {
-    fn parse(g: GenericNode) -> Self {
-        Self {
-            editor_version: g.get_value("editorversion").parse().unwrap(),
-            editor_build: g.get_value("editorbuild").parse().unwrap(),
-            map_version: g.get_value("mapversion").parse().unwrap(),
-            format_version: g.get_value("formatversion").parse().unwrap(),
-            prefab: g.get_value("prefab").parse().unwrap(),
-        }
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        Ok(Self {
+            editor_version: g.take_parsed("editorversion")?,
+            editor_build: g.take_parsed("editorbuild")?,
+            map_version: g.take_parsed("mapversion")?,
+            format_version: g.take_parsed("formatversion")?,
+            prefab: g.take_parsed("prefab")?,
+            rest: g,
+        })
     }
 
     fn as_generic(&self) -> GenericNode {
-        let mut g = GenericNode::new();
+        let mut g = self.rest.clone();
 
-        g.set_value("editorversion", self.editor_version);
-        g.set_value("editorbuild", self.editor_build);
-        g.set_value("mapversion", self.map_version);
-        g.set_value("formatversion", self.format_version);
-        g.set_value("prefab", self.prefab);
+        g.set_parsed("editorversion", &self.editor_version);
+        g.set_parsed("editorbuild", &self.editor_build);
+        g.set_parsed("mapversion", &self.map_version);
+        g.set_parsed("formatversion", &self.format_version);
+        g.set_parsed("prefab", &self.prefab);
 
         g
     }
@@ -93,65 +224,596 @@ impl VersionInfo {
 #[derive(Debug)]
 pub struct World {
     pub solids: Vec<Solid>,
+    pub groups: Vec<Group>,
     pub rest: GenericNode,
 }
 
 impl World {
-    fn parse(mut g: GenericNode) -> Self {
-        let solids = g.children_nodes.remove("solid").unwrap();
-        let solids = solids.into_iter().map(Solid::parse).collect();
-        Self { solids, rest: g }
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let solids = Solid::parse_all(&mut g)?;
+        let groups = g
+            .take_children("group")
+            .into_iter()
+            .map(Group::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            solids,
+            groups,
+            rest: g,
+        })
+    }
+    fn as_generic(&self) -> GenericNode {
+        let mut g = self.rest.clone();
+
+        Solid::set_all(&mut g, &self.solids);
+        g.set_children("group", self.groups.iter().map(Group::as_generic).collect());
+
+        g
+    }
+}
+
+/// A group made with Hammer's Ctrl+G. Groups only have an id, solids,
+/// entities and other groups are put in one by the `groupid` in their
+/// `editor` block.
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub id: u32,
+    pub editor: Option<Editor>,
+    pub rest: GenericNode,
+}
+
+impl Group {
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            editor: Some(Editor::default()),
+            rest: GenericNode::new(),
+        }
+    }
+
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let id = g.take_parsed("id")?;
+        let editor = g.try_take_child("editor").map(Editor::parse).transpose()?;
+        Ok(Self {
+            id,
+            editor,
+            rest: g,
+        })
+    }
+
+    fn as_generic(&self) -> GenericNode {
+        let mut g = self.rest.clone();
+
+        g.set_parsed("id", &self.id);
+        if let Some(editor) = &self.editor {
+            g.set_child("editor", editor.as_generic());
+        }
+
+        g
+    }
+}
+
+/// A point or brush entity. Brush entities (func_detail, trigger_*, ...) own
+/// their solids, point entities have an origin instead.
+///
+/// Only the keys the editor cares about are pulled out, every other keyvalue
+/// (targetname, spawnflags, ...) stays in `rest`.
+#[derive(Clone, Debug)]
+pub struct Entity {
+    pub id: u32,
+    pub classname: String,
+    pub origin: Option<Point>,
+    pub angles: Option<Angles>,
+    pub solids: Vec<Solid>,
+    /// The `connections` block, `None` if the entity doesn't have one
+    pub connections: Option<Vec<Output>>,
+    pub editor: Option<Editor>,
+    /// Hidden in the editor
+    pub hidden: bool,
+    pub rest: GenericNode,
+}
+
+impl Entity {
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let id = g.take_parsed("id")?;
+        // Left empty when missing, the problem checker reports it
+        let classname = g.try_take_value("classname").unwrap_or_default();
+        let origin = g.try_take_with("origin", |s| s.parse().ok())?;
+        let angles = g.try_take_with("angles", |s| s.parse().ok())?;
+        let solids = Solid::parse_all(&mut g)?;
+        let connections = g.try_take_child("connections").map(Output::parse_block);
+        let editor = g.try_take_child("editor").map(Editor::parse).transpose()?;
+
+        Ok(Self {
+            id,
+            classname,
+            origin,
+            angles,
+            solids,
+            connections,
+            editor,
+            hidden: false,
+            rest: g,
+        })
+    }
+
+    fn as_generic(&self) -> GenericNode {
+        let mut g = self.rest.clone();
+
+        g.set_parsed("id", &self.id);
+        if !self.classname.is_empty() || g.had_value("classname") {
+            g.set_value("classname", &self.classname);
+        }
+        if let Some(origin) = &self.origin {
+            g.set_parsed("origin", origin);
+        }
+        if let Some(angles) = &self.angles {
+            g.set_parsed("angles", angles);
+        }
+        Solid::set_all(&mut g, &self.solids);
+        if let Some(connections) = &self.connections {
+            g.set_child("connections", Output::as_block(connections));
+        }
+        if let Some(editor) = &self.editor {
+            g.set_child("editor", editor.as_generic());
+        }
+
+        g
+    }
+
+    /// The value of a keyvalue, whether or not it has been parsed into a field
+    pub fn key(&self, key: &str) -> Option<String> {
+        match key {
+            "id" => Some(self.id.to_string()),
+            "classname" => Some(self.classname.clone()),
+            "origin" if self.origin.is_some() => self.origin.as_ref().map(|o| o.to_string()),
+            "angles" if self.angles.is_some() => self.angles.as_ref().map(|a| a.to_string()),
+            _ => self.rest.get_value(key).map(str::to_owned),
+        }
+    }
+
+    /// Not hidden by a visgroup
+    pub fn visgroup_shown(&self) -> bool {
+        self.editor.as_ref().is_none_or(Editor::shown)
+    }
+
+    /// Every keyvalue but the id, in the order they are in the file
+    pub fn keys(&self) -> Vec<(String, String)> {
+        let mut keys: Vec<(String, String)> = self
+            .rest
+            .entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::KeyValue(k, v, _) => Some((k.clone(), v.clone())),
+                Entry::TakenValue(k, _) if k != "id" => self.key(k).map(|v| (k.clone(), v)),
+                _ => None,
+            })
+            .collect();
+        // Typed keys set after loading have no slot yet
+        for key in ["classname", "origin", "angles"] {
+            if let Some(value) = self
+                .key(key)
+                .filter(|v| !v.is_empty() && !keys.iter().any(|(k, _)| k == key))
+            {
+                keys.push((key.to_owned(), value));
+            }
+        }
+        keys
+    }
+
+    /// Sets or, with `None`, removes a keyvalue. Values that don't parse for
+    /// a typed key are kept as plain text so nothing the user typed is lost.
+    pub fn set_key(&mut self, key: &str, value: Option<String>) {
+        match (key, value) {
+            ("id", _) => {}
+            ("classname", value) => self.classname = value.unwrap_or_default(),
+            ("origin", Some(value)) if value.parse::<Point>().is_ok() => {
+                self.origin = value.parse().ok();
+                self.rest.remove_value(key);
+            }
+            ("angles", Some(value)) if value.parse::<Angles>().is_ok() => {
+                self.angles = value.parse().ok();
+                self.rest.remove_value(key);
+            }
+            (key, value) => {
+                match key {
+                    "origin" => self.origin = None,
+                    "angles" => self.angles = None,
+                    _ => {}
+                }
+                match value {
+                    Some(value) => self.rest.set_value(key, value),
+                    None => self.rest.remove_value(key),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Angles {
+    pub pitch: f32,
+    pub yaw: f32,
+    pub roll: f32,
+}
+
+impl FromStr for Angles {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [pitch, yaw, roll] = parse_floats(s).ok_or(())?;
+        Ok(Self { pitch, yaw, roll })
+    }
+}
+
+impl Display for Angles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.pitch, self.yaw, self.roll)
+    }
+}
+
+/// An entry in an entity's `connections`: when `output` fires, `input` is
+/// called on every entity named `target`.
+///
+/// ```text
+/// "OnTrigger" "door,Open,,0,-1"
+/// ```
+///
+/// Newer games separate the fields with `\x1b` instead of commas, so that a
+/// parameter can have commas in it.
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub output: String,
+    pub target: String,
+    pub input: String,
+    pub parameter: String,
+    /// In seconds
+    pub delay: f32,
+    /// How many times this can fire, -1 for no limit
+    pub times: i32,
+    /// `,` or `\x1b`, whichever the value was written with
+    pub separator: char,
+    /// The value as it was read, written back as long as nothing changed
+    original: Option<String>,
+}
+
+impl Output {
+    pub fn new(
+        output: impl Into<String>,
+        target: impl Into<String>,
+        input: impl Into<String>,
+    ) -> Self {
+        Self {
+            output: output.into(),
+            target: target.into(),
+            input: input.into(),
+            parameter: String::new(),
+            delay: 0.0,
+            times: -1,
+            separator: ',',
+            original: None,
+        }
+    }
+
+    /// Reads the value of a `connections` entry, `None` if it doesn't have
+    /// the five fields
+    pub fn parse(output: &str, value: &str) -> Option<Self> {
+        let separator = if value.contains('\x1b') { '\x1b' } else { ',' };
+        let fields: Vec<&str> = value.split(separator).collect();
+        let [target, input, .., delay, times] = fields[..] else {
+            return None;
+        };
+        if fields.len() < 5 {
+            return None;
+        }
+        Some(Self {
+            output: output.to_owned(),
+            target: target.to_owned(),
+            input: input.to_owned(),
+            // Old maps can have commas in the parameter anyway
+            parameter: fields[2..fields.len() - 2].join(&separator.to_string()),
+            delay: delay.trim().parse().ok()?,
+            times: times.trim().parse().ok()?,
+            separator,
+            original: Some(value.to_owned()),
+        })
+    }
+
+    /// Like `parse`, but an entry that doesn't parse (too few fields, a
+    /// delay that isn't a number, ...) keeps what fields it can. It is
+    /// written back as it was until it's edited, see `is_malformed`.
+    fn parse_lenient(output: &str, value: &str) -> Self {
+        Self::parse(output, value).unwrap_or_else(|| {
+            let separator = if value.contains('\x1b') { '\x1b' } else { ',' };
+            let mut fields = value.split(separator);
+            let mut field = || fields.next().unwrap_or_default().to_owned();
+            let (target, input, parameter) = (field(), field(), field());
+            Self {
+                parameter,
+                separator,
+                original: Some(value.to_owned()),
+                ..Self::new(output, target, input)
+            }
+        })
+    }
+
+    /// Read from an entry that didn't parse, and not edited since
+    pub fn is_malformed(&self) -> bool {
+        self.original.as_deref().is_some_and(|original| {
+            Self::parse(&self.output, original).is_none()
+                && Self::parse_lenient(&self.output, original).same_as(self)
+        })
+    }
+
+    /// The value as it was read, for entries that didn't parse
+    pub fn original(&self) -> Option<&str> {
+        self.original.as_deref()
+    }
+
+    /// The value to write after the output's name
+    pub fn value(&self) -> String {
+        if let Some(original) = &self.original {
+            if Self::parse_lenient(&self.output, original).same_as(self) {
+                return original.clone();
+            }
+        }
+        let s = self.separator;
+        format!(
+            "{}{s}{}{s}{}{s}{}{s}{}",
+            self.target, self.input, self.parameter, self.delay, self.times
+        )
+    }
+
+    /// Whether the two do the same thing, however they were written
+    pub fn same_as(&self, other: &Output) -> bool {
+        self.output == other.output
+            && self.target == other.target
+            && self.input == other.input
+            && self.parameter == other.parameter
+            && self.delay == other.delay
+            && self.times == other.times
+            && self.separator == other.separator
+    }
+
+    /// Every entry of a `connections` block. Ones that don't parse are kept
+    /// too, so one bad output doesn't stop the map from opening.
+    fn parse_block(g: GenericNode) -> Vec<Self> {
+        g.entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::KeyValue(k, v, _) => Some(Self::parse_lenient(k, v)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn as_block(outputs: &[Self]) -> GenericNode {
+        let mut g = GenericNode::new();
+        // Not set_value, an output can be in here any number of times
+        g.entries = outputs
+            .iter()
+            .map(|o| Entry::KeyValue(o.output.clone(), o.value(), Position::default()))
+            .collect();
+        g
+    }
+}
+
+/// A visgroup from the `visgroups` block, which can have visgroups in it
+///
+/// ```text
+/// visgroup
+/// {
+///     "name" "Lights"
+///     "visgroupid" "1"
+///     "color" "97 220 186"
+///     visgroup { ... }
+/// }
+/// ```
+///
+/// Objects say which visgroups they're in, in their `editor` block.
+#[derive(Clone, Debug)]
+pub struct Visgroup {
+    pub id: u32,
+    pub name: String,
+    pub color: [u8; 3],
+    pub children: Vec<Visgroup>,
+    pub rest: GenericNode,
+}
+
+impl Visgroup {
+    pub fn new(id: u32, name: impl Into<String>, color: [u8; 3]) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            color,
+            children: Vec::new(),
+            rest: GenericNode::new(),
+        }
+    }
+
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let name = g.take_value("name")?;
+        let id = g.take_parsed("visgroupid")?;
+        let color = g
+            .try_take_with("color", parse_floats)?
+            .unwrap_or([255, 255, 255]);
+        let children = Self::parse_all(&mut g)?;
+        Ok(Self {
+            id,
+            name,
+            color,
+            children,
+            rest: g,
+        })
     }
+
+    fn parse_all(g: &mut GenericNode) -> Result<Vec<Self>, VmfError> {
+        g.take_children("visgroup")
+            .into_iter()
+            .map(Self::parse)
+            .collect()
+    }
+
     fn as_generic(&self) -> GenericNode {
         let mut g = self.rest.clone();
 
+        g.set_value("name", &self.name);
+        g.set_parsed("visgroupid", &self.id);
+        let [r, gr, b] = self.color;
+        g.set_value("color", format!("{r} {gr} {b}"));
+        Self::set_all(&mut g, &self.children);
+
+        g
+    }
+
+    fn set_all(g: &mut GenericNode, visgroups: &[Self]) {
         g.set_children(
-            "solid",
-            self.solids.iter().map(|s| s.as_generic()).collect(),
+            "visgroup",
+            visgroups.iter().map(|v| v.as_generic()).collect(),
+        );
+    }
+
+    /// This visgroup's id and those of everything in it
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids = vec![self.id];
+        for child in &self.children {
+            ids.extend(child.ids());
+        }
+        ids
+    }
+}
+
+/// The `editor` block of a solid or entity: things only the editor cares
+/// about, like which visgroups it's in
+#[derive(Clone, Debug)]
+pub struct Editor {
+    /// The group it's in, if any
+    pub group_id: Option<u32>,
+    pub visgroup_ids: Vec<u32>,
+    /// Not hidden by one of its visgroups
+    pub visgroup_shown: bool,
+    /// Not hidden by an automatic visgroup
+    pub visgroup_auto_shown: bool,
+    pub rest: GenericNode,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            group_id: None,
+            visgroup_ids: Vec::new(),
+            visgroup_shown: true,
+            visgroup_auto_shown: true,
+            rest: GenericNode::new(),
+        }
+    }
+}
+
+impl Editor {
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let group_id = g.try_take_with("groupid", |s| s.trim().parse().ok())?;
+        let mut visgroup_ids = Vec::new();
+        while let Some(id) = g.try_take_with("visgroupid", |s| s.trim().parse().ok())? {
+            visgroup_ids.push(id);
+        }
+        let flag = |s: &str| s.trim().parse::<u8>().ok().map(|v| v != 0);
+        let visgroup_shown = g.try_take_with("visgroupshown", flag)?.unwrap_or(true);
+        let visgroup_auto_shown = g.try_take_with("visgroupautoshown", flag)?.unwrap_or(true);
+        Ok(Self {
+            group_id,
+            visgroup_ids,
+            visgroup_shown,
+            visgroup_auto_shown,
+            rest: g,
+        })
+    }
+
+    fn as_generic(&self) -> GenericNode {
+        let mut g = self.rest.clone();
+
+        if let Some(group_id) = &self.group_id {
+            g.set_parsed("groupid", group_id);
+        }
+        g.set_values(
+            "visgroupid",
+            self.visgroup_ids.iter().map(u32::to_string).collect(),
         );
+        // Hammer always writes these, but there's no need to add them to a
+        // block that didn't have them
+        for (key, value) in [
+            ("visgroupshown", self.visgroup_shown),
+            ("visgroupautoshown", self.visgroup_auto_shown),
+        ] {
+            if !value || g.had_value(key) {
+                g.set_value(key, value as u8);
+            }
+        }
 
         g
     }
+
+    /// Not hidden by any visgroup
+    pub fn shown(&self) -> bool {
+        self.visgroup_shown && self.visgroup_auto_shown
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Solid {
     pub id: u32,
     pub sides: Vec<Side>,
+    pub editor: Option<Editor>,
+    /// Hidden in the editor
+    pub hidden: bool,
     pub rest: GenericNode,
 }
 
 impl Solid {
-    fn parse(mut g: GenericNode) -> Self {
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
         let sides = g
-            .children_nodes
-            .remove("side")
-            .unwrap()
+            .take_children("side")
             .into_iter()
             .map(Side::parse)
-            .collect();
-        let id = g
-            .key_value_pairs
-            .remove("id")
-            .unwrap()
-            .pop()
-            .unwrap()
-            .parse()
-            .unwrap();
-        Self { id, sides, rest: g }
+            .collect::<Result<_, _>>()?;
+        let id = g.take_parsed("id")?;
+        let editor = g.try_take_child("editor").map(Editor::parse).transpose()?;
+        Ok(Self {
+            id,
+            sides,
+            editor,
+            hidden: false,
+            rest: g,
+        })
+    }
+
+    /// Not hidden by a visgroup
+    pub fn visgroup_shown(&self) -> bool {
+        self.editor.as_ref().is_none_or(Editor::shown)
+    }
+
+    /// The solids in a block, hidden ones included
+    fn parse_all(g: &mut GenericNode) -> Result<Vec<Self>, VmfError> {
+        parse_with_hidden(g, "solid", Solid::parse, |s| s.hidden = true)
+    }
+
+    fn set_all(g: &mut GenericNode, solids: &[Self]) {
+        set_with_hidden(g, "solid", solids, |s| (s.as_generic(), s.hidden));
     }
 
     fn as_generic(&self) -> GenericNode {
         let mut g = self.rest.clone();
 
-        g.set_value("id", self.id);
+        g.set_parsed("id", &self.id);
         g.set_children("side", self.sides.iter().map(|s| s.as_generic()).collect());
+        if let Some(editor) = &self.editor {
+            g.set_child("editor", editor.as_generic());
+        }
 
         g
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Side {
     pub id: u32,
     pub plane: Plane,
@@ -161,45 +823,27 @@ pub struct Side {
     pub rotation: f32,
     pub lightmap_scale: u32,
     pub smoothing_groups: u32,
+    /// Set if the face is a displacement
+    pub disp_info: Option<DispInfo>,
     pub rest: GenericNode,
 }
 
 impl Side {
-    fn parse(mut g: GenericNode) -> Self {
-        let id = g
-            .key_value_pairs
-            .remove("id")
-            .unwrap()
-            .pop()
-            .unwrap()
-            .parse()
-            .unwrap();
-        let plane = g.key_value_pairs.remove("plane").unwrap().pop().unwrap();
-        let material = g.key_value_pairs.remove("material").unwrap().pop().unwrap();
-        let u_axis = g.key_value_pairs.remove("uaxis").unwrap().pop().unwrap();
-        let v_axis = g.key_value_pairs.remove("vaxis").unwrap().pop().unwrap();
-        let rotation = g.key_value_pairs.remove("rotation").unwrap().pop().unwrap();
-        let lightmap_scale = g
-            .key_value_pairs
-            .remove("lightmapscale")
-            .unwrap()
-            .pop()
-            .unwrap();
-        let smoothing_groups = g
-            .key_value_pairs
-            .remove("smoothing_groups")
-            .unwrap()
-            .pop()
-            .unwrap();
-
-        let plane = Plane::parse(&plane);
-        let u_axis = UV::parse(&u_axis);
-        let v_axis = UV::parse(&v_axis);
-        let rotation = rotation.parse().unwrap();
-        let lightmap_scale = lightmap_scale.parse().unwrap();
-        let smoothing_groups = smoothing_groups.parse().unwrap();
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let id = g.take_parsed("id")?;
+        let plane = g.take_parsed("plane")?;
+        let material = g.take_value("material")?;
+        let u_axis = g.take_parsed("uaxis")?;
+        let v_axis = g.take_parsed("vaxis")?;
+        let rotation = g.take_parsed("rotation")?;
+        let lightmap_scale = g.take_parsed("lightmapscale")?;
+        let smoothing_groups = g.take_parsed("smoothing_groups")?;
+        let disp_info = g
+            .try_take_child("dispinfo")
+            .map(DispInfo::parse)
+            .transpose()?;
 
-        Self {
+        Ok(Self {
             id,
             plane,
             material,
@@ -208,68 +852,299 @@ impl Side {
             rotation,
             lightmap_scale,
             smoothing_groups,
+            disp_info,
             rest: g,
-        }
+        })
     }
 
     fn as_generic(&self) -> GenericNode {
         let mut g = self.rest.clone();
 
-        g.set_value("id", self.id);
-        g.set_value("plane", self.plane.to_string());
+        g.set_parsed("id", &self.id);
+        g.set_parsed("plane", &self.plane);
         g.set_value("material", &self.material);
-        g.set_value("uaxis", self.u_axis.to_string());
-        g.set_value("vaxis", self.v_axis.to_string());
-        g.set_value("rotation", self.rotation);
-        g.set_value("lightmapscale", self.lightmap_scale);
-        g.set_value("smoothing_groups", self.smoothing_groups);
+        g.set_parsed("uaxis", &self.u_axis);
+        g.set_parsed("vaxis", &self.v_axis);
+        g.set_parsed("rotation", &self.rotation);
+        g.set_parsed("lightmapscale", &self.lightmap_scale);
+        g.set_parsed("smoothing_groups", &self.smoothing_groups);
+        if let Some(disp_info) = &self.disp_info {
+            g.set_child("dispinfo", disp_info.as_generic());
+        }
 
         g
     }
 }
 
-#[derive(Debug)]
-pub struct UV([f32; 4], f32);
+/// The `dispinfo` block of a displacement. The face is made into a grid of
+/// `2^power + 1` points a side, starting from its corner at `start_position`,
+/// and each point is moved along its normal by its distance, then by its
+/// offset.
+///
+/// The grids are kept row by row, the way the file has them, and vectors are
+/// in Hammer's Z up space. Grid blocks the file doesn't have are left empty.
+#[derive(Clone, Debug)]
+pub struct DispInfo {
+    pub power: u32,
+    pub start_position: Point,
+    /// How far the whole surface is raised off the face
+    pub elevation: f32,
+    pub normals: Vec<Vec<Vec3>>,
+    pub distances: Vec<Vec<f32>>,
+    pub offsets: Vec<Vec<Vec3>>,
+    pub offset_normals: Vec<Vec<Vec3>>,
+    /// How much of the second texture of a blend material shows, 0 to 255
+    pub alphas: Vec<Vec<f32>>,
+    /// Flags for each triangle, two per square of the grid
+    pub triangle_tags: Vec<Vec<u32>>,
+    /// Which points the engine may leave out at a distance, a bit for each
+    pub allowed_verts: Vec<i32>,
+    pub rest: GenericNode,
+}
 
-impl UV {
-    fn parse(mut s: &str) -> Self {
-        let mut tmp = Self([0.0, 0.0, 0.0, 0.0], 0.0);
-        s = &s[1..];
-        let mut lr = s.split(']');
-        let coords = lr.next().unwrap();
-        let mut coords = coords.split(' ');
-        for i in 0..4 {
-            tmp.0[i] = coords.next().unwrap().parse().unwrap();
+impl DispInfo {
+    /// Points along each side of the grid
+    pub fn size(&self) -> usize {
+        (1 << self.power) + 1
+    }
+
+    fn parse(mut g: GenericNode) -> Result<Self, VmfError> {
+        let power = g.take_parsed("power")?;
+        let Bracketed(start_position) = g.take_parsed("startposition")?;
+        let elevation = g
+            .try_take_with("elevation", |s| s.trim().parse().ok())?
+            .unwrap_or(0.0);
+
+        // The grid blocks stay in `rest`, so their rows keep their text
+        let normals = take_rows::<Vectors>(&mut g, "normals")?;
+        let distances = take_rows::<Numbers<f32>>(&mut g, "distances")?;
+        let offsets = take_rows::<Vectors>(&mut g, "offsets")?;
+        let offset_normals = take_rows::<Vectors>(&mut g, "offset_normals")?;
+        let alphas = take_rows::<Numbers<f32>>(&mut g, "alphas")?;
+        let triangle_tags = take_rows::<Numbers<u32>>(&mut g, "triangle_tags")?;
+        // One key, named after how many numbers it has
+        let allowed_verts = match g.child_mut("allowed_verts") {
+            Some(block) => block.try_take_with("10", |s| s.parse().ok())?,
+            None => None,
+        };
+
+        Ok(Self {
+            power,
+            start_position,
+            elevation,
+            normals: normals.into_iter().map(|r| r.0).collect(),
+            distances: distances.into_iter().map(|r| r.0).collect(),
+            offsets: offsets.into_iter().map(|r| r.0).collect(),
+            offset_normals: offset_normals.into_iter().map(|r| r.0).collect(),
+            alphas: alphas.into_iter().map(|r| r.0).collect(),
+            triangle_tags: triangle_tags.into_iter().map(|r| r.0).collect(),
+            allowed_verts: allowed_verts.map_or_else(Vec::new, |Numbers(v)| v),
+            rest: g,
+        })
+    }
+
+    fn as_generic(&self) -> GenericNode {
+        let mut g = self.rest.clone();
+
+        g.set_parsed("power", &self.power);
+        g.set_parsed("startposition", &Bracketed(self.start_position.clone()));
+        if self.elevation != 0.0 || g.had_value("elevation") {
+            g.set_parsed("elevation", &self.elevation);
+        }
+
+        let vectors = |rows: &[Vec<Vec3>]| rows.iter().map(|r| Vectors(r.clone())).collect();
+        let numbers = |rows: &[Vec<f32>]| rows.iter().map(|r| Numbers(r.clone())).collect();
+        set_rows(&mut g, "normals", vectors(&self.normals));
+        set_rows(&mut g, "distances", numbers(&self.distances));
+        set_rows(&mut g, "offsets", vectors(&self.offsets));
+        set_rows(&mut g, "offset_normals", vectors(&self.offset_normals));
+        set_rows(&mut g, "alphas", numbers(&self.alphas));
+        let tags = self.triangle_tags.iter().map(|r| Numbers(r.clone()));
+        set_rows(&mut g, "triangle_tags", tags.collect());
+        if !self.allowed_verts.is_empty() || g.child_mut("allowed_verts").is_some() {
+            let block = child_or_new(&mut g, "allowed_verts");
+            block.set_parsed(
+                self.allowed_verts.len(),
+                &Numbers(self.allowed_verts.clone()),
+            );
         }
-        tmp.1 = lr.next().unwrap().trim().parse().unwrap();
 
-        tmp
+        g
     }
+}
 
-    fn to_string(&self) -> String {
-        let mut tmp = String::new();
-        tmp += "[";
-        tmp += &self.0[0].to_string();
-        tmp += " ";
-        tmp += &self.0[1].to_string();
-        tmp += " ";
-        tmp += &self.0[2].to_string();
-        tmp += " ";
-        tmp += &self.0[3].to_string();
-        tmp += "] ";
-        tmp += &self.1.to_string();
+/// Reads `row0`, `row1`, ... from the block `name`, leaving the block in `g`
+fn take_rows<R: FromStr>(g: &mut GenericNode, name: &str) -> Result<Vec<R>, VmfError> {
+    let Some(block) = g.child_mut(name) else {
+        return Ok(Vec::new());
+    };
+    let mut rows = Vec::new();
+    loop {
+        let key = format!("row{}", rows.len());
+        if block.get_value(&key).is_none() {
+            return Ok(rows);
+        }
+        rows.push(block.take_parsed(&key)?);
+    }
+}
 
-        tmp
+/// Writes the rows back into the block `name`, unchanged ones as they were
+fn set_rows<R: FromStr + PartialEq + Display>(g: &mut GenericNode, name: &str, rows: Vec<R>) {
+    if rows.is_empty() && g.child_mut(name).is_none() {
+        return;
+    }
+    let block = child_or_new(g, name);
+    for (i, row) in rows.iter().enumerate() {
+        block.set_parsed(format!("row{i}"), row);
     }
 }
 
-#[derive(Debug)]
+fn child_or_new<'a>(g: &'a mut GenericNode, name: &str) -> &'a mut GenericNode {
+    if g.child_mut(name).is_none() {
+        g.set_child(name, GenericNode::new());
+    }
+    g.child_mut(name).unwrap()
+}
+
+/// A row of numbers separated by spaces
+#[derive(PartialEq)]
+struct Numbers<T>(Vec<T>);
+
+impl<T: FromStr> FromStr for Numbers<T> {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(|n| n.parse().map_err(|_| ()))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl<T: Display> Display for Numbers<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, n) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{n}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A row of vectors, three numbers each
+#[derive(PartialEq)]
+struct Vectors(Vec<Vec3>);
+
+impl FromStr for Vectors {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Numbers::<f32>(numbers) = s.parse()?;
+        if numbers.len() % 3 != 0 {
+            return Err(());
+        }
+        Ok(Self(numbers.chunks(3).map(Vec3::from_slice).collect()))
+    }
+}
+
+impl Display for Vectors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let numbers = self.0.iter().flat_map(|v| v.to_array()).collect();
+        Numbers::<f32>(numbers).fmt(f)
+    }
+}
+
+/// A point written as `[x y z]`, like `startposition`
+#[derive(PartialEq)]
+struct Bracketed(Point);
+
+impl FromStr for Bracketed {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().strip_prefix('[').ok_or(())?;
+        s.strip_suffix(']').ok_or(())?.parse().map(Self)
+    }
+}
+
+impl Display for Bracketed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.0)
+    }
+}
+
+/// A texture axis: `[x y z shift] scale`, the axis in Hammer's Z up space
+#[derive(Clone, Debug, PartialEq)]
+pub struct UV(pub [f32; 4], pub f32);
+
+impl UV {
+    /// The direction the texture runs in, in our Y up space. Hammer has
+    /// already turned it by the side's `rotation`.
+    pub fn axis(&self) -> DVec3 {
+        DVec3::new(self.0[0] as f64, self.0[2] as f64, self.0[1] as f64)
+    }
+
+    /// In texels
+    pub fn shift(&self) -> f64 {
+        self.0[3] as f64
+    }
+
+    /// Map units per texel
+    pub fn scale(&self) -> f64 {
+        self.1 as f64
+    }
+
+    /// The same axis turned `degrees` around `normal`, which is in Hammer's
+    /// Z up space like the axis itself
+    pub fn rotated(&self, normal: DVec3, degrees: f64) -> Self {
+        let axis = DVec3::new(self.0[0] as f64, self.0[1] as f64, self.0[2] as f64);
+        let axis = DQuat::from_axis_angle(normal.normalize(), degrees.to_radians()) * axis;
+        // Keep 90 degree turns from writing out 1e-17 instead of 0
+        let axis = (axis * 1e6).round() / 1e6;
+        Self(
+            [axis.x as f32, axis.y as f32, axis.z as f32, self.0[3]],
+            self.1,
+        )
+    }
+}
+
+impl FromStr for UV {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (coords, scale) = s
+            .trim()
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .ok_or(())?;
+        Ok(Self(
+            parse_floats(coords).ok_or(())?,
+            scale.trim().parse().map_err(|_| ())?,
+        ))
+    }
+}
+
+impl Display for UV {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{} {} {} {}] {}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.1
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Plane {
     pub points: [Point; 3],
 }
 
-impl Plane {
-    fn parse(mut input: &str) -> Self {
+impl FromStr for Plane {
+    type Err = ();
+
+    fn from_str(mut input: &str) -> Result<Self, Self::Err> {
         let mut jump_past = |pattern: &str| {
             input.find(pattern).map(|pos| {
                 let d = &input[0..pos];
@@ -299,48 +1174,185 @@ impl Plane {
         };
 
         for pi in 0..3 {
-            jump_past("(").unwrap();
+            jump_past("(").ok_or(())?;
 
-            let x = jump_past(" ").unwrap().parse().unwrap();
-            let y = jump_past(" ").unwrap().parse().unwrap();
-            let z = jump_past(")").unwrap().parse().unwrap();
+            let x = jump_past(" ").ok_or(())?.parse().map_err(|_| ())?;
+            let y = jump_past(" ").ok_or(())?.parse().map_err(|_| ())?;
+            let z = jump_past(")").ok_or(())?.parse().map_err(|_| ())?;
 
             p.points[pi] = Point { x, y, z };
         }
 
-        p
+        Ok(p)
+    }
+}
+
+impl Plane {
+    /// The same plane moved by `offset`, which is in our Y up space
+    pub fn translated(&self, offset: DVec3) -> Self {
+        Self {
+            points: self
+                .points
+                .clone()
+                .map(|p| Point::from_dvec3(p.new_dvec3() + offset)),
+        }
     }
+}
 
-    fn to_string(&self) -> String {
-        let mut tmp = String::new();
+impl Display for Plane {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (i, point) in self.points.iter().enumerate() {
-            tmp += "(";
-            tmp += &point.x.to_string();
-            tmp += " ";
-            tmp += &point.y.to_string();
-            tmp += " ";
-            tmp += &point.z.to_string();
-            tmp += ")";
+            write!(f, "({point})")?;
 
             if i < self.points.len() - 1 {
-                tmp += " "
+                f.write_str(" ")?;
             }
         }
 
-        tmp
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Kept in double precision, f32 starts losing whole units not far past the
+/// edge of Hammer's grid, and the plane maths needs more than that.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Point {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// Parses a bare `x y z` triple, as used by keys like `origin`
+impl FromStr for Point {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [x, y, z] = parse_floats(s).ok_or(())?;
+        Ok(Self { x, y, z })
+    }
 }
 
 impl Point {
     // IMPORTANT: Hammer stores coordinates with Z as up, where here we use Y
+    pub fn new_dvec3(&self) -> DVec3 {
+        DVec3::new(self.x, self.z, self.y)
+    }
+
+    /// Only for things that don't need the precision, like placing a mesh
     pub fn new_vec3(&self) -> Vec3 {
-        Vec3::new(self.x, self.z, self.y)
+        self.new_dvec3().as_vec3()
+    }
+
+    /// Goes back from our Y up space to Hammer's Z up
+    pub fn from_dvec3(v: DVec3) -> Self {
+        Self {
+            x: v.x,
+            y: v.z,
+            z: v.y,
+        }
+    }
+}
+
+impl Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.x, self.y, self.z)
+    }
+}
+
+/// Takes every `name` child block, visible ones first and then the ones in
+/// `hidden` blocks, which `hide` is called on
+fn parse_with_hidden<T>(
+    g: &mut GenericNode,
+    name: &str,
+    parse: impl Fn(GenericNode) -> Result<T, VmfError>,
+    hide: impl Fn(&mut T),
+) -> Result<Vec<T>, VmfError> {
+    let mut items = g
+        .take_children(name)
+        .into_iter()
+        .map(&parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    for mut hidden in g.take_children("hidden") {
+        for child in hidden.take_children(name) {
+            let mut item = parse(child)?;
+            hide(&mut item);
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
+
+/// Puts the `name` blocks back, each hidden one wrapped in a `hidden` block
+fn set_with_hidden<T>(
+    g: &mut GenericNode,
+    name: &str,
+    items: &[T],
+    as_generic: impl Fn(&T) -> (GenericNode, bool),
+) {
+    let mut visible = Vec::new();
+    let mut hidden = Vec::new();
+    for item in items {
+        match as_generic(item) {
+            (node, false) => visible.push(node),
+            (node, true) => {
+                let mut wrapper = GenericNode::new();
+                wrapper.set_child(name, node);
+                hidden.push(wrapper);
+            }
+        }
+    }
+    g.set_children(name, visible);
+    g.set_children("hidden", hidden);
+}
+
+/// Parses exactly `N` whitespace separated numbers
+fn parse_floats<T: FromStr + Default + Copy, const N: usize>(s: &str) -> Option<[T; N]> {
+    let mut out = [T::default(); N];
+    let mut parts = s.split_whitespace();
+    for x in out.iter_mut() {
+        *x = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(connections: &str) -> String {
+        format!(
+            "versioninfo\r\n{{\r\n\t\"editorversion\" \"400\"\r\n\t\"editorbuild\" \"8864\"\r\n\t\"mapversion\" \"1\"\r\n\t\"formatversion\" \"100\"\r\n\t\"prefab\" \"0\"\r\n}}\r\nworld\r\n{{\r\n\t\"id\" \"1\"\r\n\t\"classname\" \"worldspawn\"\r\n}}\r\nentity\r\n{{\r\n\t\"id\" \"2\"\r\n\t\"classname\" \"logic_relay\"\r\n\tconnections\r\n\t{{\r\n{connections}\t}}\r\n}}\r\n"
+        )
+    }
+
+    fn parse(text: &str) -> Vmf {
+        Vmf::parse(GenericNode::parse(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn malformed_outputs_are_kept() {
+        let text = entity(
+            "\t\t\"OnTrigger\" \"door,Open,,0,-1\"\r\n\
+             \t\t\"OnTrigger\" \"door,Close\"\r\n\
+             \t\t\"OnSpawn\" \"door,Open,,soon,-1\"\r\n",
+        );
+        let vmf = parse(&text);
+        let outputs = vmf.entities[0].connections.as_ref().unwrap();
+        assert_eq!(outputs.len(), 3);
+        assert!(!outputs[0].is_malformed());
+        assert!(outputs[1].is_malformed());
+        assert!(outputs[2].is_malformed());
+        assert_eq!(outputs[1].target, "door");
+        assert_eq!(outputs[1].input, "Close");
+        assert_eq!(vmf.as_generic().to_string(), text);
+    }
+
+    #[test]
+    fn edited_malformed_output_is_written_out_whole() {
+        let mut vmf = parse(&entity("\t\t\"OnTrigger\" \"door,Close\"\r\n"));
+        let output = &mut vmf.entities[0].connections.as_mut().unwrap()[0];
+        output.delay = 1.5;
+        assert!(!output.is_malformed());
+        assert_eq!(output.value(), "door,Close,,1.5,-1");
     }
 }
@@ -0,0 +1,471 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/*
+VPK, Valve's pack format. A pack is a `<name>_dir.vpk` holding the directory
+tree (and sometimes small files), plus `<name>_000.vpk`, `<name>_001.vpk`,
+... holding the rest of the data. The directory file starts with (little
+endian):
+
+     0  signature 0x55aa1234 (u32)
+     4  version, 1 or 2 (u32)
+     8  tree size (u32)
+    12  version 2 only: file data, archive MD5, other MD5 and signature
+        section sizes (4 x u32)
+
+The tree groups files by extension, then directory, then name, each a list
+of null terminated strings ending with an empty one:
+
+    "vmt"
+        "materials/tools"
+            "toolsnodraw"   entry
+            "toolsclip"     entry
+            ""
+        ""
+    ""
+
+Every entry is a CRC (u32), preload size (u16), archive index (u16), offset
+(u32), length (u32) and 0xffff, followed by the preload bytes. The file is
+the preload bytes followed by `length` bytes at `offset` in the archive. An
+archive index of 0x7fff means the data is in the directory file itself,
+right after the tree. A directory of " " is the root, as is an extension of
+" " for files without one.
+*/
+
+const SIGNATURE: u32 = 0x55aa_1234;
+const V1_HEADER_SIZE: u64 = 12;
+const V2_HEADER_SIZE: u64 = 28;
+const ENTRY_TERMINATOR: u16 = 0xffff;
+/// The archive index of data stored in the directory file
+const DIR_ARCHIVE: u16 = 0x7fff;
+
+#[derive(Debug)]
+pub enum VpkError {
+    Io(std::io::Error),
+    NotVpk,
+    UnsupportedVersion(u32),
+    /// The directory tree ends in the middle of an entry, or an entry doesn't
+    /// end where it should
+    BadTree,
+}
+
+impl Display for VpkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VpkError::Io(e) => write!(f, "{e}"),
+            VpkError::NotVpk => f.write_str("not a VPK directory file"),
+            VpkError::UnsupportedVersion(v) => write!(f, "unsupported VPK version {v}"),
+            VpkError::BadTree => f.write_str("VPK directory tree is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for VpkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VpkError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VpkError {
+    fn from(e: std::io::Error) -> Self {
+        VpkError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct VpkEntry {
+    preload: Vec<u8>,
+    archive: u16,
+    offset: u32,
+    length: u32,
+}
+
+/// The directory of a pack. Files are read from disk when asked for.
+#[derive(Debug)]
+pub struct Vpk {
+    /// The `_dir.vpk` file
+    path: PathBuf,
+    version: u32,
+    /// Where data stored in the directory file starts
+    data_start: u64,
+    /// By lowercase path, e.g. `materials/tools/toolsnodraw.vmt`
+    entries: HashMap<String, VpkEntry>,
+}
+
+impl Vpk {
+    /// Reads the directory of a pack. `path` is the `_dir.vpk` file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, VpkError> {
+        let path = path.into();
+        let mut file = File::open(&path)?;
+
+        // Only the version 1 part matters to us, the rest is for checksums
+        let mut header = [0; V1_HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        if word(0) != SIGNATURE {
+            return Err(VpkError::NotVpk);
+        }
+        let version = word(1);
+        let tree_size = word(2);
+        let header_size = match version {
+            1 => V1_HEADER_SIZE,
+            2 => V2_HEADER_SIZE,
+            v => return Err(VpkError::UnsupportedVersion(v)),
+        };
+
+        // A corrupt size shouldn't make us allocate gigabytes
+        if header_size + tree_size as u64 > file.metadata()?.len() {
+            return Err(VpkError::BadTree);
+        }
+        let mut tree = vec![0; tree_size as usize];
+        file.seek(SeekFrom::Start(header_size))?;
+        file.read_exact(&mut tree)?;
+
+        Ok(Self {
+            path,
+            version,
+            data_start: header_size + tree_size as u64,
+            entries: parse_tree(&tree)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every file in the pack, lowercase
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(&normalize_path(path))
+    }
+
+    /// Opens a file in the pack, `None` if it isn't there
+    pub fn open_file(&self, path: &str) -> Option<std::io::Result<Box<dyn Read + Send>>> {
+        let entry = self.entries.get(&normalize_path(path))?;
+        Some(self.open_entry(entry))
+    }
+
+    /// Reads a whole file from the pack, `None` if it isn't there
+    pub fn read(&self, path: &str) -> Option<std::io::Result<Vec<u8>>> {
+        let entry = self.entries.get(&normalize_path(path))?;
+        Some(self.open_entry(entry).and_then(|mut reader| {
+            let mut data = Vec::with_capacity(entry.preload.len() + entry.length as usize);
+            reader.read_to_end(&mut data)?;
+            Ok(data)
+        }))
+    }
+
+    fn open_entry(&self, entry: &VpkEntry) -> std::io::Result<Box<dyn Read + Send>> {
+        let preload = Cursor::new(entry.preload.clone());
+        if entry.length == 0 {
+            return Ok(Box::new(preload));
+        }
+
+        let (mut file, offset) = if entry.archive == DIR_ARCHIVE {
+            (
+                File::open(&self.path)?,
+                self.data_start + entry.offset as u64,
+            )
+        } else {
+            (
+                File::open(self.archive_path(entry.archive))?,
+                entry.offset as u64,
+            )
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(preload.chain(file.take(entry.length as u64))))
+    }
+
+    /// `pak01_dir.vpk` keeps archive 3 in `pak01_003.vpk`
+    fn archive_path(&self, index: u16) -> PathBuf {
+        let name = self
+            .path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let stem = name
+            .strip_suffix("_dir.vpk")
+            .or_else(|| name.strip_suffix(".vpk"))
+            .unwrap_or(name);
+        self.path.with_file_name(format!("{stem}_{index:03}.vpk"))
+    }
+}
+
+fn parse_tree(tree: &[u8]) -> Result<HashMap<String, VpkEntry>, VpkError> {
+    let mut reader = TreeReader { tree, pos: 0 };
+    let mut entries = HashMap::new();
+
+    loop {
+        let extension = reader.string()?;
+        if extension.is_empty() {
+            break;
+        }
+        loop {
+            let dir = reader.string()?;
+            if dir.is_empty() {
+                break;
+            }
+            loop {
+                let name = reader.string()?;
+                if name.is_empty() {
+                    break;
+                }
+
+                let _crc = reader.u32()?;
+                let preload_size = reader.u16()?;
+                let archive = reader.u16()?;
+                let offset = reader.u32()?;
+                let length = reader.u32()?;
+                if reader.u16()? != ENTRY_TERMINATOR {
+                    return Err(VpkError::BadTree);
+                }
+                let preload = reader.bytes(preload_size as usize)?.to_vec();
+
+                let mut path = String::new();
+                if dir != " " {
+                    path.push_str(&dir);
+                    path.push('/');
+                }
+                path.push_str(&name);
+                if extension != " " {
+                    path.push('.');
+                    path.push_str(&extension);
+                }
+                entries.insert(
+                    normalize_path(&path),
+                    VpkEntry {
+                        preload,
+                        archive,
+                        offset,
+                        length,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+struct TreeReader<'a> {
+    tree: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TreeReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], VpkError> {
+        let bytes = self
+            .tree
+            .get(self.pos..self.pos + len)
+            .ok_or(VpkError::BadTree)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, VpkError> {
+        let len = self.tree[self.pos.min(self.tree.len())..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(VpkError::BadTree)?;
+        let s = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.pos += 1;
+        Ok(s)
+    }
+
+    fn u16(&mut self) -> Result<u16, VpkError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, VpkError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+/// Paths in packs are lowercase with forward slashes
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// A directory of its own for each test, they run at the same time
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chisel-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `<stem>_dir.vpk` and `<stem>_000.vpk`. The first two bytes of
+    /// each file are preloaded, the rest is stored in the directory file for
+    /// every other file and in the archive for the others.
+    pub(crate) fn write_vpk(dir: &Path, stem: &str, version: u32, files: &[(&str, &[u8])]) {
+        // Extension, then directory, then the file names with their index
+        let mut tree_files = BTreeMap::new();
+        let mut stored = Vec::new();
+        let (mut dir_data, mut archive_data) = (Vec::new(), Vec::new());
+        for (i, (path, data)) in files.iter().enumerate() {
+            let (dir, file) = path.rsplit_once('/').unwrap_or((" ", path));
+            let (name, extension) = file.rsplit_once('.').unwrap_or((file, " "));
+            let (archive, store) = if i % 2 == 0 {
+                (DIR_ARCHIVE, &mut dir_data)
+            } else {
+                (0, &mut archive_data)
+            };
+            stored.push((archive, store.len() as u32));
+            store.extend_from_slice(&data[data.len().min(2)..]);
+            tree_files
+                .entry(extension)
+                .or_insert_with(BTreeMap::new)
+                .entry(dir)
+                .or_insert_with(Vec::new)
+                .push((name, i));
+        }
+
+        let mut tree = Vec::new();
+        let string = |tree: &mut Vec<u8>, s: &str| {
+            tree.extend_from_slice(s.as_bytes());
+            tree.push(0);
+        };
+        for (extension, dirs) in &tree_files {
+            string(&mut tree, extension);
+            for (dir, names) in dirs {
+                string(&mut tree, dir);
+                for &(name, i) in names {
+                    let data = files[i].1;
+                    let (preload, rest) = data.split_at(data.len().min(2));
+                    let (archive, offset) = stored[i];
+                    string(&mut tree, name);
+                    tree.extend_from_slice(&0u32.to_le_bytes());
+                    tree.extend_from_slice(&(preload.len() as u16).to_le_bytes());
+                    tree.extend_from_slice(&archive.to_le_bytes());
+                    tree.extend_from_slice(&offset.to_le_bytes());
+                    tree.extend_from_slice(&(rest.len() as u32).to_le_bytes());
+                    tree.extend_from_slice(&ENTRY_TERMINATOR.to_le_bytes());
+                    tree.extend_from_slice(preload);
+                }
+                string(&mut tree, "");
+            }
+            string(&mut tree, "");
+        }
+        string(&mut tree, "");
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&version.to_le_bytes());
+        out.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+        if version == 2 {
+            out.extend_from_slice(&(dir_data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&[0; 12]);
+        }
+        out.extend_from_slice(&tree);
+        out.extend_from_slice(&dir_data);
+        std::fs::write(dir.join(format!("{stem}_dir.vpk")), out).unwrap();
+        std::fs::write(dir.join(format!("{stem}_000.vpk")), archive_data).unwrap();
+    }
+
+    const FILES: [(&str, &[u8]); 5] = [
+        ("materials/tools/toolsnodraw.vmt", b"LightmappedGeneric {}"),
+        ("materials/tools/toolsclip.vmt", b"UnlitGeneric {}"),
+        ("materials/Dev/Dev_Measure.vtf", b"VTF\0 pixels"),
+        ("readme", b"no extension, in the root"),
+        ("a.txt", b"x"),
+    ];
+
+    fn check(version: u32) {
+        let dir = temp_dir(&format!("vpk-v{version}"));
+        write_vpk(&dir, "pak01", version, &FILES);
+        let vpk = Vpk::open(dir.join("pak01_dir.vpk")).unwrap();
+        assert_eq!(vpk.version(), version);
+        assert_eq!(vpk.len(), FILES.len());
+        for (path, data) in FILES {
+            assert_eq!(vpk.read(path).unwrap().unwrap(), data, "{path}");
+        }
+        // Lowercase, with either slash
+        assert!(vpk.files().any(|f| f == "materials/dev/dev_measure.vtf"));
+        assert!(vpk.contains("MATERIALS\\DEV\\dev_measure.VTF"));
+        assert!(vpk.read("materials/tools/missing.vmt").is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn version_1() {
+        check(1);
+    }
+
+    #[test]
+    fn version_2() {
+        check(2);
+    }
+
+    #[test]
+    fn bad_files() {
+        let dir = temp_dir("vpk-bad");
+        write_vpk(&dir, "pak01", 2, &FILES);
+        let path = dir.join("pak01_dir.vpk");
+        let good = std::fs::read(&path).unwrap();
+        let open = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            Vpk::open(&path)
+        };
+
+        assert!(matches!(open(b"not a pack at all"), Err(VpkError::NotVpk)));
+        let mut bytes = good.clone();
+        bytes[4] = 3;
+        assert!(matches!(open(&bytes), Err(VpkError::UnsupportedVersion(3))));
+        // A tree size far past the end of the file
+        let mut bytes = good.clone();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(open(&bytes), Err(VpkError::BadTree)));
+        // Cut off in the middle of the tree
+        let tree_size = u32::from_le_bytes(good[8..12].try_into().unwrap()) as usize;
+        let bytes = &good[..V2_HEADER_SIZE as usize + tree_size / 2];
+        assert!(matches!(open(bytes), Err(VpkError::BadTree)));
+        // An entry that doesn't end in 0xffff
+        let mut bytes = good.clone();
+        let end = bytes.windows(2).position(|w| w == [0xff, 0xff]).unwrap();
+        bytes[end] = 0;
+        assert!(matches!(open(&bytes), Err(VpkError::BadTree)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            normalize_path("Materials\\Tools//ToolsNodraw.VMT"),
+            "materials/tools/toolsnodraw.vmt"
+        );
+    }
+}
@@ -0,0 +1,709 @@
+use std::fmt::Display;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        texture::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+    },
+    utils::BoxedFuture,
+};
+
+/*
+VTF, Valve's texture format. The header looks like this (packed, little
+endian):
+
+     0  "VTF\0"
+     4  version major, minor (u32, u32)
+    12  header size (u32)
+    16  width, height (u16, u16)
+    20  flags (u32)
+    24  frames, first frame (u16, u16)
+    28  padding, reflectivity (3 x f32), padding, bumpmap scale (f32)
+    52  high res image format (i32)
+    56  mipmap count (u8)
+    57  low res image format (i32)
+    61  low res width, height (u8, u8)
+    63  depth (u16)                                    7.2 and up
+    68  resource count (u32), then 8 bytes padding     7.3 and up
+    80  resources: tag (3 bytes), flags (u8), offset (u32)
+
+Up to 7.2 the small thumbnail comes straight after the header, then the
+image. Later versions point at both through the resources. Image data goes
+from the smallest mipmap to the largest, and each mipmap has every frame,
+each frame every face, each face every depth slice.
+*/
+
+/// Enough for every version's header, not counting resources
+const HEADER_SIZE: u64 = 80;
+const FLAG_ENVMAP: u32 = 0x4000;
+const RESOURCE_HIGH_RES_IMAGE: [u8; 3] = [0x30, 0, 0];
+
+#[derive(Debug)]
+pub enum VtfError {
+    Io(std::io::Error),
+    NotVtf,
+    UnsupportedVersion(u32, u32),
+    UnsupportedFormat(i32),
+    /// The file ends before the data the header says is there
+    Truncated,
+}
+
+impl Display for VtfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VtfError::Io(e) => write!(f, "{e}"),
+            VtfError::NotVtf => f.write_str("not a VTF file"),
+            VtfError::UnsupportedVersion(major, minor) => {
+                write!(f, "unsupported VTF version {major}.{minor}")
+            }
+            VtfError::UnsupportedFormat(format) => {
+                write!(f, "unsupported VTF image format {format}")
+            }
+            VtfError::Truncated => f.write_str("VTF file is cut short"),
+        }
+    }
+}
+
+impl std::error::Error for VtfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VtfError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VtfError {
+    fn from(e: std::io::Error) -> Self {
+        VtfError::Io(e)
+    }
+}
+
+/// The image formats we can decode, with their numbers in the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Rgba8888 = 0,
+    Abgr8888 = 1,
+    Rgb888 = 2,
+    Bgr888 = 3,
+    I8 = 5,
+    Ia88 = 6,
+    A8 = 8,
+    Argb8888 = 11,
+    Bgra8888 = 12,
+    Dxt1 = 13,
+    Dxt3 = 14,
+    Dxt5 = 15,
+    Bgrx8888 = 16,
+    Dxt1OneBitAlpha = 20,
+}
+
+impl ImageFormat {
+    fn from_i32(format: i32) -> Result<Self, VtfError> {
+        Ok(match format {
+            0 => Self::Rgba8888,
+            1 => Self::Abgr8888,
+            2 => Self::Rgb888,
+            3 => Self::Bgr888,
+            5 => Self::I8,
+            6 => Self::Ia88,
+            8 => Self::A8,
+            11 => Self::Argb8888,
+            12 => Self::Bgra8888,
+            13 => Self::Dxt1,
+            14 => Self::Dxt3,
+            15 => Self::Dxt5,
+            16 => Self::Bgrx8888,
+            20 => Self::Dxt1OneBitAlpha,
+            _ => return Err(VtfError::UnsupportedFormat(format)),
+        })
+    }
+
+    /// Bytes taken up by an image of this size
+    fn data_size(self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        let blocks = width.div_ceil(4).max(1) * height.div_ceil(4).max(1);
+        match self {
+            Self::Dxt1 | Self::Dxt1OneBitAlpha => blocks * 8,
+            Self::Dxt3 | Self::Dxt5 => blocks * 16,
+            Self::I8 | Self::A8 => width * height,
+            Self::Ia88 => width * height * 2,
+            Self::Rgb888 | Self::Bgr888 => width * height * 3,
+            Self::Rgba8888 | Self::Abgr8888 | Self::Argb8888 | Self::Bgra8888 | Self::Bgrx8888 => {
+                width * height * 4
+            }
+        }
+    }
+}
+
+/// The parts of the header we use
+#[derive(Clone, Debug)]
+pub struct VtfHeader {
+    pub version: (u32, u32),
+    pub width: u32,
+    pub height: u32,
+    pub flags: u32,
+    pub frames: u32,
+    pub first_frame: u16,
+    pub format: i32,
+    pub mipmaps: u32,
+    pub low_res_format: i32,
+    pub low_res_width: u32,
+    pub low_res_height: u32,
+    pub depth: u32,
+    header_size: usize,
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, VtfError> {
+    let bytes = data.get(at..at + 2).ok_or(VtfError::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, VtfError> {
+    let bytes = data.get(at..at + 4).ok_or(VtfError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+impl VtfHeader {
+    /// Only needs the first 80 bytes of the file
+    pub fn parse(data: &[u8]) -> Result<Self, VtfError> {
+        if !data.starts_with(b"VTF\0") {
+            return Err(VtfError::NotVtf);
+        }
+        let version = (u32_at(data, 4)?, u32_at(data, 8)?);
+        if version.0 != 7 || version.1 > 5 {
+            return Err(VtfError::UnsupportedVersion(version.0, version.1));
+        }
+
+        Ok(Self {
+            version,
+            header_size: u32_at(data, 12)? as usize,
+            width: u16_at(data, 16)? as u32,
+            height: u16_at(data, 18)? as u32,
+            flags: u32_at(data, 20)?,
+            frames: (u16_at(data, 24)? as u32).max(1),
+            first_frame: u16_at(data, 26)?,
+            format: u32_at(data, 52)? as i32,
+            mipmaps: (*data.get(56).ok_or(VtfError::Truncated)? as u32).max(1),
+            low_res_format: u32_at(data, 57)? as i32,
+            low_res_width: *data.get(61).ok_or(VtfError::Truncated)? as u32,
+            low_res_height: *data.get(62).ok_or(VtfError::Truncated)? as u32,
+            depth: if version.1 >= 2 {
+                (u16_at(data, 63)? as u32).max(1)
+            } else {
+                1
+            },
+        })
+    }
+
+    /// Cubemaps have 6 faces, older versions add a spheremap as a 7th
+    fn faces(&self) -> u32 {
+        if self.flags & FLAG_ENVMAP == 0 {
+            1
+        } else if self.version.1 < 5 && self.first_frame != 0xFFFF {
+            7
+        } else {
+            6
+        }
+    }
+
+    fn mip_size(&self, mip: u32) -> (u32, u32, u32) {
+        (
+            (self.width >> mip).max(1),
+            (self.height >> mip).max(1),
+            (self.depth >> mip).max(1),
+        )
+    }
+}
+
+/// Reads just enough of a VTF to know how big it is
+pub fn read_size(reader: impl std::io::Read) -> Result<UVec2, VtfError> {
+    use std::io::Read;
+
+    let mut header = Vec::new();
+    reader.take(HEADER_SIZE).read_to_end(&mut header)?;
+    let header = VtfHeader::parse(&header)?;
+    Ok(UVec2::new(header.width, header.height))
+}
+
+/// A decoded VTF: the first frame of the first face, every mipmap as RGBA
+#[derive(Debug)]
+pub struct Vtf {
+    pub header: VtfHeader,
+    /// Largest first
+    pub mipmaps: Vec<Vec<u8>>,
+}
+
+impl Vtf {
+    pub fn parse(data: &[u8]) -> Result<Self, VtfError> {
+        let header = VtfHeader::parse(data)?;
+        let format = ImageFormat::from_i32(header.format)?;
+
+        let image_start = if header.version.1 >= 3 {
+            let count = u32_at(data, 68)? as usize;
+            let entry = (0..count)
+                .map(|i| 80 + i * 8)
+                .find(|at| data.get(*at..*at + 3) == Some(&RESOURCE_HIGH_RES_IMAGE))
+                .ok_or(VtfError::Truncated)?;
+            u32_at(data, entry + 4)? as usize
+        } else {
+            let has_low_res = header.low_res_format != -1
+                && header.low_res_width > 0
+                && header.low_res_height > 0;
+            let low_res = if has_low_res {
+                ImageFormat::from_i32(header.low_res_format)?
+                    .data_size(header.low_res_width, header.low_res_height)
+            } else {
+                0
+            };
+            header.header_size + low_res
+        };
+
+        let copies = (header.frames * header.faces()) as usize;
+        let mip_bytes = |mip: u32| {
+            let (w, h, d) = header.mip_size(mip);
+            format.data_size(w, h) * d as usize
+        };
+
+        let mut mipmaps = Vec::with_capacity(header.mipmaps as usize);
+        for mip in 0..header.mipmaps {
+            // Every smaller mipmap comes first
+            let offset: usize = image_start
+                + (mip + 1..header.mipmaps)
+                    .map(|m| mip_bytes(m) * copies)
+                    .sum::<usize>();
+            let (w, h, _) = header.mip_size(mip);
+            let size = format.data_size(w, h);
+            let bytes = data.get(offset..offset + size).ok_or(VtfError::Truncated)?;
+            mipmaps.push(decode(format, bytes, w, h));
+        }
+
+        Ok(Self { header, mipmaps })
+    }
+
+    /// A repeating texture with all the mipmaps, ready to go on a mesh
+    pub fn to_image(&self) -> Image {
+        Image {
+            data: self.mipmaps.concat(),
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: self.header.width,
+                    height: self.header.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: self.mipmaps.len() as u32,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8UnormSrgb,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            sampler: ImageSampler::Descriptor(ImageSamplerDescriptor {
+                address_mode_u: ImageAddressMode::Repeat,
+                address_mode_v: ImageAddressMode::Repeat,
+                mag_filter: ImageFilterMode::Linear,
+                min_filter: ImageFilterMode::Linear,
+                mipmap_filter: ImageFilterMode::Linear,
+                ..default()
+            }),
+            texture_view_descriptor: None,
+            asset_usage: RenderAssetUsages::RENDER_WORLD,
+        }
+    }
+}
+
+/// Turns one image into RGBA, 4 bytes a pixel
+fn decode(format: ImageFormat, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let pixels = (width * height) as usize;
+    let per_pixel = |size: usize, f: fn(&[u8]) -> [u8; 4]| -> Vec<u8> {
+        data.chunks_exact(size).take(pixels).flat_map(f).collect()
+    };
+
+    match format {
+        ImageFormat::Rgba8888 => per_pixel(4, |p| [p[0], p[1], p[2], p[3]]),
+        ImageFormat::Abgr8888 => per_pixel(4, |p| [p[3], p[2], p[1], p[0]]),
+        ImageFormat::Argb8888 => per_pixel(4, |p| [p[1], p[2], p[3], p[0]]),
+        ImageFormat::Bgra8888 => per_pixel(4, |p| [p[2], p[1], p[0], p[3]]),
+        ImageFormat::Bgrx8888 => per_pixel(4, |p| [p[2], p[1], p[0], 255]),
+        ImageFormat::Rgb888 => per_pixel(3, |p| [p[0], p[1], p[2], 255]),
+        ImageFormat::Bgr888 => per_pixel(3, |p| [p[2], p[1], p[0], 255]),
+        ImageFormat::I8 => per_pixel(1, |p| [p[0], p[0], p[0], 255]),
+        ImageFormat::Ia88 => per_pixel(2, |p| [p[0], p[0], p[0], p[1]]),
+        ImageFormat::A8 => per_pixel(1, |p| [255, 255, 255, p[0]]),
+        ImageFormat::Dxt1 | ImageFormat::Dxt1OneBitAlpha => {
+            decode_blocks(data, width, height, 8, |block, out| {
+                decode_color_block(block, out, true)
+            })
+        }
+        ImageFormat::Dxt3 => decode_blocks(data, width, height, 16, |block, out| {
+            decode_color_block(&block[8..], out, false);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, pixel) in out.iter_mut().enumerate() {
+                pixel[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+            }
+        }),
+        ImageFormat::Dxt5 => decode_blocks(data, width, height, 16, |block, out| {
+            decode_color_block(&block[8..], out, false);
+            let (a0, a1) = (block[0] as u32, block[1] as u32);
+            let mut alphas = [a0, a1, 0, 0, 0, 0, 0, 255];
+            // Either 6 steps between the two, or 4 steps plus 0 and 255
+            let steps = if a0 > a1 { 7 } else { 5 };
+            for (i, alpha) in alphas.iter_mut().enumerate().take(steps + 1).skip(2) {
+                let i = i as u32;
+                *alpha = ((steps as u32 + 1 - i) * a0 + (i - 1) * a1) / steps as u32;
+            }
+            let mut bits = [0; 8];
+            bits[..6].copy_from_slice(&block[2..8]);
+            let indices = u64::from_le_bytes(bits);
+            for (i, pixel) in out.iter_mut().enumerate() {
+                pixel[3] = alphas[((indices >> (i * 3)) & 0x7) as usize] as u8;
+            }
+        }),
+    }
+}
+
+/// Runs `decode_block` over each 4x4 block and puts the pixels where they
+/// go, leaving off the parts of edge blocks that are outside the image
+fn decode_blocks(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_size: usize,
+    decode_block: impl Fn(&[u8], &mut [[u8; 4]; 16]),
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4).max(1);
+    let mut out = vec![0; width * height * 4];
+
+    for (b, block) in data.chunks_exact(block_size).enumerate() {
+        let (bx, by) = ((b % blocks_wide) * 4, (b / blocks_wide) * 4);
+        if by >= height {
+            break;
+        }
+        let mut pixels = [[0; 4]; 16];
+        decode_block(block, &mut pixels);
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            if x < width && y < height {
+                let at = (y * width + x) * 4;
+                out[at..at + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+    out
+}
+
+/// The colour half of a DXT block. Only DXT1 has the mode where the 4th
+/// colour is transparent.
+fn decode_color_block(block: &[u8], out: &mut [[u8; 4]; 16], dxt1: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (r0, g0, b0) = rgb565(c0);
+    let (r1, g1, b1) = rgb565(c1);
+
+    let mix = |a: u32, b: u32, wa: u32, wb: u32| ((a * wa + b * wb) / (wa + wb)) as u8;
+    let colors = if c0 > c1 || !dxt1 {
+        [
+            [r0 as u8, g0 as u8, b0 as u8, 255],
+            [r1 as u8, g1 as u8, b1 as u8, 255],
+            [mix(r0, r1, 2, 1), mix(g0, g1, 2, 1), mix(b0, b1, 2, 1), 255],
+            [mix(r0, r1, 1, 2), mix(g0, g1, 1, 2), mix(b0, b1, 1, 2), 255],
+        ]
+    } else {
+        [
+            [r0 as u8, g0 as u8, b0 as u8, 255],
+            [r1 as u8, g1 as u8, b1 as u8, 255],
+            [mix(r0, r1, 1, 1), mix(g0, g1, 1, 1), mix(b0, b1, 1, 1), 255],
+            [0, 0, 0, 0],
+        ]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = colors[((indices >> (i * 2)) & 0x3) as usize];
+    }
+}
+
+fn rgb565(c: u16) -> (u32, u32, u32) {
+    let (r, g, b) = (
+        (c >> 11) as u32,
+        ((c >> 5) & 0x3F) as u32,
+        (c & 0x1F) as u32,
+    );
+    (
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    )
+}
+
+/// Lets the asset server load `.vtf` files as `Image`s
+#[derive(Default)]
+pub struct VtfLoader;
+
+impl AssetLoader for VtfLoader {
+    type Asset = Image;
+    type Settings = ();
+    type Error = VtfError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Image, VtfError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(Vtf::parse(&bytes)?.to_image())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vtf"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0xF800;
+    const BLUE: u16 = 0x001F;
+
+    /// A header for version 7.`minor`, with no thumbnail. From 7.3 on it
+    /// has a thumbnail resource that isn't there, then the image's.
+    fn header(
+        minor: u32,
+        (width, height): (u16, u16),
+        format: ImageFormat,
+        mipmaps: u8,
+    ) -> Vec<u8> {
+        let size: u32 = match minor {
+            0 | 1 => 64,
+            2 => 80,
+            _ => 96,
+        };
+        let mut data = vec![0; size as usize];
+        data[..4].copy_from_slice(b"VTF\0");
+        data[4..8].copy_from_slice(&7u32.to_le_bytes());
+        data[8..12].copy_from_slice(&minor.to_le_bytes());
+        data[12..16].copy_from_slice(&size.to_le_bytes());
+        data[16..18].copy_from_slice(&width.to_le_bytes());
+        data[18..20].copy_from_slice(&height.to_le_bytes());
+        data[24..26].copy_from_slice(&1u16.to_le_bytes());
+        data[52..56].copy_from_slice(&(format as i32).to_le_bytes());
+        data[56] = mipmaps;
+        data[57..61].copy_from_slice(&(-1i32).to_le_bytes());
+        if minor >= 2 {
+            data[63..65].copy_from_slice(&1u16.to_le_bytes());
+        }
+        if minor >= 3 {
+            data[68..72].copy_from_slice(&2u32.to_le_bytes());
+            data[80..84].copy_from_slice(&[0x01, 0, 0, 0]);
+            data[88..91].copy_from_slice(&RESOURCE_HIGH_RES_IMAGE);
+            data[92..96].copy_from_slice(&size.to_le_bytes());
+        }
+        data
+    }
+
+    fn color_block(c0: u16, c1: u16, indices: [u8; 4]) -> Vec<u8> {
+        [c0.to_le_bytes(), c1.to_le_bytes()]
+            .concat()
+            .into_iter()
+            .chain(indices)
+            .collect()
+    }
+
+    fn pixel(vtf: &Vtf, x: usize, y: usize) -> [u8; 4] {
+        let at = (y * vtf.header.width as usize + x) * 4;
+        vtf.mipmaps[0][at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn versions() {
+        for minor in 0..=5 {
+            let mut data = header(minor, (16, 8), ImageFormat::Dxt1, 5);
+            let parsed = VtfHeader::parse(&data).unwrap();
+            assert_eq!(parsed.version, (7, minor));
+            assert_eq!((parsed.width, parsed.height), (16, 8));
+            assert_eq!(parsed.format, ImageFormat::Dxt1 as i32);
+            assert_eq!(parsed.mipmaps, 5);
+            assert_eq!(parsed.depth, 1);
+            assert_eq!(read_size(data.as_slice()).unwrap(), UVec2::new(16, 8));
+
+            // Only 7.2 and up have a depth, before that the bytes are padding
+            data[63] = 4;
+            assert_eq!(
+                VtfHeader::parse(&data).unwrap().depth,
+                if minor >= 2 { 4 } else { 1 }
+            );
+        }
+        for (major, minor) in [(7, 6), (8, 0)] {
+            let mut data = header(5, (16, 8), ImageFormat::Dxt1, 1);
+            data[4..8].copy_from_slice(&u32::to_le_bytes(major));
+            data[8..12].copy_from_slice(&u32::to_le_bytes(minor));
+            assert!(matches!(
+                VtfHeader::parse(&data),
+                Err(VtfError::UnsupportedVersion(a, b)) if (a, b) == (major, minor)
+            ));
+        }
+        assert!(matches!(VtfHeader::parse(b"DDS "), Err(VtfError::NotVtf)));
+        assert!(matches!(
+            VtfHeader::parse(b"VTF\0\x07\0"),
+            Err(VtfError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn mip_offsets() {
+        // 4x2, 2x1 and 1x1, smallest first, each filled with its number
+        let mut data = header(2, (4, 2), ImageFormat::Rgba8888, 3);
+        for (mip, pixels) in [(2, 1), (1, 2), (0, 8)] {
+            data.extend(vec![mip; pixels * 4]);
+        }
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(vtf.mipmaps.len(), 3);
+        for (mip, pixels) in [(0, 8), (1, 2), (2, 1)] {
+            assert_eq!(vtf.mipmaps[mip], vec![mip as u8; pixels * 4]);
+        }
+
+        // Every frame of a mipmap comes before the next one, we take the first
+        let mut data = header(2, (2, 1), ImageFormat::I8, 2);
+        data[24] = 2;
+        data.extend([10, 11, 20, 20, 21, 21]);
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(vtf.mipmaps[0], [20, 20, 20, 255, 20, 20, 20, 255]);
+        assert_eq!(vtf.mipmaps[1], [10, 10, 10, 255]);
+
+        // Cut short in the first frame, the second one isn't read
+        data.truncate(data.len() - 3);
+        assert!(matches!(Vtf::parse(&data), Err(VtfError::Truncated)));
+    }
+
+    #[test]
+    fn thumbnail_and_resources() {
+        // Up to 7.2 a DXT1 thumbnail sits between the header and the image
+        let mut data = header(1, (1, 1), ImageFormat::Bgr888, 1);
+        data[57..61].copy_from_slice(&(ImageFormat::Dxt1 as i32).to_le_bytes());
+        data[61] = 4;
+        data[62] = 4;
+        data.extend([0xEE; 8]);
+        data.extend([1, 2, 3]);
+        assert_eq!(Vtf::parse(&data).unwrap().mipmaps[0], [3, 2, 1, 255]);
+
+        // Later the resources say where the image is
+        let mut data = header(5, (1, 1), ImageFormat::Bgr888, 1);
+        data[92..96].copy_from_slice(&100u32.to_le_bytes());
+        data.extend([0xEE; 4]);
+        data.extend([1, 2, 3]);
+        assert_eq!(Vtf::parse(&data).unwrap().mipmaps[0], [3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn uncompressed() {
+        let formats: [(ImageFormat, &[u8], [u8; 4]); 10] = [
+            (ImageFormat::Rgba8888, &[1, 2, 3, 4], [1, 2, 3, 4]),
+            (ImageFormat::Abgr8888, &[4, 3, 2, 1], [1, 2, 3, 4]),
+            (ImageFormat::Argb8888, &[4, 1, 2, 3], [1, 2, 3, 4]),
+            (ImageFormat::Bgra8888, &[3, 2, 1, 4], [1, 2, 3, 4]),
+            (ImageFormat::Bgrx8888, &[3, 2, 1, 4], [1, 2, 3, 255]),
+            (ImageFormat::Rgb888, &[1, 2, 3], [1, 2, 3, 255]),
+            (ImageFormat::Bgr888, &[3, 2, 1], [1, 2, 3, 255]),
+            (ImageFormat::I8, &[7], [7, 7, 7, 255]),
+            (ImageFormat::Ia88, &[7, 8], [7, 7, 7, 8]),
+            (ImageFormat::A8, &[8], [255, 255, 255, 8]),
+        ];
+        for (format, bytes, rgba) in formats {
+            let mut data = header(4, (1, 1), format, 1);
+            data.extend(bytes);
+            assert_eq!(Vtf::parse(&data).unwrap().mipmaps[0], rgba, "{format:?}");
+        }
+
+        let mut data = header(4, (1, 1), ImageFormat::Rgba8888, 1);
+        data[52] = 4;
+        assert!(matches!(
+            Vtf::parse(&data),
+            Err(VtfError::UnsupportedFormat(4))
+        ));
+    }
+
+    #[test]
+    fn dxt1() {
+        // The first row has each of the 4 colours, the rest is colour 0
+        let mut data = header(3, (4, 4), ImageFormat::Dxt1, 1);
+        data.extend(color_block(RED, BLUE, [0b11_10_01_00, 0, 0, 0]));
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(pixel(&vtf, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&vtf, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&vtf, 2, 0), [170, 0, 85, 255]);
+        assert_eq!(pixel(&vtf, 3, 0), [85, 0, 170, 255]);
+        assert_eq!(pixel(&vtf, 3, 3), [255, 0, 0, 255]);
+
+        // With the colours the other way round, the last one is transparent
+        let mut data = header(3, (4, 4), ImageFormat::Dxt1OneBitAlpha, 1);
+        data.extend(color_block(BLUE, RED, [0b11_10_00_00, 0, 0, 0]));
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(pixel(&vtf, 2, 0), [127, 0, 127, 255]);
+        assert_eq!(pixel(&vtf, 3, 0), [0, 0, 0, 0]);
+
+        // A 6x2 image takes two blocks, and only the part of them inside it
+        let mut data = header(3, (6, 2), ImageFormat::Dxt1, 1);
+        data.extend(color_block(RED, BLUE, [0; 4]));
+        data.extend(color_block(RED, BLUE, [0x55; 4]));
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(vtf.mipmaps[0].len(), 6 * 2 * 4);
+        assert_eq!(pixel(&vtf, 3, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&vtf, 4, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&vtf, 5, 1), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn dxt3() {
+        // 4 bits of alpha a pixel, the colours always have 4 steps
+        let mut data = header(5, (4, 4), ImageFormat::Dxt3, 1);
+        data.extend([0x50, 0xFA, 0, 0, 0, 0, 0, 0]);
+        data.extend(color_block(BLUE, RED, [0b11_10_01_00, 0, 0, 0]));
+        let vtf = Vtf::parse(&data).unwrap();
+        assert_eq!(pixel(&vtf, 0, 0), [0, 0, 255, 0]);
+        assert_eq!(pixel(&vtf, 1, 0), [255, 0, 0, 85]);
+        assert_eq!(pixel(&vtf, 2, 0), [85, 0, 170, 170]);
+        assert_eq!(pixel(&vtf, 3, 0), [170, 0, 85, 255]);
+        assert_eq!(pixel(&vtf, 0, 1), [0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn dxt5() {
+        // 3 bit indices into 8 alphas, pixels 0 to 3 use indices 0, 1, 2 and 7
+        let indices: u64 = 0b111_010_001_000;
+        let alpha_block = |a0: u8, a1: u8| -> Vec<u8> {
+            [a0, a1]
+                .into_iter()
+                .chain(indices.to_le_bytes().into_iter().take(6))
+                .collect()
+        };
+        let alphas = |data: Vec<u8>| -> Vec<u8> {
+            let vtf = Vtf::parse(&data).unwrap();
+            (0..4).map(|x| pixel(&vtf, x, 0)[3]).collect()
+        };
+
+        // 6 steps between the two
+        let mut data = header(5, (4, 4), ImageFormat::Dxt5, 1);
+        data.extend(alpha_block(255, 0));
+        data.extend(color_block(RED, BLUE, [0; 4]));
+        assert_eq!(alphas(data), [255, 0, 218, 36]);
+
+        // 4 steps, then 0 and 255
+        let mut data = header(5, (4, 4), ImageFormat::Dxt5, 1);
+        data.extend(alpha_block(0, 255));
+        data.extend(color_block(RED, BLUE, [0; 4]));
+        assert_eq!(alphas(data), [0, 255, 51, 255]);
+    }
+}
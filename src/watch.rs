@@ -0,0 +1,155 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::vmf2::res::{ActiveVmf, VmfFile};
+
+/// Notices when the open map is changed by something other than us (Hammer,
+/// a build script, ...) and offers to load the new version.
+///
+/// Bevy's own file watcher only covers the `assets` folder, and maps can live
+/// anywhere, so this just checks the modification time every so often.
+pub struct WatchPlugin;
+
+impl Plugin for WatchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WatchedVmf>()
+            .add_systems(Update, (watch_active_vmf, reload_prompt));
+    }
+}
+
+#[derive(Resource)]
+pub struct WatchedVmf {
+    path: Option<PathBuf>,
+    /// When the file was last changed by us, loading or saving it
+    modified: Option<SystemTime>,
+    /// The last `VmfFile::saved_at` we saw
+    saved_at: Option<SystemTime>,
+    changed_on_disk: bool,
+    timer: Timer,
+}
+
+impl Default for WatchedVmf {
+    fn default() -> Self {
+        Self {
+            path: None,
+            modified: None,
+            saved_at: None,
+            changed_on_disk: false,
+            timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+        }
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn watch_active_vmf(
+    time: Res<Time>,
+    active_vmf: Res<ActiveVmf>,
+    vmf_files: Res<Assets<VmfFile>>,
+    mut vmf_events: EventReader<AssetEvent<VmfFile>>,
+    mut watched: ResMut<WatchedVmf>,
+) {
+    // Whenever the file is (re)loaded by us, what is on disk is what we have.
+    // Edits made in here show up as `Modified` too, those don't count.
+    let mut loaded = false;
+    for event in vmf_events.read() {
+        if let Some(handle) = &active_vmf.active {
+            loaded |= event.is_loaded_with_dependencies(handle);
+        }
+    }
+
+    let Some(vmf_file) = active_vmf.active.as_ref().and_then(|h| vmf_files.get(h)) else {
+        watched.path = None;
+        return;
+    };
+    if loaded || watched.path.as_ref() != Some(&vmf_file.path) {
+        watched.path = Some(vmf_file.path.clone());
+        watched.modified = modified_time(&vmf_file.path);
+        watched.saved_at = vmf_file.saved_at;
+        watched.changed_on_disk = false;
+        return;
+    }
+    // Same for saving, which also overwrites whatever changed on disk
+    if vmf_file.saved_at != watched.saved_at {
+        watched.saved_at = vmf_file.saved_at;
+        watched.modified = vmf_file.saved_at;
+        watched.changed_on_disk = false;
+        return;
+    }
+
+    if !watched.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = modified_time(&vmf_file.path);
+    if modified != watched.modified {
+        watched.modified = modified;
+        watched.changed_on_disk = true;
+    }
+}
+
+fn reload_prompt(
+    mut contexts: EguiContexts,
+    mut watched: ResMut<WatchedVmf>,
+    mut active_vmf: ResMut<ActiveVmf>,
+    vmf_files: Res<Assets<VmfFile>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !watched.changed_on_disk {
+        return;
+    }
+    let Some(active) = active_vmf.active.clone() else {
+        return;
+    };
+    let Some(vmf_file) = vmf_files.get(&active) else {
+        return;
+    };
+
+    let mut reload = false;
+    let mut keep = false;
+
+    egui::Window::new("Map changed on disk")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!(
+                "{} has been changed by another program.",
+                vmf_file.path.display()
+            ));
+            if vmf_file.unsaved_changes() {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    "You have unsaved changes, reloading will throw them away. \
+                     Keeping your version and saving will overwrite the other changes.",
+                );
+            }
+            ui.horizontal(|ui| {
+                reload = ui.button("Reload").clicked();
+                keep = ui.button("Keep my version").clicked();
+            });
+        });
+
+    if reload {
+        info!("Reloading {}", vmf_file.path.display());
+        // After a Save As the handle still points at the file we opened
+        let handle = if asset_server
+            .get_path(&active)
+            .is_some_and(|p| p.path() == vmf_file.path)
+        {
+            asset_server.reload(vmf_file.path.clone());
+            active
+        } else {
+            asset_server.load(vmf_file.path.clone())
+        };
+        // Goes through the same path as opening a map, so failures are reported
+        active_vmf.loading = Some(handle);
+    }
+    if reload || keep {
+        watched.changed_on_disk = false;
+    }
+}
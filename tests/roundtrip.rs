@@ -0,0 +1,76 @@
+//! Loading a map and saving it again without touching anything has to give
+//! back the exact same file, otherwise every save shows up as a change in
+//! version control.
+
+use std::path::Path;
+
+use chisel::vmf2::{generic::GenericNode, vmf::Vmf};
+
+fn roundtrip(text: &str) -> String {
+    let vmf = Vmf::parse(GenericNode::parse(text).unwrap()).unwrap();
+    vmf.as_generic().to_string()
+}
+
+#[test]
+fn sample_maps_are_saved_unchanged() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/maps");
+    let mut maps = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "vmf") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let saved = roundtrip(&text);
+        for (line, (a, b)) in text.lines().zip(saved.lines()).enumerate() {
+            assert_eq!(a, b, "{} line {}", path.display(), line + 1);
+        }
+        assert!(text == saved, "{} changed when saved", path.display());
+        maps += 1;
+    }
+    assert!(maps > 0);
+}
+
+#[test]
+fn keys_and_blocks_keep_their_order() {
+    let text = "versioninfo\r\n{\r\n\t\"editorversion\" \"400\"\r\n\t\"editorbuild\" \"8864\"\r\n\
+                \t\"mapversion\" \"1\"\r\n\t\"formatversion\" \"100\"\r\n\t\"prefab\" \"0\"\r\n}\r\n\
+                world\r\n{\r\n\
+                \t\"id\" \"1\"\r\n\
+                \tsolid\r\n\t{\r\n\t\t\"id\" \"2\"\r\n\t}\r\n\
+                \t\"classname\" \"worldspawn\"\r\n\
+                \tsolid\r\n\t{\r\n\t\t\"id\" \"4\"\r\n\t}\r\n\
+                \t\"skyname\" \"sky_day01_01\"\r\n\
+                \t\"custom\" \"kept\"\r\n\
+                }\r\n\
+                cameras\r\n{\r\n\t\"activecamera\" \"-1\"\r\n}\r\n\
+                entity\r\n{\r\n\
+                \t\"id\" \"3\"\r\n\
+                \t\"origin\" \"0 0 0\"\r\n\
+                \t\"classname\" \"info_target\"\r\n\
+                \teditor\r\n\t{\r\n\t\t\"color\" \"220 30 220\"\r\n\t}\r\n\
+                \t\"targetname\" \"a\"\r\n\
+                }\r\n";
+    assert_eq!(roundtrip(text), text);
+}
+
+#[test]
+fn only_the_changed_value_is_written_differently() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/maps/sample.vmf");
+    let text = std::fs::read_to_string(path).unwrap();
+    let mut vmf = Vmf::parse(GenericNode::parse(&text).unwrap()).unwrap();
+    vmf.world.solids[0].sides[0].material = "TOOLS/TOOLSNODRAW".to_owned();
+    let saved = vmf.as_generic().to_string();
+
+    let before: Vec<_> = text.lines().collect();
+    let after: Vec<_> = saved.lines().collect();
+    assert_eq!(before.len(), after.len());
+    let changed: Vec<_> = before.iter().zip(&after).filter(|(a, b)| a != b).collect();
+    assert_eq!(
+        changed,
+        [(
+            &"\t\t\t\"material\" \"DEV/DEVMEASUREGENERIC01\"",
+            &"\t\t\t\"material\" \"TOOLS/TOOLSNODRAW\""
+        )]
+    );
+}